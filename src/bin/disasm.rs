@@ -0,0 +1,102 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Disassembles contract code, from geth (`eth_getCode`) or a file
+//!
+
+#[macro_use]
+extern crate clap;
+extern crate evmobserver;
+
+use clap::{App, Arg};
+use evmobserver::disasm;
+use evmobserver::util::is_address;
+
+fn main() {
+    let args = App::new("disasm")
+        .version(crate_version!())
+        .about("Disassembles contract code: offset, instruction and push data")
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .value_name("ADDRESS")
+                .validator(is_address)
+                .requires("rpc-url")
+                .required_unless("code-file")
+                .conflicts_with("code-file")
+                .help("Contract whose code to fetch with eth_getCode"),
+        )
+        .arg(
+            Arg::with_name("rpc-url")
+                .long("rpc-url")
+                .value_name("URL")
+                .help(
+                    "geth endpoint: ipc:///path/geth.ipc (or a bare path), http(s):// or ws(s)://",
+                ),
+        )
+        .arg(
+            Arg::with_name("block")
+                .long("block")
+                .value_name("N")
+                .requires("address")
+                .help("Fetch the code as of this block [default: latest]"),
+        )
+        .arg(
+            Arg::with_name("code-file")
+                .long("code-file")
+                .value_name("FILE")
+                .help("Read the code from a file instead, as hex or raw bytes"),
+        )
+        .get_matches();
+
+    let block_num: Option<u64> = args.value_of("block").map(|block| {
+        block.parse().unwrap_or_else(|_| {
+            let msg = format!("Invalid value for --block: {}", block);
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+        })
+    });
+
+    let code = if let Some(path) = args.value_of("code-file") {
+        disasm::read_code(path).map_err(|e| format!("Reading {}: {}", path, e))
+    } else {
+        let address = args.value_of("address").unwrap();
+        disasm::fetch_code(args.value_of("rpc-url").unwrap(), address, block_num)
+            .map_err(|e| format!("Fetching code of {}: {}", address, e))
+    };
+    let code = code.unwrap_or_else(|msg| {
+        clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+    });
+
+    if code.is_empty() {
+        println!("no code");
+        return;
+    }
+
+    let disassembly = disasm::disassemble(&code);
+
+    for instruction in disassembly.instructions.iter() {
+        println!("{}", instruction);
+    }
+
+    if let Some(metadata) = disassembly.metadata {
+        println!(
+            "{:06x}  metadata: {} bytes, {}, solc {}",
+            metadata.offset,
+            metadata.len,
+            metadata.keys.join(" "),
+            metadata.solc.unwrap_or_else(|| "unknown".into())
+        );
+    }
+}
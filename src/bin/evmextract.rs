@@ -17,6 +17,8 @@
 //!
 
 extern crate bytesize;
+#[macro_use]
+extern crate clap;
 extern crate evmobserver;
 extern crate json;
 #[macro_use]
@@ -25,100 +27,833 @@ extern crate separator;
 extern crate simple_logger;
 
 use bytesize::ByteSize;
-use evmobserver::csvfiles::TraceOutFile;
-use evmobserver::evminst::EvmInst;
-use evmobserver::gethrpc::BlockInfo;
-use evmobserver::gethrpc::GethRpc;
+use clap::{App, Arg, ArgMatches};
+use evmobserver::checkpoint::Checkpoint;
+use evmobserver::config::ExtractConfig;
+use evmobserver::csvfiles::{CallOutFile, FileNameTemplate, OutputOptions, TraceOutFile};
+use evmobserver::fork::ForkSchedule;
+use evmobserver::gasacct;
+use evmobserver::gasacct::GasAccount;
+use evmobserver::gethrpc::histogram;
+use evmobserver::gethrpc::{BlockInfo, CallFrame, GethRpc, GethRpcError, HISTOGRAM_TRACER};
 use evmobserver::instcount::InstCount;
-use json::JsonValue;
+use log::Level;
 use separator::Separatable;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 use std::u64;
 
+/// What to do about a block when talking to geth fails
+enum Recovery {
+    Retry,
+    Skip,
+    Abort,
+}
+
+fn recovery(err: &GethRpcError) -> Recovery {
+    match *err {
+        // geth restarting, a network blip or a slow trace; worth another go
+        GethRpcError::Io(_) | GethRpcError::Timeout { .. } => Recovery::Retry,
+        // geth can't serve this block (pruned state, tracer failure); log it and move on
+        GethRpcError::Rpc { .. } => Recovery::Skip,
+        // geth sent something we don't understand; carrying on risks writing garbage
+        GethRpcError::Malformed(_)
+        | GethRpcError::MissingField(_)
+        | GethRpcError::InvalidField { .. } => Recovery::Abort,
+        // wrong URL, missing credentials, a proxy turning us away; every block would fail
+        GethRpcError::HttpStatus { .. } => Recovery::Abort,
+    }
+}
+
+/// Attempts at a request that fails with a transient error before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Run `f` on `target`, retrying with a growing delay while it fails with transient errors
+fn retrying<S, T, F>(what: &str, target: &mut S, mut f: F) -> Result<T, GethRpcError>
+where
+    F: FnMut(&mut S) -> Result<T, GethRpcError>,
+{
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+
+    loop {
+        let err = match f(target) {
+            Err(e) => e,
+            result => return result,
+        };
+
+        match recovery(&err) {
+            Recovery::Retry if attempt < MAX_ATTEMPTS => warn!(
+                "{} failed (attempt {} of {}), retrying in {:?}: {}",
+                what, attempt, MAX_ATTEMPTS, delay, err
+            ),
+            _ => return Err(err),
+        }
+
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Why extraction had to stop
+#[derive(Debug)]
+enum ExtractError {
+    /// Geth couldn't be reached or didn't make sense
+    Rpc(GethRpcError),
+
+    /// Writing the output or the checkpoint failed
+    Io(io::Error),
+
+    /// None of the last `depth` blocks written is still on the canonical chain; the chain
+    /// was reorganised further back than can be rolled back
+    DeepReorg { block_num: u64, depth: usize },
+}
+
+impl From<GethRpcError> for ExtractError {
+    fn from(err: GethRpcError) -> Self {
+        ExtractError::Rpc(err)
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(err: io::Error) -> Self {
+        ExtractError::Io(err)
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtractError::Rpc(ref e) => write!(f, "{}", e),
+            ExtractError::Io(ref e) => write!(f, "writing output: {}", e),
+            ExtractError::DeepReorg { block_num, depth } => write!(
+                f,
+                "chain reorganised under block {} deeper than the last {} blocks written, \
+                 can't roll back",
+                block_num, depth
+            ),
+        }
+    }
+}
+
+/// How geth is asked to trace blocks
+#[derive(Clone)]
+enum Tracer {
+    /// Default struct logger, every step of every transaction is sent to us
+    StructLog,
+
+    /// A tracer that tallies opcodes in geth: JavaScript code or a native tracer's name
+    Histogram(String),
+
+    /// `callTracer`
+    Calls,
+}
+
+/// What gets extracted, and where it's written
+enum Output {
+    /// Per-instruction counts and gas, from geth's default struct logger
+    Counts(Box<TraceOutFile>),
+
+    /// Call frames, from geth's `callTracer`
+    Calls(Box<CallOutFile>),
+}
+
+impl Output {
+    fn new(tracer: &Tracer, starting_block: u64, options: &OutputOptions) -> io::Result<Self> {
+        let options = options.clone();
+
+        match *tracer {
+            Tracer::Calls => {
+                CallOutFile::new(starting_block, options).map(|file| Output::Calls(Box::new(file)))
+            }
+            _ => TraceOutFile::new(starting_block, options)
+                .map(|file| Output::Counts(Box::new(file))),
+        }
+    }
+
+    /// Carry on writing the output recorded in `checkpoint`
+    fn resume(
+        tracer: &Tracer,
+        checkpoint: &Checkpoint,
+        options: &OutputOptions,
+    ) -> io::Result<Self> {
+        let options = options.clone();
+
+        match *tracer {
+            Tracer::Calls => {
+                CallOutFile::resume(checkpoint, options).map(|file| Output::Calls(Box::new(file)))
+            }
+            _ => {
+                TraceOutFile::resume(checkpoint, options).map(|file| Output::Counts(Box::new(file)))
+            }
+        }
+    }
+
+    /// Describe the output once everything before `next_block` has been written,
+    /// `last_hash` being the hash of the block before it if it was extracted
+    fn checkpoint(&mut self, next_block: u64, last_hash: Option<String>) -> io::Result<Checkpoint> {
+        let mut checkpoint = match *self {
+            Output::Counts(ref mut out_file) => out_file.checkpoint(next_block)?,
+            Output::Calls(ref mut call_file) => call_file.checkpoint(next_block)?,
+        };
+        checkpoint.last_hash = last_hash;
+
+        Ok(checkpoint)
+    }
+
+    /// Sync the output and save `checkpoint`, taken of it, to `path`
+    fn save_checkpoint(&mut self, path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+        match *self {
+            Output::Counts(ref mut out_file) => out_file.sync()?,
+            Output::Calls(ref mut call_file) => call_file.sync()?,
+        }
+
+        checkpoint.save(path)
+    }
+
+    /// Will the next block with rows start a new file?
+    fn rotation_due(&self) -> bool {
+        match *self {
+            Output::Counts(ref out_file) => out_file.rotation_due(),
+            Output::Calls(ref call_file) => call_file.rotation_due(),
+        }
+    }
+
+    /// Discard everything written after `checkpoint`
+    fn roll_back(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        match *self {
+            Output::Counts(ref mut out_file) => out_file.roll_back(checkpoint),
+            Output::Calls(ref mut call_file) => call_file.roll_back(checkpoint),
+        }
+    }
+}
+
+/// A block's header and rows, fetched from geth and ready to be written
+struct Block {
+    info: BlockInfo,
+    rows: Rows,
+}
+
+impl Block {
+    fn has_rows(&self) -> bool {
+        match self.rows {
+            Rows::Empty => false,
+            Rows::Counts(ref rows) => !rows.is_empty(),
+            Rows::Calls(ref txns) => !txns.is_empty(),
+        }
+    }
+}
+
+/// What gets written for a block
+enum Rows {
+    /// The block has no transactions
+    Empty,
+
+    /// Instruction counts and gas accounting of each transaction that ran code, by index
+    /// into the block's `txns`; no accounting on a chain whose forks aren't known
+    Counts(Vec<(InstCount, Option<GasAccount>, usize)>),
+
+    /// Call frames of each transaction
+    Calls(Vec<Vec<CallFrame>>),
+}
+
+///
+/// Fetches blocks from geth and turns them into rows, without writing anything
+///
+struct Extractor {
+    rpc: GethRpc,
+    tracer: Tracer,
+
+    /// The chain's forks, which decide its gas rules
+    forks: Option<&'static ForkSchedule>,
+}
+
+impl Extractor {
+    fn new(
+        rpc_url: &str,
+        tracer: &Tracer,
+        forks: Option<&'static ForkSchedule>,
+    ) -> Result<Self, GethRpcError> {
+        let mut rpc = GethRpc::new(rpc_url)?;
+
+        if let Tracer::Histogram(ref code) = *tracer {
+            rpc.set_tracer(Some(code));
+        }
+
+        Ok(Extractor {
+            rpc,
+            tracer: tracer.clone(),
+            forks,
+        })
+    }
+
+    /// Trace `block_num` and prepare its rows. The whole block is fetched before anything is
+    /// written, so a failed attempt can be retried without duplicating rows.
+    fn fetch_block(&mut self, block_num: u64) -> Result<Block, GethRpcError> {
+        match self.tracer {
+            Tracer::Calls => self.fetch_calls(block_num),
+            _ => self.fetch_counts(block_num),
+        }
+    }
+
+    /// Instruction counts per transaction of `block_num`.
+    ///
+    /// A histogram tracer's results are small, so one batch request fetches the header,
+    /// its transactions, their receipts and the trace. Struct logs are tallied as they
+    /// stream in and the block is fetched separately.
+    fn fetch_counts(&mut self, block_num: u64) -> Result<Block, GethRpcError> {
+        let (block_info, txns) = if let Tracer::Histogram(_) = self.tracer {
+            let (block_info, trace) = self.rpc.traced_block(block_num)?;
+            (block_info, histogram::tallies(&trace)?)
+        } else {
+            let txns = self.rpc.struct_log_tallies(block_num)?;
+            if txns.is_empty() {
+                return Ok(Block {
+                    info: self.rpc.block_info(block_num)?,
+                    rows: Rows::Empty,
+                });
+            }
+            (self.rpc.block_with_receipts(block_num)?, txns)
+        };
+
+        if txns.is_empty() {
+            return Ok(Block {
+                info: block_info,
+                rows: Rows::Empty,
+            });
+        };
+
+        let fork = self
+            .forks
+            .map(|forks| forks.fork_at(block_num, block_info.time_stamp));
+        let mut rows = Vec::with_capacity(txns.len());
+
+        for (idx, tally) in txns.into_iter().enumerate() {
+            // transactions that ran no code (plain transfers) have nothing to count
+            let exec = match tally.exec {
+                Some(exec) => exec,
+                None => continue,
+            };
+
+            let txn_info = block_info.txns.get(idx).ok_or_else(|| {
+                GethRpcError::MissingField(format!("transaction {} of block {}", idx, block_num))
+            })?;
+
+            let gas_account = fork.map(|fork| {
+                let gas_account = gasacct::account(txn_info, fork, &exec);
+                if gas_account.delta.unwrap_or(0) != 0 {
+                    debug!(
+                        "Block {} txn {}: gas accounting off by {:?} ({})",
+                        block_num, idx, gas_account.delta, fork
+                    );
+                }
+                gas_account
+            });
+
+            rows.push((tally.counts, gas_account, idx));
+        }
+
+        Ok(Block {
+            info: block_info,
+            rows: Rows::Counts(rows),
+        })
+    }
+
+    /// The call frames of every transaction of `block_num`
+    fn fetch_calls(&mut self, block_num: u64) -> Result<Block, GethRpcError> {
+        let info = self.rpc.block_info(block_num)?;
+        let txns = self.rpc.call_frames(block_num)?;
+
+        let rows = if txns.is_empty() {
+            Rows::Empty
+        } else {
+            Rows::Calls(txns)
+        };
+
+        Ok(Block { info, rows })
+    }
+}
+
+/// A block number and its rows, or why they couldn't be fetched
+type Fetched = (u64, Result<Block, GethRpcError>);
+
+///
+/// Worker threads fetching blocks in parallel, each with its own connection to geth.
+/// Blocks are handed out one at a time and come back in whatever order they finish.
+///
+struct WorkerPool {
+    jobs: Sender<(u64, u64)>,
+    results: Receiver<(u64, Fetched)>,
+    workers: usize,
+
+    /// Tags jobs so results of those handed out before `restart` can be told apart
+    epoch: u64,
+}
+
+impl WorkerPool {
+    fn new(
+        workers: usize,
+        rpc_url: &str,
+        tracer: &Tracer,
+        forks: Option<&'static ForkSchedule>,
+    ) -> Self {
+        let (jobs, job_queue) = mpsc::channel();
+        let (done, results) = mpsc::channel();
+        let job_queue = Arc::new(Mutex::new(job_queue));
+
+        for worker in 0..workers {
+            let job_queue = Arc::clone(&job_queue);
+            let done = done.clone();
+            let rpc_url = rpc_url.to_string();
+            let tracer = tracer.clone();
+
+            thread::spawn(move || match Extractor::new(&rpc_url, &tracer, forks) {
+                Ok(extractor) => work(extractor, &job_queue, &done),
+                Err(e) => error!(
+                    "Worker {} can't connect to geth at {}: {}",
+                    worker, rpc_url, e
+                ),
+            });
+        }
+
+        WorkerPool {
+            jobs,
+            results,
+            workers,
+            epoch: 0,
+        }
+    }
+
+    /// Queue `block_num` for the next free worker
+    fn fetch(&self, block_num: u64) -> Result<(), GethRpcError> {
+        self.jobs
+            .send((self.epoch, block_num))
+            .map_err(|_| no_workers())
+    }
+
+    /// Wait for the next block any worker finishes
+    fn next_fetched(&self) -> Result<Fetched, GethRpcError> {
+        loop {
+            match self.results.recv() {
+                Ok((epoch, fetched)) if epoch == self.epoch => return Ok(fetched),
+                Ok(_) => (),
+                Err(_) => return Err(no_workers()),
+            }
+        }
+    }
+
+    /// Forget every block handed out so far; whatever the workers return for them is dropped
+    fn restart(&mut self) {
+        self.epoch += 1;
+    }
+}
+
+/// Body of a worker thread: fetch the blocks from `job_queue` until it's closed
+fn work(
+    mut extractor: Extractor,
+    job_queue: &Mutex<Receiver<(u64, u64)>>,
+    done: &Sender<(u64, Fetched)>,
+) {
+    loop {
+        let (epoch, block_num) = match job_queue.lock().expect("job queue poisoned").recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let what = format!("block {}", block_num);
+        let result = retrying(&what, &mut extractor, |e| e.fetch_block(block_num));
+
+        if done.send((epoch, (block_num, result))).is_err() {
+            return;
+        }
+    }
+}
+
+fn no_workers() -> GethRpcError {
+    GethRpcError::Io(io::Error::new(
+        io::ErrorKind::NotConnected,
+        "no workers left to fetch blocks",
+    ))
+}
+
 ///
 /// Collect EVM statistics
 ///
 struct EvmExtract {
+    /// Tracks the chain head; blocks are fetched over the workers' own connections
     rpc: GethRpc,
-    current_block: u64,
-    txn_count: InstCount,
+    workers: WorkerPool,
+
+    /// First block not yet extracted. Every block before it is done: written, found to
+    /// have no transactions, or skipped.
+    next_block: u64,
+
     total_count: InstCount,
-    out_file: TraceOutFile,
+    output: Output,
     last_update: Instant,
+
+    /// Where progress is saved, and every how many blocks. Syncing output and checkpoint to
+    /// disk after every block would limit extraction to the disk's flush rate; a resumed
+    /// run redoes whatever came after the last checkpoint saved.
+    checkpoint_path: PathBuf,
+    checkpoint_blocks: u64,
+
+    /// `next_block` of the last checkpoint saved, and the latest one not saved yet
+    saved_block: u64,
+    unsaved: Option<Checkpoint>,
+
+    /// Following the chain head, stay this many blocks behind it
+    confirmations: u64,
+
+    /// The last `REORG_WINDOW` blocks written, oldest first, to notice reorgs by
+    recent: VecDeque<WrittenBlock>,
+}
+
+/// A block that was written and the output as of the end of it
+struct WrittenBlock {
+    block_num: u64,
+    hash: String,
+    after: Checkpoint,
 }
 
 impl EvmExtract {
-    fn new(starting_block: u64, rpc_path: &str) -> Self {
-        EvmExtract {
-            rpc: GethRpc::new(rpc_path),
-            current_block: starting_block,
-            txn_count: InstCount::new(),
+    /// Blocks each worker may be ahead of the oldest block not yet written, which bounds
+    /// how many fetched blocks wait in memory to be written in order
+    const BLOCKS_PER_WORKER: u64 = 4;
+
+    /// How far back a reorg can be undone; comfortably more than the blocks mainnet takes
+    /// to finalize
+    const REORG_WINDOW: usize = 128;
+
+    /// Extract from `starting_block` on, or from where the run that left `resumed` stopped
+    fn new(
+        starting_block: u64,
+        settings: &Settings,
+        output: Output,
+        resumed: Option<Checkpoint>,
+    ) -> Result<Self, GethRpcError> {
+        let rpc = GethRpc::new(&settings.rpc_url)?;
+
+        // the last block the earlier run wrote, to notice a reorg while we were stopped
+        let recent = resumed
+            .and_then(|after| {
+                let hash = after.last_hash.clone()?;
+                Some(WrittenBlock {
+                    block_num: after.next_block - 1,
+                    hash,
+                    after,
+                })
+            })
+            .into_iter()
+            .collect();
+
+        Ok(EvmExtract {
+            rpc,
+            workers: WorkerPool::new(
+                settings.workers,
+                &settings.rpc_url,
+                &settings.tracer,
+                settings.forks,
+            ),
+            next_block: starting_block,
             total_count: InstCount::new(),
-            out_file: TraceOutFile::new(starting_block),
+            output,
             last_update: Instant::now(),
-        }
+            checkpoint_path: settings.output.path(Checkpoint::FILE_NAME),
+            checkpoint_blocks: settings.checkpoint_blocks,
+            saved_block: starting_block,
+            unsaved: None,
+            confirmations: settings.confirmations,
+            recent,
+        })
     }
 
-    fn catchup_latest(&mut self) {
-        let latest_block = match self.rpc.get_latest_block() {
-            Some(v) => v,
-            None => panic!("Failed to read latest block from geth, can't proceed"),
-        };
+    fn catchup_latest(&mut self) -> Result<(), ExtractError> {
+        let latest_block = retrying("eth_blockNumber", &mut self.rpc, |rpc| {
+            rpc.get_latest_block()
+        })?;
 
         info!(
             "Latest block from geth: {}",
             latest_block.separated_string()
         );
 
-        self.catchup(latest_block)
+        let target_block = self.confirmed(latest_block);
+        self.catchup(target_block)
     }
 
-    fn catchup(&mut self, target_block: u64) {
-        if self.current_block >= target_block {
+    /// Newest block with enough confirmations when `head` is the chain head
+    fn confirmed(&self, head: u64) -> u64 {
+        head.saturating_sub(self.confirmations)
+    }
+
+    /// Follow the chain head indefinitely. Reacts to `newHeads` notifications when the
+    /// transport supports them, polling `eth_blockNumber` otherwise or once the
+    /// subscription drops. Only returns if extraction has to be abandoned.
+    fn follow_latest(&mut self) -> Result<(), ExtractError> {
+        let poll_interval = Duration::from_secs(2);
+        let head_timeout = Duration::from_secs(60);
+
+        let mut subscribed = match self.rpc.subscribe_new_heads() {
+            Ok(Some(id)) => {
+                info!("Continuous update loop, newHeads subscription {}", id);
+                true
+            }
+            Ok(None) => {
+                info!("Continuous update loop, polling every {:?}", poll_interval);
+                false
+            }
+            Err(e) => {
+                warn!("newHeads subscription refused ({}), polling instead", e);
+                false
+            }
+        };
+
+        loop {
+            if !subscribed {
+                self.catchup_latest()?;
+                thread::sleep(poll_interval);
+                continue;
+            }
+
+            match self.rpc.next_new_head(head_timeout) {
+                Ok(Some(head)) => {
+                    let target_block = self.confirmed(head);
+                    self.catchup(target_block)?
+                }
+                // quiet period; make sure nothing slipped past us
+                Ok(None) => self.catchup_latest()?,
+                Err(e) => {
+                    warn!(
+                        "newHeads subscription dropped ({}), falling back to polling",
+                        e
+                    );
+                    subscribed = false;
+                }
+            }
+        }
+    }
+
+    /// Extract blocks through `target_block`. Workers fetch blocks in parallel while this
+    /// thread writes them strictly in block order. A block whose parent isn't the block
+    /// written before it means the chain was reorganised: rows back to the last block still
+    /// on the canonical chain are rolled back and extracted again.
+    fn catchup(&mut self, target_block: u64) -> Result<(), ExtractError> {
+        if self.next_block > target_block {
             info!(
-                "Catch-up complete: current {}, target {}",
-                self.current_block.separated_string(),
+                "Catch-up complete: next {}, target {}",
+                self.next_block.separated_string(),
                 target_block.separated_string()
             );
-            return;
+            return Ok(());
         }
 
+        let end_block = target_block + 1;
+
         info!(
-            "{} blocks to catch-up on (current {}, target {})",
-            (target_block - self.current_block).separated_string(),
-            self.current_block.separated_string(),
+            "{} blocks to catch-up on (next {}, target {})",
+            (end_block - self.next_block).separated_string(),
+            self.next_block.separated_string(),
             target_block.separated_string()
         );
 
         let ten_seconds = Duration::from_secs(10);
-        let mut last_update_block = self.current_block;
-
-        for block_num in self.current_block..(target_block + 1) {
-            if self.last_update.elapsed() > ten_seconds {
-                let block_delta = block_num - last_update_block;
-                self.log_status_update(block_num, target_block, block_delta);
-                self.last_update = Instant::now();
-                last_update_block = block_num;
-            };
+        let mut last_update_block = self.next_block;
 
-            let block_info = self.rpc.block_info(block_num);
-            let trace_resp = self.rpc.trace_block(block_num);
-            let trace = &trace_resp.unwrap_or(JsonValue::Null)["result"];
+        let window = self.workers.workers as u64 * Self::BLOCKS_PER_WORKER;
 
-            if trace.is_empty() {
-                continue;
-            };
+        // blocks that arrived ahead of `next_write`
+        let mut pending: BTreeMap<u64, Result<Block, GethRpcError>> = BTreeMap::new();
+        let mut next_fetch = self.next_block;
+        let mut next_write = self.next_block;
 
-            for idx in 0..trace.len() {
-                let trace_logs = &trace[idx]["result"]["structLogs"];
-                if !trace_logs.is_empty() {
-                    self.update_instruction_counts(idx as u32, trace_logs, &block_info);
+        while next_write < end_block {
+            while next_fetch < end_block && next_fetch < next_write + window {
+                self.workers.fetch(next_fetch)?;
+                next_fetch += 1;
+            }
+
+            let (block_num, result) = self.workers.next_fetched()?;
+            pending.insert(block_num, result);
+
+            while let Some(result) = pending.remove(&next_write) {
+                let block_num = next_write;
+                next_write += 1;
+
+                if self.last_update.elapsed() > ten_seconds {
+                    let block_delta = block_num - last_update_block;
+                    self.log_status_update(block_num, target_block, block_delta);
+                    self.last_update = Instant::now();
+                    last_update_block = block_num;
                 };
+
+                let hash = match result {
+                    Ok(block) => {
+                        if let Some(fork) = self.fork_point(&block.info)? {
+                            self.roll_back(fork)?;
+                            self.workers.restart();
+                            pending.clear();
+                            next_fetch = fork + 1;
+                            next_write = fork + 1;
+                            break;
+                        }
+
+                        // a resumed run may only remove a file started by the block after
+                        // the checkpoint, so one is never started past an unsaved checkpoint
+                        if block.has_rows() && self.output.rotation_due() {
+                            self.save_checkpoint()?;
+                        }
+
+                        let hash = block.info.hash.clone();
+                        self.write_block(block)?;
+                        Some(hash)
+                    }
+                    Err(e) => match recovery(&e) {
+                        Recovery::Skip => {
+                            error!("Skipping block {}: {}", block_num, e);
+                            None
+                        }
+                        _ => return Err(e.into()),
+                    },
+                };
+
+                let after = self.output.checkpoint(next_write, hash.clone())?;
+                self.next_block = next_write;
+                self.unsaved = Some(after.clone());
+                if next_write - self.saved_block >= self.checkpoint_blocks {
+                    self.save_checkpoint()?;
+                }
+
+                if let Some(hash) = hash {
+                    self.recent.push_back(WrittenBlock {
+                        block_num,
+                        hash,
+                        after,
+                    });
+                    if self.recent.len() > Self::REORG_WINDOW {
+                        self.recent.pop_front();
+                    }
+                }
+            }
+        }
+
+        self.save_checkpoint()?;
+
+        Ok(())
+    }
+
+    /// Save the latest checkpoint, if it hasn't been
+    fn save_checkpoint(&mut self) -> io::Result<()> {
+        if let Some(checkpoint) = self.unsaved.take() {
+            self.output
+                .save_checkpoint(&self.checkpoint_path, &checkpoint)?;
+            self.saved_block = checkpoint.next_block;
+        }
+
+        Ok(())
+    }
+
+    /// If `block` shows the chain was reorganised under us, i.e. its parent isn't the block
+    /// written before it, find the last block written that's still canonical
+    fn fork_point(&mut self, block: &BlockInfo) -> Result<Option<u64>, ExtractError> {
+        match self.recent.back() {
+            Some(prev)
+                if prev.block_num + 1 == block.block_num && prev.hash != block.parent_hash =>
+            {
+                warn!(
+                    "Chain reorganised: parent of block {} is {}, we wrote {}",
+                    block.block_num.separated_string(),
+                    block.parent_hash,
+                    prev.hash
+                )
             }
+            _ => return Ok(None),
+        }
+
+        for idx in (0..self.recent.len()).rev() {
+            let block_num = self.recent[idx].block_num;
+            let canonical = retrying("block_info", &mut self.rpc, |rpc| rpc.block_info(block_num))?;
 
-            self.current_block = block_num;
+            if canonical.hash == self.recent[idx].hash {
+                return Ok(Some(block_num));
+            }
         }
+
+        Err(ExtractError::DeepReorg {
+            block_num: block.block_num,
+            depth: self.recent.len(),
+        })
+    }
+
+    /// Undo everything written after block `fork`
+    fn roll_back(&mut self, fork: u64) -> io::Result<()> {
+        while self.recent.back().is_some_and(|b| b.block_num > fork) {
+            self.recent.pop_back();
+        }
+
+        let checkpoint = self
+            .recent
+            .back()
+            .expect("fork point was written")
+            .after
+            .clone();
+
+        warn!(
+            "Rolling back to block {} in {}",
+            fork.separated_string(),
+            checkpoint.file_name
+        );
+
+        // a checkpoint saved since the fork vouches for rows about to be cut off
+        self.output.roll_back(&checkpoint)?;
+        self.unsaved = Some(checkpoint);
+        self.save_checkpoint()?;
+
+        self.next_block = fork + 1;
+
+        Ok(())
+    }
+
+    /// Write the rows of `block`, if it has any
+    fn write_block(&mut self, block: Block) -> io::Result<()> {
+        let Block { info, rows } = block;
+
+        match (rows, &mut self.output) {
+            (Rows::Empty, _) => (),
+
+            (Rows::Counts(rows), &mut Output::Counts(ref mut out_file)) => {
+                for (txn_count, gas_account, idx) in rows {
+                    self.total_count.merge(&txn_count);
+
+                    out_file.write_count(
+                        &txn_count,
+                        gas_account.as_ref(),
+                        &info.txns[idx],
+                        &info,
+                    )?;
+                }
+            }
+
+            (Rows::Calls(txns), &mut Output::Calls(ref mut call_file)) => {
+                for frames in txns {
+                    call_file.write_frames(&frames).map_err(io::Error::from)?;
+                }
+            }
+
+            _ => unreachable!("block fetched for a different output"),
+        }
+
+        Ok(())
     }
 
     fn log_status_update(&self, curr_block: u64, max_block: u64, block_delta: u64) {
@@ -128,89 +863,373 @@ impl EvmExtract {
         };
         let blocks_per_sec = block_delta as f64 / elapsed;
 
-        info!("Wrote block {} ({}) of {} (geth {}): {:.1} blks/s, txns {}, minsts {:.1}, mgas {}, written {}",
-              self.out_file.last_block.separated_string(),
-              self.out_file.last_time_stamp,
-              max_block.separated_string(),
-              curr_block.separated_string(),
-              blocks_per_sec,
-              self.out_file.total_txns.separated_string(),
-              self.out_file.total_inst as f64 / 1_000_000.0,
-              self.out_file.total_gas / 1_000_000,
-              ByteSize::b(self.out_file.total_written)
-        );
+        match self.output {
+            Output::Counts(ref out_file) => info!(
+                "Wrote block {} ({}) of {} (geth {}): {:.1} blks/s, txns {}, minsts {:.1}, mgas {}, written {}",
+                out_file.last_block.separated_string(),
+                out_file.last_time_stamp,
+                max_block.separated_string(),
+                curr_block.separated_string(),
+                blocks_per_sec,
+                out_file.total_txns.separated_string(),
+                out_file.total_inst as f64 / 1_000_000.0,
+                out_file.total_gas / 1_000_000,
+                ByteSize::b(out_file.total_written)
+            ),
+            Output::Calls(ref call_file) => info!(
+                "Wrote block {} of {} (geth {}): {:.1} blks/s, txns {}, call frames {}",
+                call_file.last_block.separated_string(),
+                max_block.separated_string(),
+                curr_block.separated_string(),
+                blocks_per_sec,
+                call_file.total_txns.separated_string(),
+                call_file.total_frames.separated_string()
+            ),
+        }
     }
+}
 
-    fn update_instruction_counts(
-        &mut self,
-        txn_idx: u32,
-        trace_logs: &JsonValue,
-        block_info: &BlockInfo,
-    ) {
-        self.txn_count.clear();
-        let txn_info = self.rpc.txn_info(block_info.block_num, txn_idx);
+///
+/// Everything `evmextract` was asked to do, from the command line and the config file
+///
+struct Settings {
+    rpc_url: String,
+
+    /// `None` to carry on from the checkpoint in the output directory
+    start_block: Option<u64>,
+
+    /// `None` to follow the chain head
+    end_block: Option<u64>,
+
+    output: OutputOptions,
 
-        for trace in trace_logs.members() {
-            let op = EvmInst::from_opt_str(trace["op"].as_str());
-            let gas_cost = trace["gasCost"].as_u64().expect("gasCost extract failed");
+    /// Forks of the chain geth is on, `None` if they aren't known
+    forks: Option<&'static ForkSchedule>,
 
-            self.txn_count.inc_count(op);
-            self.txn_count.add_gas(op, gas_cost);
+    /// Save the checkpoint at least this often, in blocks
+    checkpoint_blocks: u64,
+
+    tracer: Tracer,
+    workers: usize,
+    confirmations: u64,
+    log_level: Level,
+}
+
+impl Settings {
+    /// Combine the command line with the config file it names, if any, and check the
+    /// result makes sense
+    fn from_args(args: &ArgMatches) -> Result<Self, String> {
+        let config = match args.value_of("config") {
+            Some(path) => ExtractConfig::load(path)
+                .map_err(|e| format!("Can't read config file {}: {}", path, e))?,
+            None => ExtractConfig::default(),
+        };
 
-            self.total_count.inc_count(op);
-            self.total_count.add_gas(op, gas_cost);
+        let rpc_url = setting(args, "rpc-url", config.rpc_url)?
+            .ok_or("No RPC endpoint, give --rpc-url or rpc-url in the config file")?;
+
+        let start_block = if args.is_present("resume") {
+            None
+        } else {
+            let start_block = setting(args, "start-block", config.start_block)?.ok_or(
+                "No starting block, give --start-block, start-block in the config file or \
+                 --resume",
+            )?;
+            Some(start_block)
+        };
+
+        let end_block = setting(args, "end-block", config.end_block)?;
+        if let (Some(start), Some(end)) = (start_block, end_block) {
+            if end < start {
+                return Err(format!("End block {} is before start block {}", end, start));
+            }
         }
 
-        self.out_file
-            .write_count(&self.txn_count, &txn_info, &block_info)
-            .expect("write_count failed");
+        let format: String = setting(args, "format", config.format)?.unwrap_or("counts".into());
+        let tracer: Option<String> = setting(args, "tracer", config.tracer)?;
+        let tracer = match (format.as_str(), tracer.as_deref()) {
+            ("counts", None) | ("counts", Some("structlog")) => Tracer::StructLog,
+            ("counts", Some("histogram")) => Tracer::Histogram(HISTOGRAM_TRACER.to_string()),
+            ("counts", Some(t)) if t.starts_with("native:") && t.len() > "native:".len() => {
+                Tracer::Histogram(t["native:".len()..].to_string())
+            }
+            ("counts", Some(t)) => return Err(format!("Unknown tracer {}", t)),
+            ("calls", None) => Tracer::Calls,
+            ("calls", Some(_)) => return Err("A tracer only applies to counts output".into()),
+            (f, _) => return Err(format!("Unknown output format {}", f)),
+        };
+
+        let out_dir: String = setting(args, "out-dir", config.out_dir)?.unwrap_or(".".into());
+        let dir = PathBuf::from(out_dir);
+        if dir.exists() && !dir.is_dir() {
+            return Err(format!(
+                "Output directory {} isn't a directory",
+                dir.display()
+            ));
+        }
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Can't create output directory {}: {}", dir.display(), e))?;
+
+        let template: String = setting(args, "file-name", config.file_name)?
+            .unwrap_or(FileNameTemplate::DEFAULT.into());
+        let template = FileNameTemplate::parse(&template)?;
+
+        let rotate_rows = setting(args, "rotate-rows", config.rotate_rows)?
+            .unwrap_or_else(|| OutputOptions::default().rotate_rows);
+        if rotate_rows == 0 {
+            return Err("Rotation size must be at least one row".into());
+        }
+
+        let checkpoint_blocks =
+            setting(args, "checkpoint-blocks", config.checkpoint_blocks)?.unwrap_or(100);
+        if checkpoint_blocks == 0 {
+            return Err("Checkpoint interval must be at least one block".into());
+        }
+
+        let workers = setting(args, "workers", config.workers)?.unwrap_or(1);
+        if workers == 0 {
+            return Err("Need at least one worker".into());
+        }
+
+        let log_level: String =
+            setting(args, "log-level", config.log_level)?.unwrap_or("info".into());
+        let log_level =
+            Level::from_str(&log_level).map_err(|_| format!("Unknown log level {}", log_level))?;
+
+        Ok(Settings {
+            rpc_url,
+            start_block,
+            end_block,
+            output: OutputOptions {
+                dir,
+                rotate_rows,
+                template,
+                chain: String::new(),
+            },
+            forks: None,
+            checkpoint_blocks,
+            tracer,
+            workers,
+            confirmations: setting(args, "confirmations", config.confirmations)?.unwrap_or(0),
+            log_level,
+        })
     }
 }
 
+/// Option `name` from the command line, otherwise what the config file had for it
+fn setting<T: FromStr>(
+    args: &ArgMatches,
+    name: &str,
+    config: Option<T>,
+) -> Result<Option<T>, String> {
+    match args.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid value for --{}: {}", name, value)),
+        None => Ok(config),
+    }
+}
+
+/// What `{chain}` in file names reads for chain `chain_id`
+fn chain_name(chain_id: u64) -> String {
+    match chain_id {
+        1 => "mainnet".to_string(),
+        17000 => "holesky".to_string(),
+        560048 => "hoodi".to_string(),
+        11155111 => "sepolia".to_string(),
+        _ => chain_id.to_string(),
+    }
+}
+
+fn cli() -> App<'static, 'static> {
+    App::new("evmextract")
+        .version(crate_version!())
+        .about("Dumps EVM per-instruction counts and gas consumption, or call frames, from geth")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML file with defaults for any of the options below"),
+        )
+        .arg(
+            Arg::with_name("rpc-url")
+                .long("rpc-url")
+                .value_name("URL")
+                .help(
+                    "geth endpoint: ipc:///path/geth.ipc (or a bare path), http(s):// or ws(s)://",
+                ),
+        )
+        .arg(
+            Arg::with_name("start-block")
+                .long("start-block")
+                .value_name("N")
+                .help("First block to extract"),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .conflicts_with("start-block")
+                .help("Carry on from the checkpoint in the output directory"),
+        )
+        .arg(
+            Arg::with_name("end-block")
+                .long("end-block")
+                .value_name("N")
+                .help("Last block to extract; without it the chain head is followed"),
+        )
+        .arg(
+            Arg::with_name("out-dir")
+                .long("out-dir")
+                .value_name("DIR")
+                .help("Directory for output files and the checkpoint [default: .]"),
+        )
+        .arg(
+            Arg::with_name("file-name")
+                .long("file-name")
+                .value_name("TEMPLATE")
+                .help(
+                    "Output file names within --out-dir, from {chain}, {format}, \
+                     {start_block} and {end_block}, e.g. \
+                     {chain}/{start_block:010}-{end_block:010}.csv \
+                     [default: {format}.{start_block}.csv]",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["counts", "calls"])
+                .help("Per-instruction counts, or every call frame [default: counts]"),
+        )
+        .arg(
+            Arg::with_name("rotate-rows")
+                .long("rotate-rows")
+                .value_name("N")
+                .help("Start a new output file after this many rows [default: 10000]"),
+        )
+        .arg(
+            Arg::with_name("checkpoint-blocks")
+                .long("checkpoint-blocks")
+                .value_name("N")
+                .help("Save progress for --resume every this many blocks [default: 100]"),
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .value_name("N")
+                .help("Blocks traced at a time, each over its own connection [default: 1]"),
+        )
+        .arg(
+            Arg::with_name("tracer")
+                .long("tracer")
+                .value_name("TRACER")
+                .help(
+                    "How counts are traced: structlog, histogram (a JS tracer) or \
+                     native:NAME (a native tracer registered in geth) [default: structlog]",
+                ),
+        )
+        .arg(
+            Arg::with_name("confirmations")
+                .long("confirmations")
+                .value_name("N")
+                .help("Following the chain head, stay this many blocks behind it [default: 0]"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("error, warn, info, debug or trace [default: info]"),
+        )
+}
+
 fn main() {
-    use log::Level;
-    use std::env::args;
-
-    simple_logger::init_with_level(Level::Info).unwrap();
-
-    let argv: Vec<String> = args().collect();
-
-    let starting_block;
-    let ending_block;
-    let ipc_path;
-
-    match argv.len() {
-        3 => {
-            starting_block = argv[1]
-                .parse::<u64>()
-                .expect("Couldn't parse starting block");
-            ending_block = None;
-            ipc_path = &argv[2];
-        }
-        4 => {
-            starting_block = argv[1]
-                .parse::<u64>()
-                .expect("Couldn't parse starting block");
-            ending_block = Some(argv[2].parse::<u64>().expect("Couldn't parse ending block"));
-            ipc_path = &argv[3];
-        }
-        _ => {
-            info!("Usage: evmextract STARTING_BLOCK [END_BLOCK] IPC_PATH");
+    let mut settings = match Settings::from_args(&cli().get_matches()) {
+        Ok(settings) => settings,
+        Err(msg) => clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit(),
+    };
+
+    simple_logger::init_with_level(settings.log_level).unwrap();
+
+    let chain_id = match GethRpc::new(&settings.rpc_url).and_then(|mut rpc| rpc.chain_id()) {
+        Ok(chain_id) => chain_id,
+        Err(e) => {
+            error!("Can't get the chain id from {}: {}", settings.rpc_url, e);
             std::process::exit(1);
         }
+    };
+
+    settings.output.chain = chain_name(chain_id);
+    settings.forks = ForkSchedule::for_chain(chain_id);
+    if settings.forks.is_none() && !matches!(settings.tracer, Tracer::Calls) {
+        warn!(
+            "Forks of chain {} aren't known, gas accounting columns are left empty",
+            chain_id
+        );
     }
 
-    let mut evm = EvmExtract::new(starting_block, &ipc_path);
+    let checkpoint_path = settings.output.path(Checkpoint::FILE_NAME);
 
-    if ending_block.is_some() {
-        evm.catchup(ending_block.unwrap())
-    } else {
-        info!("Continuous update loop");
-        let sleep_duration = std::time::Duration::from_secs(2);
-        loop {
-            evm.catchup_latest();
-            std::thread::sleep(sleep_duration);
+    let (starting_block, output, resumed) = match settings.start_block {
+        Some(starting_block) => {
+            match Output::new(&settings.tracer, starting_block, &settings.output) {
+                Ok(output) => (starting_block, output, None),
+                Err(e) => {
+                    error!(
+                        "Can't start output in {}: {}",
+                        settings.output.dir.display(),
+                        e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let checkpoint = match Checkpoint::load(&checkpoint_path) {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => {
+                    error!("Can't resume from {}: {}", checkpoint_path.display(), e);
+                    std::process::exit(1);
+                }
+            };
+
+            match Output::resume(&settings.tracer, &checkpoint, &settings.output) {
+                Ok(output) => {
+                    info!(
+                        "Resuming at block {} in {}",
+                        checkpoint.next_block.separated_string(),
+                        checkpoint.file_name
+                    );
+                    (checkpoint.next_block, output, Some(checkpoint))
+                }
+                Err(e) => {
+                    error!("Can't resume {}: {}", checkpoint.file_name, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    let mut evm = match EvmExtract::new(starting_block, &settings, output, resumed) {
+        Ok(evm) => evm,
+        Err(e) => {
+            error!("Can't connect to geth at {}: {}", settings.rpc_url, e);
+            std::process::exit(1);
         }
+    };
+
+    let result = match settings.end_block {
+        Some(end_block) => evm.catchup(end_block),
+        None => evm.catchup_latest().and_then(|_| evm.follow_latest()),
+    };
+
+    if let Err(e) = result {
+        error!(
+            "Aborting at block {}: {}",
+            evm.next_block.separated_string(),
+            e
+        );
+        std::process::exit(1);
     }
 
     info!("Done.");
@@ -0,0 +1,499 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Re-costs the instructions in evmextract counts files under another gas schedule: what
+//! would these transactions have paid after EIP-1884, EIP-2929, ...?
+//!
+//! Only the static gas of each instruction can be recomputed from counts. Whatever a trace
+//! recorded on top of it (memory expansion, cold access, gas forwarded by calls, ...) is
+//! kept as it was; where the two forks' rules for that part differ it's reported as stale.
+//!
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate clap;
+extern crate csv;
+extern crate evmobserver;
+extern crate simple_logger;
+extern crate toml;
+
+use clap::{App, Arg};
+use evmobserver::evminst::{self, EvmInst};
+use evmobserver::evmtrace::{self, CountsLayout};
+use evmobserver::fork::Fork;
+use evmobserver::util::is_file;
+use log::Level;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+///
+/// Static gas to re-cost with: a fork's, optionally with some instructions overridden
+///
+struct Schedule {
+    /// `None` keeps each transaction's own fork
+    fork: Option<Fork>,
+
+    /// Static gas replacing the fork's, indexed like `evminst::VALUES`
+    overrides: Vec<Option<u64>>,
+}
+
+impl Schedule {
+    /// Read overrides from a TOML file of `OPCODE = gas` lines
+    fn load_overrides(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Reading {}: {}", path, e))?;
+        let table: BTreeMap<String, u64> =
+            toml::from_str(&text).map_err(|e| format!("Parsing {}: {}", path, e))?;
+
+        for (name, gas) in table {
+            let idx = evminst::VALUES
+                .iter()
+                .position(|inst| evminst::as_str(inst) == name)
+                .ok_or_else(|| format!("{}: unknown instruction {}", path, name))?;
+            self.overrides[idx] = Some(gas);
+        }
+
+        Ok(())
+    }
+
+    /// Static gas of instruction `idx` in `VALUES`, `None` if it isn't enabled in `fork`
+    fn static_gas(&self, idx: usize, fork: Fork) -> Option<u64> {
+        match self.overrides[idx] {
+            Some(gas) => Some(gas),
+            None => evminst::VALUES[idx].info(fork).map(|info| info.static_gas),
+        }
+    }
+}
+
+///
+/// One instruction's gas in a transaction, or summed over many
+///
+#[derive(Debug, Clone, Default)]
+struct Repriced {
+    count: u64,
+
+    /// As traced
+    gas: u64,
+
+    /// Under the new schedule
+    repriced: i64,
+
+    /// Gas beyond the static gas follows different rules in the new schedule's fork: what
+    /// was traced is kept, what the new rules would add or remove is unknown
+    stale: bool,
+
+    /// Traced gas beyond the static gas of stale instructions
+    stale_dynamic: u64,
+
+    /// Not enabled in the new schedule's fork, so kept at its traced gas
+    unavailable: bool,
+}
+
+impl Repriced {
+    fn add(&mut self, other: &Repriced) {
+        self.count += other.count;
+        self.gas += other.gas;
+        self.repriced += other.repriced;
+        self.stale |= other.stale;
+        self.stale_dynamic += other.stale_dynamic;
+        self.unavailable |= other.unavailable;
+    }
+
+    fn diff(&self) -> i64 {
+        self.repriced - self.gas as i64
+    }
+}
+
+/// True if gas beyond the static part of `inst` follows different rules in `from` and `to`
+fn dynamic_rules_differ(inst: EvmInst, from: Fork, to: Fork) -> bool {
+    match (inst.info(from), inst.info(to)) {
+        (Some(old), Some(new)) => {
+            (old.dynamic_gas || new.dynamic_gas) && old.repriced != new.repriced
+        }
+        _ => false,
+    }
+}
+
+/// Re-cost `count` executions of instruction `idx`, traced at `gas` under `from`
+fn reprice(schedule: &Schedule, idx: usize, count: u64, gas: u64, from: Fork) -> Repriced {
+    let inst = evminst::VALUES[idx];
+    let to = schedule.fork.unwrap_or(from);
+
+    let old_static = inst.info(from).map_or(0, |info| info.static_gas);
+    let new_static = match schedule.static_gas(idx, to) {
+        Some(gas) => gas,
+        None => {
+            return Repriced {
+                count,
+                gas,
+                repriced: gas as i64,
+                stale: false,
+                stale_dynamic: 0,
+                unavailable: true,
+            }
+        }
+    };
+
+    let dynamic = gas as i64 - (count * old_static) as i64;
+    let stale = dynamic_rules_differ(inst, from, to);
+
+    Repriced {
+        count,
+        gas,
+        repriced: (count * new_static) as i64 + dynamic,
+        stale,
+        stale_dynamic: if stale {
+            cmp::max(dynamic, 0) as u64
+        } else {
+            0
+        },
+        unavailable: false,
+    }
+}
+
+fn is_fork(arg: String) -> Result<(), String> {
+    arg.parse::<Fork>().map(|_| ())
+}
+
+/// Exit with the error writing `path` to the output CSV file
+fn write_failed<E: fmt::Display>(path: &str, e: E) -> ! {
+    let msg = format!("Writing {}: {}", path, e);
+    clap::Error::with_description(&msg, clap::ErrorKind::Io).exit()
+}
+
+fn percent(diff: i64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        diff as f64 * 100.0 / total as f64
+    }
+}
+
+fn main() {
+    let args = App::new("gas_reprice")
+        .version(crate_version!())
+        .about("Re-costs the instructions in evmextract counts files under another gas schedule")
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .value_name("FORK")
+                .validator(is_fork)
+                .required_unless("schedule")
+                .help(
+                    "Fork whose static gas to use, e.g. istanbul (EIP-1884) or berlin (EIP-2929)",
+                ),
+        )
+        .arg(
+            Arg::with_name("schedule")
+                .long("schedule")
+                .value_name("FILE")
+                .validator(is_file)
+                .help("TOML file of OPCODE = gas lines overriding the static gas of --to"),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("FORK")
+                .validator(is_fork)
+                .help("Fork the transactions were traced under [default: mainnet's at each block]"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("Write each transaction's traced and repriced gas to this CSV file"),
+        )
+        .arg(
+            Arg::with_name("COUNTS.CSV")
+                .required(true)
+                .multiple(true)
+                .validator(is_file)
+                .help("Counts files from evmextract"),
+        )
+        .get_matches();
+
+    simple_logger::init_with_level(Level::Info).unwrap();
+
+    let mut schedule = Schedule {
+        fork: args.value_of("to").map(|fork| fork.parse().unwrap()),
+        overrides: vec![None; evminst::INST_COUNT],
+    };
+    if let Some(path) = args.value_of("schedule") {
+        if let Err(msg) = schedule.load_overrides(path) {
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit();
+        }
+    }
+    let from: Option<Fork> = args.value_of("from").map(|fork| fork.parse().unwrap());
+
+    let mut output = args.value_of("output").map(|path| {
+        let mut writer = csv::Writer::from_path(path).unwrap_or_else(|e| write_failed(path, e));
+        writer
+            .write_record([
+                "block_num",
+                "txn_index",
+                "fork",
+                "gas",
+                "repriced_gas",
+                "diff",
+                "stale_dynamic_gas",
+                "stale_ops",
+            ])
+            .unwrap_or_else(|e| write_failed(path, e));
+        (path, writer)
+    });
+
+    let mut totals = vec![Repriced::default(); evminst::INST_COUNT];
+    let mut txns = 0u64;
+    let mut stale_txns = 0u64;
+
+    for count_file in args.values_of("COUNTS.CSV").unwrap() {
+        let mut reader = csv::Reader::from_path(count_file)
+            .unwrap_or_else(|e| panic!("Opening {}: {}", count_file, e));
+        let layout = CountsLayout::from_header(
+            reader
+                .byte_headers()
+                .unwrap_or_else(|e| panic!("Reading header of {}: {}", count_file, e)),
+        );
+
+        for record in reader.byte_records() {
+            let trace = match record {
+                Ok(trace) => trace,
+                Err(e) => {
+                    warn!("file {} record {:?}", count_file, e);
+                    continue;
+                }
+            };
+
+            let ts = evmtrace::get_field_u64(&trace, evmtrace::TS_IDX);
+            let block_num = evmtrace::get_field_u64(&trace, evmtrace::BLOCK_NUM_IDX);
+            let txn_index = evmtrace::get_field_u16(&trace, evmtrace::TXN_INDEX_IDX);
+            let txn_fork = from.unwrap_or_else(|| Fork::at(block_num, ts));
+
+            let mut txn = Repriced::default();
+            let mut stale_ops = Vec::new();
+
+            for (idx, total) in totals.iter_mut().enumerate() {
+                let (count, gas) = evmtrace::get_inst_fields(&trace, &layout, idx);
+                if count == 0 {
+                    continue;
+                }
+
+                let repriced = reprice(&schedule, idx, count as u64, gas, txn_fork);
+                if repriced.stale || repriced.unavailable {
+                    stale_ops.push(evminst::as_str(&evminst::VALUES[idx]));
+                }
+
+                txn.add(&repriced);
+                total.add(&repriced);
+            }
+
+            txns += 1;
+            if !stale_ops.is_empty() {
+                stale_txns += 1;
+            }
+
+            if let Some((path, ref mut writer)) = output {
+                writer
+                    .write_record(&[
+                        block_num.to_string(),
+                        txn_index.to_string(),
+                        txn_fork.to_string(),
+                        txn.gas.to_string(),
+                        txn.repriced.to_string(),
+                        txn.diff().to_string(),
+                        txn.stale_dynamic.to_string(),
+                        stale_ops.join(" "),
+                    ])
+                    .unwrap_or_else(|e| write_failed(path, e));
+            }
+        }
+    }
+
+    if let Some((path, ref mut writer)) = output {
+        writer.flush().unwrap_or_else(|e| write_failed(path, e));
+    }
+
+    let mut all = Repriced::default();
+    for total in totals.iter() {
+        all.add(total);
+    }
+
+    info!(
+        "{} transactions, instruction gas {} repriced {} ({:+}, {:+.2}%)",
+        txns,
+        all.gas,
+        all.repriced,
+        all.diff(),
+        percent(all.diff(), all.gas)
+    );
+
+    let mut changed: Vec<(usize, &Repriced)> = totals
+        .iter()
+        .enumerate()
+        .filter(|&(_, total)| total.diff() != 0 || total.stale || total.unavailable)
+        .collect();
+    changed.sort_by_key(|&(_, total)| -total.diff().abs());
+
+    for (idx, total) in changed {
+        let mut note = String::new();
+        if total.stale {
+            note = format!(
+                " STALE: dynamic gas rules differ, {} traced gas beyond static gas kept as is",
+                total.stale_dynamic
+            );
+        }
+        if total.unavailable {
+            note = " UNAVAILABLE: not enabled in the new schedule's fork, kept as traced".into();
+        }
+
+        info!(
+            "{:>14} count {} gas {} repriced {} ({:+}, {:+.2}%){}",
+            evminst::as_str(&evminst::VALUES[idx]),
+            total.count,
+            total.gas,
+            total.repriced,
+            total.diff(),
+            percent(total.diff(), total.gas),
+            note
+        );
+    }
+
+    if stale_txns > 0 {
+        warn!(
+            "{} of {} transactions have STALE or UNAVAILABLE instructions ({} traced dynamic \
+             gas, {:.2}% of the total); their repriced gas is only an estimate",
+            stale_txns,
+            txns,
+            all.stale_dynamic,
+            percent(all.stale_dynamic as i64, all.gas)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idx(inst: EvmInst) -> usize {
+        evminst::VALUES.iter().position(|&op| op == inst).unwrap()
+    }
+
+    fn schedule(fork: Fork) -> Schedule {
+        Schedule {
+            fork: Some(fork),
+            overrides: vec![None; evminst::INST_COUNT],
+        }
+    }
+
+    #[test]
+    fn dynamic_rules() {
+        let table = [
+            // EIP-2929 made cold access dynamic
+            (EvmInst::SLOAD, Fork::Istanbul, Fork::Berlin, true),
+            (EvmInst::CALL, Fork::Istanbul, Fork::Berlin, true),
+            // EIP-150 repriced SLOAD, but it had no dynamic gas either side
+            (EvmInst::SLOAD, Fork::Frontier, Fork::Istanbul, false),
+            (EvmInst::SLOAD, Fork::Berlin, Fork::Prague, false),
+            (
+                EvmInst::SUICIDE,
+                Fork::Frontier,
+                Fork::TangerineWhistle,
+                true,
+            ),
+            (EvmInst::ADD, Fork::Frontier, Fork::Osaka, false),
+            // not enabled in one of the forks
+            (EvmInst::PUSH0, Fork::Paris, Fork::Shanghai, false),
+        ];
+
+        for &(inst, from, to, differ) in table.iter() {
+            assert_eq!(
+                dynamic_rules_differ(inst, from, to),
+                differ,
+                "{} {} {}",
+                inst,
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn istanbul_to_berlin() {
+        let berlin = schedule(Fork::Berlin);
+
+        let sload = reprice(&berlin, idx(EvmInst::SLOAD), 2, 1600, Fork::Istanbul);
+        assert_eq!(sload.repriced, 200);
+        assert_eq!(sload.diff(), -1400);
+        assert!(sload.stale);
+        assert_eq!(sload.stale_dynamic, 0);
+        assert!(!sload.unavailable);
+
+        // the 9,000 gas of a value transfer is kept as traced
+        let call = reprice(&berlin, idx(EvmInst::CALL), 1, 9700, Fork::Istanbul);
+        assert_eq!(call.repriced, 9100);
+        assert!(call.stale);
+        assert_eq!(call.stale_dynamic, 9000);
+
+        let add = reprice(&berlin, idx(EvmInst::ADD), 10, 30, Fork::Istanbul);
+        assert_eq!(add.repriced, 30);
+        assert!(!add.stale);
+    }
+
+    #[test]
+    fn not_enabled_in_new_fork() {
+        let paris = schedule(Fork::Paris);
+
+        let push0 = reprice(&paris, idx(EvmInst::PUSH0), 3, 6, Fork::Shanghai);
+        assert!(push0.unavailable);
+        assert!(!push0.stale);
+        assert_eq!(push0.repriced, 6);
+        assert_eq!(push0.diff(), 0);
+    }
+
+    #[test]
+    fn override_file() {
+        let path = std::env::temp_dir().join(format!("gas_reprice-{}.toml", std::process::id()));
+        fs::write(&path, "SLOAD = 2100\nPUSH0 = 1\n").unwrap();
+
+        let mut schedule = schedule(Fork::Berlin);
+        schedule.load_overrides(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(
+            schedule.static_gas(idx(EvmInst::SLOAD), Fork::Berlin),
+            Some(2100)
+        );
+        assert_eq!(
+            schedule.static_gas(idx(EvmInst::BALANCE), Fork::Berlin),
+            Some(100)
+        );
+
+        let sload = reprice(&schedule, idx(EvmInst::SLOAD), 2, 200, Fork::Berlin);
+        assert_eq!(sload.repriced, 4200);
+        assert!(!sload.stale);
+
+        // an override makes the instruction available even before its fork
+        let push0 = reprice(&schedule, idx(EvmInst::PUSH0), 3, 6, Fork::Shanghai);
+        assert!(!push0.unavailable);
+        assert_eq!(push0.repriced, 3);
+
+        fs::write(&path, "KECCAK = 30\n").unwrap();
+        let err = schedule.load_overrides(path.to_str().unwrap()).unwrap_err();
+        assert!(err.ends_with("unknown instruction KECCAK"), "{}", err);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Compares the instructions in a contract's code with those executed by transactions sent
+//! to it, according to evmextract counts files
+//!
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate clap;
+extern crate csv;
+extern crate evmobserver;
+extern crate simple_logger;
+
+use clap::{App, Arg};
+use evmobserver::disasm;
+use evmobserver::evminst::{self, EvmInst};
+use evmobserver::evmtrace::{self, CountsLayout};
+use evmobserver::instcount::InstCount;
+use evmobserver::util::{is_address, is_file};
+use log::Level;
+
+/// Instructions in `code`, and how many bytes of it aren't instructions
+fn static_counts(code: &[u8]) -> (InstCount, usize) {
+    let mut counts = InstCount::new();
+    let mut unknown = 0;
+
+    for instruction in disasm::disassemble(code).instructions {
+        match instruction.inst {
+            Some(inst) => counts.inc_count(inst),
+            None => unknown += 1,
+        }
+    }
+
+    (counts, unknown)
+}
+
+/// Instructions executed by transactions to `address` in `count_files`, and how many
+/// transactions there were
+fn dynamic_counts(count_files: Vec<&str>, address: &str) -> (InstCount, u64) {
+    let mut counts = InstCount::new();
+    let mut txns = 0;
+
+    for count_file in count_files {
+        let mut reader = csv::Reader::from_path(count_file)
+            .unwrap_or_else(|e| panic!("Opening {}: {}", count_file, e));
+        let layout = CountsLayout::from_header(
+            reader
+                .byte_headers()
+                .unwrap_or_else(|e| panic!("Reading header of {}: {}", count_file, e)),
+        );
+
+        for record in reader.byte_records() {
+            let trace = match record {
+                Ok(trace) => trace,
+                Err(e) => {
+                    warn!("file {} record {:?}", count_file, e);
+                    continue;
+                }
+            };
+
+            let addr_to = evmtrace::get_field_str(&trace, evmtrace::ADDR_TO_IDX);
+            if !addr_to.eq_ignore_ascii_case(address) {
+                continue;
+            }
+
+            txns += 1;
+            for (idx, inst) in evminst::VALUES.iter().enumerate() {
+                let (count, gas) = evmtrace::get_inst_fields(&trace, &layout, idx);
+                counts.add_count(*inst, count as u64);
+                counts.add_gas(*inst, gas);
+            }
+        }
+    }
+
+    (counts, txns)
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+fn main() {
+    let args = App::new("opcode_profile")
+        .version(crate_version!())
+        .about("Compares a contract's instructions with those executed by transactions to it")
+        .after_help(
+            "Every byte of the code is read as an instruction or push data, without following \
+             jumps, so data stored in the code (constructor arguments, lookup tables, ...) is \
+             counted as instructions too. Executed counts are per transaction and include any \
+             code it called in other contracts.",
+        )
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .value_name("ADDRESS")
+                .required(true)
+                .validator(is_address)
+                .help("Contract to profile, matched against addr_to in the counts files"),
+        )
+        .arg(
+            Arg::with_name("rpc-url")
+                .long("rpc-url")
+                .value_name("URL")
+                .required_unless("code-file")
+                .help("geth endpoint to fetch the code from with eth_getCode"),
+        )
+        .arg(
+            Arg::with_name("block")
+                .long("block")
+                .value_name("N")
+                .requires("rpc-url")
+                .help("Fetch the code as of this block [default: latest]"),
+        )
+        .arg(
+            Arg::with_name("code-file")
+                .long("code-file")
+                .value_name("FILE")
+                .conflicts_with("rpc-url")
+                .help("Read the code from a file instead, as hex or raw bytes"),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .value_name("N")
+                .help("How many of the most executed instructions to list [default: 20]"),
+        )
+        .arg(
+            Arg::with_name("COUNTS.CSV")
+                .required(true)
+                .multiple(true)
+                .validator(is_file)
+                .help("Counts files from evmextract"),
+        )
+        .get_matches();
+
+    simple_logger::init_with_level(Level::Info).unwrap();
+
+    let address = args.value_of("address").unwrap();
+    let top: usize = value_t!(args, "top", usize).unwrap_or_else(|e| match e.kind {
+        clap::ErrorKind::ArgumentNotFound => 20,
+        _ => e.exit(),
+    });
+
+    let block_num: Option<u64> = args.value_of("block").map(|block| {
+        block.parse().unwrap_or_else(|_| {
+            let msg = format!("Invalid value for --block: {}", block);
+            clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+        })
+    });
+
+    let code = if let Some(path) = args.value_of("code-file") {
+        disasm::read_code(path).map_err(|e| format!("Reading {}: {}", path, e))
+    } else {
+        disasm::fetch_code(args.value_of("rpc-url").unwrap(), address, block_num)
+            .map_err(|e| format!("Fetching code of {}: {}", address, e))
+    };
+    let code = code.unwrap_or_else(|msg| {
+        clap::Error::with_description(&msg, clap::ErrorKind::InvalidValue).exit()
+    });
+    if code.is_empty() {
+        clap::Error::with_description(
+            &format!("{} has no code", address),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+
+    let (in_code, unknown) = static_counts(&code);
+    let (executed, txns) = dynamic_counts(args.values_of("COUNTS.CSV").unwrap().collect(), address);
+
+    let code_insts: u64 = evminst::VALUES
+        .iter()
+        .map(|inst| in_code.get_count(*inst))
+        .sum();
+    let executed_insts: u64 = evminst::VALUES
+        .iter()
+        .map(|inst| executed.get_count(*inst))
+        .sum();
+    let executed_gas: u64 = evminst::VALUES
+        .iter()
+        .map(|inst| executed.get_gas(*inst))
+        .sum();
+
+    println!(
+        "{}: {} bytes of code, {} instructions ({} distinct), {} unknown bytes",
+        address,
+        code.len(),
+        code_insts,
+        evminst::VALUES
+            .iter()
+            .filter(|inst| in_code.get_count(**inst) > 0)
+            .count(),
+        unknown
+    );
+    println!(
+        "{} transactions to it executed {} instructions ({} gas), including any code they \
+         called in other contracts",
+        txns, executed_insts, executed_gas
+    );
+
+    let never: Vec<&EvmInst> = evminst::VALUES
+        .iter()
+        .filter(|inst| in_code.get_count(**inst) > 0 && executed.get_count(**inst) == 0)
+        .collect();
+    println!();
+    println!("In the code, never executed (by it or code it called; data bytes count as code):");
+    for inst in never.iter() {
+        println!(
+            "  {:<14} {:>8} in code",
+            evminst::as_str(inst),
+            in_code.get_count(**inst)
+        );
+    }
+    if never.is_empty() {
+        println!("  (none)");
+    }
+
+    let mut hot: Vec<&EvmInst> = evminst::VALUES
+        .iter()
+        .filter(|inst| executed.get_count(**inst) > 0)
+        .collect();
+    hot.sort_by_key(|inst| -(executed.get_count(**inst) as i64));
+
+    println!();
+    println!("Most executed:");
+    println!(
+        "  {:<14} {:>12} {:>7} {:>14} {:>7} {:>8}",
+        "", "executed", "%", "gas", "% gas", "in code"
+    );
+    for inst in hot.iter().take(top) {
+        let count = executed.get_count(**inst);
+        let gas = executed.get_gas(**inst);
+        let static_count = in_code.get_count(**inst);
+
+        println!(
+            "  {:<14} {:>12} {:>6.2}% {:>14} {:>6.2}% {:>8}{}",
+            evminst::as_str(inst),
+            count,
+            percent(count, executed_insts),
+            gas,
+            percent(gas, executed_gas),
+            static_count,
+            if static_count == 0 {
+                "  not in this code"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn static_counts_of_code() {
+        // PUSH1 1, PUSH2 0x000c, an unknown byte, STOP, then data read as JUMPDEST and ADD
+        let code = [0x60, 0x01, 0x61, 0x00, 0x0c, 0x0c, 0x00, 0x5b, 0x01];
+
+        let (counts, unknown) = static_counts(&code);
+
+        assert_eq!(unknown, 1);
+        assert_eq!(counts.get_count(EvmInst::PUSH1), 1);
+        assert_eq!(counts.get_count(EvmInst::PUSH2), 1);
+        // the 0x00 pushed isn't another STOP
+        assert_eq!(counts.get_count(EvmInst::STOP), 1);
+        assert_eq!(counts.get_count(EvmInst::JUMPDEST), 1);
+        assert_eq!(counts.get_count(EvmInst::ADD), 1);
+        let total: u64 = evminst::VALUES
+            .iter()
+            .map(|&inst| counts.get_count(inst))
+            .sum();
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn dynamic_counts_of_address() {
+        let path = std::env::temp_dir().join(format!("opcode_profile-{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "ts,block_num,txn_index,addr_from,addr_to,gas_px,ADD_count,ADD_gas,SLOAD_count,SLOAD_gas\n\
+             1,10,0,0xaa,0xBB,1,2,6,1,2100\n\
+             1,10,1,0xaa,0xcc,1,5,15,0,0\n\
+             1,11,0,0xaa,0xbb,1,1,3,1,100\n",
+        )
+        .unwrap();
+
+        let (counts, txns) = dynamic_counts(vec![path.to_str().unwrap()], "0xbb");
+
+        assert_eq!(txns, 2);
+        assert_eq!(counts.get_count(EvmInst::ADD), 3);
+        assert_eq!(counts.get_gas(EvmInst::ADD), 9);
+        assert_eq!(counts.get_count(EvmInst::SLOAD), 2);
+        assert_eq!(counts.get_gas(EvmInst::SLOAD), 2200);
+        assert_eq!(counts.get_count(EvmInst::STOP), 0);
+
+        let _ = fs::remove_file(&path);
+    }
+}
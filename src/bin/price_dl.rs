@@ -17,6 +17,8 @@
 //!
 
 extern crate chrono;
+#[macro_use]
+extern crate clap;
 extern crate csv;
 extern crate evmobserver;
 extern crate json;
@@ -26,6 +28,7 @@ extern crate serde;
 extern crate serde_json;
 extern crate simple_logger;
 
+use clap::{App, Arg};
 use csv::Writer;
 use evmobserver::prices::Candlestick;
 use std::io;
@@ -41,19 +44,29 @@ fn write_prices<W: io::Write>(writer: &mut Writer<W>, prices: &Vec<Candlestick>)
     }
 }
 
+fn is_epoch(arg: String) -> Result<(), String> {
+    arg.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("{} isn't a Unix timestamp", arg))
+}
+
 fn main() {
     use log::Level;
-    use std::env::args;
 
-    simple_logger::init_with_level(Level::Info).unwrap();
+    let args = App::new("price_dl")
+        .version(crate_version!())
+        .about("Downloads ETH prices from Poloniex to prices.csv")
+        .arg(
+            Arg::with_name("START_EPOCH")
+                .required(true)
+                .validator(is_epoch)
+                .help("Unix timestamp of the first price"),
+        )
+        .get_matches();
 
-    let argv: Vec<String> = args().collect();
-    if argv.len() != 2 {
-        println!("usage: price_dl START_EPOCH");
-        std::process::exit(1);
-    }
+    simple_logger::init_with_level(Level::Info).unwrap();
 
-    let start_ts: u64 = argv.get(1).unwrap().parse().unwrap();
+    let start_ts: u64 = args.value_of("START_EPOCH").unwrap().parse().unwrap();
 
     let mut writer = csv::Writer::from_path("prices.csv").unwrap();
 
@@ -14,32 +14,47 @@
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate clap;
 extern crate csv;
 extern crate evmobserver;
 extern crate simple_logger;
 
-use std::env::args;
 use std::fmt::Write;
 
 use log::Level;
+use clap::{App, Arg};
 use csv::ByteRecord;
 use evmobserver::csvfiles::PriceReader;
 use evmobserver::evminst;
 use evmobserver::evmtrace;
+use evmobserver::evmtrace::CountsLayout;
 use evmobserver::prices::Candlestick;
+use evmobserver::util::is_file;
 
 const DIVISOR: f64 = 1e9;
 
-fn visitor(candle: &Candlestick, trace: &ByteRecord) {
+fn visitor(candle: &Candlestick, layout: &CountsLayout, trace: &ByteRecord) {
     let ts = evmtrace::get_field_u64(trace, evmtrace::TS_IDX);
     let block_num = evmtrace::get_field_u32(trace, evmtrace::BLOCK_NUM_IDX);
     let txn_index = evmtrace::get_field_u16(trace, evmtrace::TXN_INDEX_IDX);
     let addr_from = evmtrace::get_field_str(trace, evmtrace::ADDR_FROM_IDX);
-    let gas_px_gwei = evmtrace::get_field_u64(trace, evmtrace::GAS_PX_IDX) as f64 / DIVISOR;
+
+    // Wei per gas actually paid, and how much of it is burned (EIP-1559 base fee) versus
+    // tipped to the miner. Files without the fee columns predate London: all of gas_px tips.
+    let (gas_px_wei, burn_px_wei) = match evmtrace::get_fee_fields(trace, layout) {
+        Some(fees) => (fees.eff_gas_px, fees.base_fee.unwrap_or(0)),
+        None => (evmtrace::get_field_u64(trace, evmtrace::GAS_PX_IDX), 0),
+    };
+    let tip_px_wei = gas_px_wei.saturating_sub(burn_px_wei);
+
+    let gas_px_gwei = gas_px_wei as f64 / DIVISOR;
 
     let mid_px_fiat = candle.mid_price();
     let gas_px_eth = gas_px_gwei / DIVISOR;
     let gas_px_fiat = gas_px_eth * mid_px_fiat;
+    let burn_px_eth = burn_px_wei as f64 / DIVISOR / DIVISOR;
+    let tip_px_eth = tip_px_wei as f64 / DIVISOR / DIVISOR;
 
     let mut block_total_gas = 0u64;
     let mut block_total_px_eth = 0f64;
@@ -48,7 +63,7 @@ fn visitor(candle: &Candlestick, trace: &ByteRecord) {
     let mut output = String::with_capacity(2048);
 
     for (i, inst) in evminst::VALUES.iter().enumerate() {
-        let (count, gas) = evmtrace::get_inst_fields(trace, i);
+        let (count, gas) = evmtrace::get_inst_fields(trace, layout, i);
         if count == 0 || gas == 0 {
             continue;
         };
@@ -64,27 +79,41 @@ fn visitor(candle: &Candlestick, trace: &ByteRecord) {
         write!(output, "{}:{} {} = ${:.9}\n", inst, count, gas, gas_px_used_fiat).unwrap();
     }
 
+    let burned_eth = burn_px_eth * block_total_gas as f64;
+    let tip_eth = tip_px_eth * block_total_gas as f64;
+
     info!(
         "ts {}, block {}, from {}, txn {}, gas_total_count {}, gas_px_eth {:.12} \
-        ({:.3} gwei) * mid ${:.3} = ${:.9} TOTAL=${:.6} ({:.9} eth)\n{}",
+        ({:.3} gwei) * mid ${:.3} = ${:.9} TOTAL=${:.6} ({:.9} eth) \
+        burned=${:.6} ({:.9} eth) tip=${:.6} ({:.9} eth)\n{}",
         ts, block_num, addr_from, txn_index, block_total_gas, gas_px_eth, gas_px_gwei,
-        mid_px_fiat, gas_px_fiat, block_total_px_fiat, block_total_px_eth, output
+        mid_px_fiat, gas_px_fiat, block_total_px_fiat, block_total_px_eth,
+        burned_eth * mid_px_fiat, burned_eth, tip_eth * mid_px_fiat, tip_eth, output
     );
 }
 
 fn main() {
-    simple_logger::init_with_level(Level::Info).unwrap();
+    let args = App::new("price_load")
+        .version(crate_version!())
+        .about("Prices the gas of every instruction in evmextract counts files")
+        .arg(Arg::with_name("PRICES.CSV")
+            .required(true)
+            .validator(is_file)
+            .help("Prices from price_dl"))
+        .arg(Arg::with_name("COUNTS.CSV")
+            .required(true)
+            .multiple(true)
+            .validator(is_file)
+            .help("Counts files from evmextract"))
+        .get_matches();
 
-    let mut argv: Vec<String> = args().collect();
-
-    if argv.len() < 3 {
-        info!("Usage: price_load PRICES.CSV [COUNTS.CSV ...]");
-        std::process::exit(1);
-    }
+    simple_logger::init_with_level(Level::Info).unwrap();
 
-    let prices = PriceReader::new(argv.get(1).unwrap());
+    let prices = PriceReader::new(args.value_of("PRICES.CSV").unwrap());
 
     info!("Loaded {} prices", prices.len());
 
-    prices.process(argv.split_off(2), visitor);
+    let counts = args.values_of("COUNTS.CSV").unwrap().map(String::from).collect();
+
+    prices.process(counts, visitor);
 }
@@ -0,0 +1,83 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Where extraction got to, so an interrupted run can pick up exactly where it stopped
+//!
+
+use serde_json;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+///
+/// Progress of an extraction as of the end of a block
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// First block not yet written; everything before it is complete
+    pub next_block: u64,
+
+    /// Output file being written and its length once the blocks before `next_block` were
+    /// written. Anything past `file_len` belongs to a block that didn't finish.
+    pub file_name: String,
+    pub file_len: u64,
+
+    /// Rows in `file_name`, which decides when it's rotated
+    pub file_rows: u64,
+
+    /// Hash of block `next_block - 1` if this run or an earlier one extracted it, so a
+    /// resumed run can tell whether the chain was reorganised under it
+    #[serde(default)]
+    pub last_hash: Option<String>,
+}
+
+impl Checkpoint {
+    /// File the checkpoint is kept in, next to the output files
+    pub const FILE_NAME: &'static str = "evmextract.checkpoint";
+
+    /// Read the checkpoint at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        serde_json::from_reader(file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replace the checkpoint at `path`. Written to a temporary file, synced and renamed into
+    /// place, so a crash (even of the machine) leaves either the old checkpoint or the new
+    /// one, never a mix.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            serde_json::to_writer(&mut tmp, self).map_err(io::Error::other)?;
+            tmp.write_all(b"\n")?;
+            tmp.sync_all()?;
+        }
+
+        fs::rename(&tmp_path, path)?;
+
+        // the rename itself is durable once the directory is
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+}
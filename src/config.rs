@@ -0,0 +1,61 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Settings for `evmextract` kept in a TOML file instead of on the command line
+//!
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml;
+
+///
+/// Contents of an `evmextract` config file. Keys are named like the command line options
+/// (`rpc-url = "http://localhost:8545"`) and any of them may be left out; options given on
+/// the command line take precedence.
+///
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExtractConfig {
+    pub rpc_url: Option<String>,
+    pub start_block: Option<u64>,
+    pub end_block: Option<u64>,
+    pub out_dir: Option<String>,
+
+    /// Output file names, see `FileNameTemplate`
+    pub file_name: Option<String>,
+
+    /// `counts` or `calls`
+    pub format: Option<String>,
+
+    pub rotate_rows: Option<u64>,
+    pub checkpoint_blocks: Option<u64>,
+    pub workers: Option<usize>,
+
+    /// `structlog`, `histogram` or `native:NAME`
+    pub tracer: Option<String>,
+
+    pub confirmations: Option<u64>,
+    pub log_level: Option<String>,
+}
+
+impl ExtractConfig {
+    /// Read the config file at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
@@ -12,24 +12,294 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use checkpoint::Checkpoint;
 use csv;
 use csv::ByteRecord;
 use evminst;
-use gethrpc::{BlockInfo, TxnInfo};
+use evmtrace::CountsLayout;
+use gasacct::GasAccount;
+use gethrpc::{BlockInfo, CallFrame, TxnInfo};
 use instcount::InstCount;
 use prices::{BestPrice, Candlestick};
-use std::fs::File;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::BufWriter;
 use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, SeekFrom};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
 use std::result::Result::Ok;
 use std::str;
 
+///
+/// Where output files are written and how large they grow
+///
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Directory for the output files and the checkpoint
+    pub dir: PathBuf,
+
+    /// A new file is started, at the next block, once this many rows have been written
+    pub rotate_rows: u64,
+
+    /// How files are named, relative to `dir`
+    pub template: FileNameTemplate,
+
+    /// Name of the chain, for `{chain}` in `template`
+    pub chain: String,
+}
+
+impl OutputOptions {
+    /// Path of `file_name` in the output directory
+    pub fn path(&self, file_name: &str) -> PathBuf {
+        self.dir.join(file_name)
+    }
+
+    /// Name of the `format` file from `start_block` through `end_block`, `None` while it's
+    /// still being written
+    fn file_name(&self, format: &str, start_block: u64, end_block: Option<u64>) -> String {
+        let parts = self.template.resolve(&self.chain, format);
+
+        let mut name = String::new();
+        for part in &parts {
+            match *part {
+                NamePart::Text(ref text) => name.push_str(text),
+                NamePart::StartBlock(width) => {
+                    name.push_str(&format!("{:01$}", start_block, width))
+                }
+                NamePart::EndBlock(width) => match end_block {
+                    Some(end_block) => name.push_str(&format!("{:01$}", end_block, width)),
+                    None => name.push_str(FileNameTemplate::OPEN),
+                },
+                NamePart::Chain | NamePart::Format => unreachable!("resolved"),
+            }
+        }
+
+        name
+    }
+
+    /// First and last block of a `format` file named by `file_name`, `None` for any other
+    /// file
+    fn file_blocks(&self, format: &str, file_name: &str) -> Option<(u64, Option<u64>)> {
+        let parts = self.template.resolve(&self.chain, format);
+
+        match_name(&parts, file_name, None, None)
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            dir: PathBuf::from("."),
+            rotate_rows: 10_000,
+            template: FileNameTemplate::parse(FileNameTemplate::DEFAULT).unwrap(),
+            chain: String::new(),
+        }
+    }
+}
+
+/// A piece of a `FileNameTemplate`
+#[derive(Debug, Clone, PartialEq)]
+enum NamePart {
+    Text(String),
+    Chain,
+    Format,
+
+    /// Zero-padded to the width
+    StartBlock(usize),
+    EndBlock(usize),
+}
+
+///
+/// How output files are named, e.g. `{chain}/{start_block:010}-{end_block:010}.csv`.
+///
+/// `{start_block}` and `{end_block}` are the first and last block of a file, zero-padded to
+/// the width after the colon if there is one; `{chain}` is the chain's name and `{format}`
+/// the kind of output, `counts` or `calls`. Until a file is rotated its last block isn't
+/// known and `{end_block}` reads `open`; the file is renamed once it's finished.
+///
+#[derive(Debug, Clone)]
+pub struct FileNameTemplate {
+    parts: Vec<NamePart>,
+}
+
+impl FileNameTemplate {
+    /// `counts.123.csv` and `calls.123.csv`
+    pub const DEFAULT: &'static str = "{format}.{start_block}.csv";
+
+    /// Stands in for `{end_block}` in the name of a file still being written
+    const OPEN: &'static str = "open";
+
+    /// Parse `template`, which must name each file by its first block and stay within the
+    /// output directory
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while !rest.is_empty() {
+            let (text, placeholder) = match rest.find('{') {
+                Some(idx) => rest.split_at(idx),
+                None => (rest, ""),
+            };
+            if !text.is_empty() {
+                parts.push(NamePart::Text(text.to_string()));
+            }
+            if placeholder.is_empty() {
+                break;
+            }
+
+            let close = placeholder
+                .find('}')
+                .ok_or_else(|| format!("Unclosed {{ in file name template {}", template))?;
+            let inner = &placeholder[1..close];
+            rest = &placeholder[close + 1..];
+
+            let (name, width) = match inner.find(':') {
+                Some(idx) => {
+                    let width = inner[idx + 1..]
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid width in {{{}}}", inner))?;
+                    (&inner[..idx], Some(width))
+                }
+                None => (inner, None),
+            };
+
+            parts.push(match (name, width) {
+                ("chain", None) => NamePart::Chain,
+                ("format", None) => NamePart::Format,
+                ("start_block", width) => NamePart::StartBlock(width.unwrap_or(0)),
+                ("end_block", width) => NamePart::EndBlock(width.unwrap_or(0)),
+                _ => return Err(format!("Unknown placeholder {{{}}} in {}", inner, template)),
+            });
+        }
+
+        let starts = parts.iter().filter(|p| matches_block(p, true)).count();
+        let ends = parts.iter().filter(|p| matches_block(p, false)).count();
+        if starts != 1 || ends > 1 {
+            return Err(format!(
+                "File name template {} needs one {{start_block}} and at most one {{end_block}}",
+                template
+            ));
+        }
+
+        // a file's siblings are found by listing its directory, so blocks go in the file name
+        let first_block = parts
+            .iter()
+            .position(|p| matches_block(p, true) || matches_block(p, false));
+        let last_dir = parts.iter().rposition(|p| match *p {
+            NamePart::Text(ref text) => text.contains('/'),
+            _ => false,
+        });
+        if let (Some(first_block), Some(last_dir)) = (first_block, last_dir) {
+            if first_block < last_dir {
+                return Err(format!(
+                    "Blocks can only be in the file name, not the directory, of {}",
+                    template
+                ));
+            }
+        }
+
+        let outside = Path::new(template)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if outside {
+            return Err(format!(
+                "File name template {} must stay within the output directory",
+                template
+            ));
+        }
+
+        Ok(FileNameTemplate { parts })
+    }
+
+    /// Whether file names include the chain's name
+    pub fn has_chain(&self) -> bool {
+        self.parts.contains(&NamePart::Chain)
+    }
+
+    /// The template with `{chain}` and `{format}` filled in
+    fn resolve(&self, chain: &str, format: &str) -> Vec<NamePart> {
+        self.parts
+            .iter()
+            .map(|part| match *part {
+                NamePart::Chain => NamePart::Text(chain.to_string()),
+                NamePart::Format => NamePart::Text(format.to_string()),
+                ref part => part.clone(),
+            })
+            .collect()
+    }
+}
+
+fn matches_block(part: &NamePart, start: bool) -> bool {
+    match *part {
+        NamePart::StartBlock(_) => start,
+        NamePart::EndBlock(_) => !start,
+        _ => false,
+    }
+}
+
+/// Match `name` against resolved template `parts`, returning the first and last block it
+/// names. Numbers can be any length, so every split is tried.
+fn match_name(
+    parts: &[NamePart],
+    name: &str,
+    start_block: Option<u64>,
+    end_block: Option<u64>,
+) -> Option<(u64, Option<u64>)> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None if name.is_empty() => return start_block.map(|start| (start, end_block)),
+        None => return None,
+    };
+
+    if let NamePart::Text(ref text) = *part {
+        return if name.starts_with(text.as_str()) {
+            match_name(rest, &name[text.len()..], start_block, end_block)
+        } else {
+            None
+        };
+    }
+
+    if matches_block(part, false) && name.starts_with(FileNameTemplate::OPEN) {
+        let open = &name[FileNameTemplate::OPEN.len()..];
+        if let Some(blocks) = match_name(rest, open, start_block, None) {
+            return Some(blocks);
+        }
+    }
+
+    let digits = name.bytes().take_while(|b| b.is_ascii_digit()).count();
+    for len in (1..digits + 1).rev() {
+        let block = match name[..len].parse::<u64>() {
+            Ok(block) => block,
+            Err(_) => continue,
+        };
+
+        let found = if matches_block(part, true) {
+            match_name(rest, &name[len..], Some(block), end_block)
+        } else {
+            match_name(rest, &name[len..], start_block, Some(block))
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    None
+}
+
 ///
 /// CSV output of counts
 ///
 pub struct TraceOutFile {
     out_writer: BufWriter<File>,
+    options: OutputOptions,
+
+    /// File being written, and the rows written to it so far
+    file_name: String,
+    file_rows: u64,
+
+    /// First block of each file this run started; only those may be removed by `roll_back`
+    started: Vec<u64>,
 
     pub last_block: u64,
     pub last_time_stamp: u64,
@@ -39,6 +309,25 @@ pub struct TraceOutFile {
     pub total_gas: u64,
 }
 
+///
+/// CSV output of call frames
+///
+pub struct CallOutFile {
+    out_writer: csv::Writer<File>,
+    options: OutputOptions,
+
+    /// File being written, and the transactions written to it so far
+    file_name: String,
+    file_rows: u64,
+
+    /// First block of each file this run started; only those may be removed by `roll_back`
+    started: Vec<u64>,
+
+    pub last_block: u64,
+    pub total_txns: u64,
+    pub total_frames: u64,
+}
+
 pub struct PriceReader {
     pub prices: BestPrice,
 }
@@ -53,10 +342,18 @@ impl PriceReader {
 
     // visit_fn is intentionally a function pointer to prevent painfully long recompilation
     // whenever visit_fn is changed; cargo workspaces might help, if I knew how to use them :/
-    pub fn process(&self, count_files: Vec<String>, visit_fn: fn(&Candlestick, &ByteRecord) -> ()) {
+    pub fn process(
+        &self,
+        count_files: Vec<String>,
+        visit_fn: fn(&Candlestick, &CountsLayout, &ByteRecord) -> (),
+    ) {
         for count_file in count_files {
             let mut reader = csv::Reader::from_path(count_file.clone())
                 .expect(&format!("Opening {}", count_file));
+            let header = reader
+                .byte_headers()
+                .unwrap_or_else(|e| panic!("Reading header of {}: {}", count_file, e));
+            let layout = CountsLayout::from_header(header);
 
             for record in reader.byte_records() {
                 if record.is_err() {
@@ -69,7 +366,7 @@ impl PriceReader {
                 let ts: u64 = unsafe { str::from_utf8_unchecked(tmp).parse().unwrap() };
                 let candle = self.prices.nearest_record(ts).unwrap();
 
-                visit_fn(candle, &trace);
+                visit_fn(candle, &layout, &trace);
             }
         }
     }
@@ -80,26 +377,130 @@ impl PriceReader {
 }
 
 impl TraceOutFile {
-    const LINE_LIMIT: u64 = 10_000;
+    const PREFIX: &'static str = "counts";
+
+    pub fn new(starting_block: u64, options: OutputOptions) -> io::Result<Self> {
+        let file_name = options.file_name(Self::PREFIX, starting_block, None);
 
-    pub fn new(starting_block: u64) -> Self {
-        TraceOutFile {
-            out_writer: Self::create_outfile(starting_block),
+        Ok(TraceOutFile {
+            out_writer: Self::create_outfile(&options.path(&file_name))?,
+            options,
+            file_name,
+            file_rows: 0,
+            started: vec![starting_block],
             last_block: 0,
             last_time_stamp: 0,
             total_written: 0,
             total_txns: 0,
             total_inst: 0,
             total_gas: 0,
+        })
+    }
+
+    /// Carry on with the output recorded in `checkpoint`, discarding anything written after it
+    pub fn resume(checkpoint: &Checkpoint, options: OutputOptions) -> io::Result<Self> {
+        // the interrupted run may have started a file with the block it didn't finish
+        Self::reopen(checkpoint, options, vec![checkpoint.next_block])
+    }
+
+    /// `resume`, where the files starting at `started` may be removed
+    fn reopen(
+        checkpoint: &Checkpoint,
+        options: OutputOptions,
+        mut started: Vec<u64>,
+    ) -> io::Result<Self> {
+        let file = reopen_output(Self::PREFIX, &options, checkpoint, &started)?;
+
+        // a file written with fewer instruction columns is finished where it stands, so
+        // every file has a single layout
+        let path = options.path(&checkpoint.file_name);
+        let mut header = String::new();
+        BufReader::new(File::open(&path)?).read_line(&mut header)?;
+        if header.trim_end() != Self::header().trim_end() {
+            drop(file);
+            info!("{} has older columns, starting a new file", checkpoint.file_name);
+
+            if checkpoint.file_rows == 0 {
+                fs::remove_file(&path)?;
+            } else {
+                let end_block = checkpoint.next_block - 1;
+                finish_output(Self::PREFIX, &options, &checkpoint.file_name, end_block)?;
+            }
+
+            let mut out = Self::new(checkpoint.next_block, options)?;
+            out.last_block = checkpoint.next_block.saturating_sub(1);
+            out.started.append(&mut started);
+            return Ok(out);
         }
+
+        Ok(TraceOutFile {
+            out_writer: BufWriter::new(file),
+            options,
+            file_name: checkpoint.file_name.clone(),
+            file_rows: checkpoint.file_rows,
+            started,
+            last_block: checkpoint.next_block.saturating_sub(1),
+            last_time_stamp: 0,
+            total_written: 0,
+            total_txns: 0,
+            total_inst: 0,
+            total_gas: 0,
+        })
+    }
+
+    /// Discard everything written after `checkpoint`, e.g. blocks that were reorged out
+    pub fn roll_back(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        self.out_writer.flush()?;
+
+        let started = mem::take(&mut self.started);
+        let resumed = Self::reopen(checkpoint, self.options.clone(), started)?;
+        self.out_writer = resumed.out_writer;
+        self.file_name = resumed.file_name;
+        self.file_rows = resumed.file_rows;
+        self.started = resumed.started;
+        self.last_block = resumed.last_block;
+
+        Ok(())
+    }
+
+    /// Flush and describe the output so far, all blocks before `next_block` being written
+    pub fn checkpoint(&mut self, next_block: u64) -> io::Result<Checkpoint> {
+        self.out_writer.flush()?;
+
+        Ok(Checkpoint {
+            next_block,
+            file_name: self.file_name.clone(),
+            file_len: self.out_writer.get_ref().metadata()?.len(),
+            file_rows: self.file_rows,
+            last_hash: None,
+        })
+    }
+
+    /// Get everything written so far onto the disk; a checkpoint mustn't reach the disk
+    /// before the rows it vouches for
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.out_writer.flush()?;
+        self.out_writer.get_ref().sync_data()
+    }
+
+    /// Will the next block with rows start a new file?
+    pub fn rotation_due(&self) -> bool {
+        self.file_rows >= self.options.rotate_rows
     }
 
     pub fn write_count(
         &mut self,
         txn_count: &InstCount,
+        gas_account: Option<&GasAccount>,
         txn_info: &TxnInfo,
         block_info: &BlockInfo,
     ) -> io::Result<()> {
+        // a new file starts only with a new block, so no block is split across files
+        if block_info.block_num != self.last_block && self.file_rows >= self.options.rotate_rows
+        {
+            self.rotate_file(block_info.block_num)?;
+        }
+
         let mut written: usize = 0;
 
         written += self.out_writer
@@ -138,47 +539,675 @@ impl TraceOutFile {
             };
         }
 
+        // fee columns follow the opcodes so older readers still find everything else
+        written += self.out_writer
+            .write(txn_info.txn_type.to_string().as_ref())?;
+        written += self.out_writer.write(b",")?;
+        written += self.write_opt(txn_info.max_fee)?;
+        written += self.write_opt(txn_info.max_priority_fee)?;
+        written += self.out_writer
+            .write(txn_info.effective_gas_price.to_string().as_ref())?;
+        written += self.out_writer.write(b",")?;
+        written += self.write_opt(block_info.base_fee)?;
+
+        match txn_info.receipt {
+            Some(ref receipt) => {
+                let status = receipt.status.map(|ok| if ok { 1 } else { 0 });
+                written += self.write_opt(status)?;
+                written += self.write_opt(Some(receipt.gas_used))?;
+                written += self.write_opt(Some(receipt.cumulative_gas_used))?;
+                written += self.write_opt(Some(receipt.log_count as u64))?;
+            }
+            None => written += self.out_writer.write(b",,,,")?,
+        }
+
+        // left empty on a chain whose forks, and so gas rules, aren't known
+        match gas_account {
+            Some(gas_account) => {
+                written += self.write_opt(Some(gas_account.intrinsic))?;
+                written += self.write_opt(Some(gas_account.execution))?;
+                written += self.write_opt(Some(gas_account.refund))?;
+                if let Some(delta) = gas_account.delta {
+                    written += self.out_writer.write(delta.to_string().as_ref())?;
+                }
+                written += self.out_writer.write(b",")?;
+            }
+            None => written += self.out_writer.write(b",,,,")?,
+        }
+
         written += self.out_writer.write(b"\n")?;
         self.out_writer.flush()?;
 
         self.total_txns += 1;
+        self.file_rows += 1;
         self.last_block = block_info.block_num;
         self.last_time_stamp = block_info.time_stamp;
         self.total_written += written as u64;
 
-        if self.total_txns % Self::LINE_LIMIT == 0 {
-            self.rotate_file();
+        Ok(())
+    }
+
+    /// Write `val` and its trailing comma, leaving the field empty for `None`
+    fn write_opt(&mut self, val: Option<u64>) -> io::Result<usize> {
+        let mut written = 0;
+
+        if let Some(val) = val {
+            written += self.out_writer.write(val.to_string().as_ref())?;
         }
+        written += self.out_writer.write(b",")?;
 
-        Ok(())
+        Ok(written)
     }
 
-    fn rotate_file(&mut self) {
-        self.out_writer.flush().unwrap();
-        self.out_writer = Self::create_outfile(self.last_block);
+    /// Continue in a new file starting with `block_num`, naming the finished one for the
+    /// blocks it holds
+    fn rotate_file(&mut self, block_num: u64) -> io::Result<()> {
+        self.out_writer.flush()?;
+
+        let new_name = self.options.file_name(Self::PREFIX, block_num, None);
+        self.started.push(block_num);
+        self.out_writer = Self::create_outfile(&self.options.path(&new_name))?;
+        let finished = mem::replace(&mut self.file_name, new_name);
+        self.file_rows = 0;
+
+        finish_output(Self::PREFIX, &self.options, &finished, block_num - 1)
     }
 
-    fn create_outfile(block_num: u64) -> BufWriter<File> {
-        let file_name = format!("counts.{}.csv", block_num);
-        let outfile = File::create(&file_name).expect("Failed to create file");
-        info!("Writing to {}", &file_name);
+    fn create_outfile(path: &Path) -> io::Result<BufWriter<File>> {
+        create_parent(path)?;
+        let outfile = File::create(path)?;
+        info!("Writing to {}", path.display());
 
         let mut writer = BufWriter::new(outfile);
 
-        writer
-            .write("ts,block_num,txn_index,addr_from,addr_to,gas_px,".as_ref())
-            .unwrap();
+        writer.write_all(Self::header().as_bytes())?;
+        writer.flush()?;
 
-        for i in 0..evminst::VALUES.len() {
-            let op = evminst::as_str(&evminst::VALUES[i]).as_ref();
-            writer.write(op).unwrap();
-            writer.write("_count,".as_ref()).unwrap();
-            writer.write(op).unwrap();
-            writer.write("_gas,".as_ref()).unwrap();
+        Ok(writer)
+    }
+
+    /// Column names, one line
+    fn header() -> String {
+        let mut header = String::from("ts,block_num,txn_index,addr_from,addr_to,gas_px,");
+
+        for op in evminst::VALUES.iter() {
+            let op = evminst::as_str(op);
+            header.push_str(&format!("{}_count,{}_gas,", op, op));
+        }
+        header.push_str("txn_type,max_fee,max_prio_fee,eff_gas_px,base_fee,");
+        header.push_str("status,gas_used,cum_gas_used,log_count,");
+        header.push_str("intrinsic_gas,exec_gas,refund,gas_delta,\n");
+
+        header
+    }
+}
+
+impl CallOutFile {
+    const PREFIX: &'static str = "calls";
+
+    const HEADER: [&'static str; 12] = [
+        "block_num",
+        "txn_index",
+        "frame",
+        "depth",
+        "parent",
+        "call_type",
+        "addr_from",
+        "addr_to",
+        "value",
+        "gas",
+        "gas_used",
+        "error",
+    ];
+
+    pub fn new(starting_block: u64, options: OutputOptions) -> io::Result<Self> {
+        let file_name = options.file_name(Self::PREFIX, starting_block, None);
+
+        Ok(CallOutFile {
+            out_writer: Self::create_outfile(&options.path(&file_name))?,
+            options,
+            file_name,
+            file_rows: 0,
+            started: vec![starting_block],
+            last_block: 0,
+            total_txns: 0,
+            total_frames: 0,
+        })
+    }
+
+    /// Carry on with the output recorded in `checkpoint`, discarding anything written after it
+    pub fn resume(checkpoint: &Checkpoint, options: OutputOptions) -> io::Result<Self> {
+        // the interrupted run may have started a file with the block it didn't finish
+        Self::reopen(checkpoint, options, vec![checkpoint.next_block])
+    }
+
+    /// `resume`, where the files starting at `started` may be removed
+    fn reopen(
+        checkpoint: &Checkpoint,
+        options: OutputOptions,
+        started: Vec<u64>,
+    ) -> io::Result<Self> {
+        let file = reopen_output(Self::PREFIX, &options, checkpoint, &started)?;
+
+        Ok(CallOutFile {
+            out_writer: csv::Writer::from_writer(file),
+            options,
+            file_name: checkpoint.file_name.clone(),
+            file_rows: checkpoint.file_rows,
+            started,
+            last_block: checkpoint.next_block.saturating_sub(1),
+            total_txns: 0,
+            total_frames: 0,
+        })
+    }
+
+    /// Discard everything written after `checkpoint`, e.g. blocks that were reorged out
+    pub fn roll_back(&mut self, checkpoint: &Checkpoint) -> io::Result<()> {
+        self.out_writer.flush()?;
+
+        let started = mem::take(&mut self.started);
+        let resumed = Self::reopen(checkpoint, self.options.clone(), started)?;
+        self.out_writer = resumed.out_writer;
+        self.file_name = resumed.file_name;
+        self.file_rows = resumed.file_rows;
+        self.started = resumed.started;
+        self.last_block = resumed.last_block;
+
+        Ok(())
+    }
+
+    /// Flush and describe the output so far, all blocks before `next_block` being written
+    pub fn checkpoint(&mut self, next_block: u64) -> io::Result<Checkpoint> {
+        self.out_writer.flush()?;
+
+        Ok(Checkpoint {
+            next_block,
+            file_name: self.file_name.clone(),
+            file_len: self.out_writer.get_ref().metadata()?.len(),
+            file_rows: self.file_rows,
+            last_hash: None,
+        })
+    }
+
+    /// Get everything written so far onto the disk; a checkpoint mustn't reach the disk
+    /// before the rows it vouches for
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.out_writer.flush()?;
+        self.out_writer.get_ref().sync_data()
+    }
+
+    /// Will the next block with rows start a new file?
+    pub fn rotation_due(&self) -> bool {
+        self.file_rows >= self.options.rotate_rows
+    }
+
+    /// Write the call frames of one transaction
+    pub fn write_frames(&mut self, frames: &[CallFrame]) -> csv::Result<()> {
+        // a new file starts only with a new block, so no block is split across files
+        if let Some(frame) = frames.first() {
+            if frame.block_num != self.last_block && self.file_rows >= self.options.rotate_rows {
+                self.rotate_file(frame.block_num)?;
+            }
+        }
+
+        for frame in frames {
+            // quoting is up to the csv writer, `error` is free text
+            self.out_writer.write_record(&[
+                frame.block_num.to_string(),
+                frame.txn_index.to_string(),
+                frame.frame_index.to_string(),
+                frame.depth.to_string(),
+                frame.parent.map(|p| p.to_string()).unwrap_or_default(),
+                frame.call_type.clone(),
+                frame.from.clone(),
+                frame.to.clone(),
+                frame.value.map(|v| v.to_string()).unwrap_or_default(),
+                frame.gas.to_string(),
+                frame.gas_used.to_string(),
+                frame.error.clone().unwrap_or_default(),
+            ])?;
+
+            self.last_block = frame.block_num;
+            self.total_frames += 1;
+        }
+
+        self.out_writer.flush()?;
+        self.total_txns += 1;
+        self.file_rows += 1;
+
+        Ok(())
+    }
+
+    /// Continue in a new file starting with `block_num`, naming the finished one for the
+    /// blocks it holds
+    fn rotate_file(&mut self, block_num: u64) -> io::Result<()> {
+        self.out_writer.flush()?;
+
+        let new_name = self.options.file_name(Self::PREFIX, block_num, None);
+        self.started.push(block_num);
+        self.out_writer = Self::create_outfile(&self.options.path(&new_name))?;
+        let finished = mem::replace(&mut self.file_name, new_name);
+        self.file_rows = 0;
+
+        finish_output(Self::PREFIX, &self.options, &finished, block_num - 1)
+    }
+
+    fn create_outfile(path: &Path) -> io::Result<csv::Writer<File>> {
+        create_parent(path)?;
+        let outfile = File::create(path)?;
+        info!("Writing to {}", path.display());
+
+        let mut writer = csv::Writer::from_writer(outfile);
+
+        writer.write_record(Self::HEADER)?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+}
+
+/// Make sure the directory `path` is to be created in exists
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+/// Rename the finished output file `file_name`, whose last block is `end_block`, to include
+/// its block range
+fn finish_output(
+    format: &str,
+    options: &OutputOptions,
+    file_name: &str,
+    end_block: u64,
+) -> io::Result<()> {
+    let start_block = match options.file_blocks(format, file_name) {
+        Some((start_block, None)) => start_block,
+        _ => return Ok(()),
+    };
+
+    let final_name = options.file_name(format, start_block, Some(end_block));
+    if final_name != file_name {
+        fs::rename(options.path(file_name), options.path(&final_name))?;
+        info!("Finished {}", final_name);
+    }
+
+    Ok(())
+}
+
+/// Open the output file of `checkpoint` to append to it, cutting off whatever was written
+/// after the checkpoint. Later output files can only hold blocks about to be redone; they're
+/// deleted if they start with one of the `started` blocks, i.e. were created by us or by the
+/// run that was interrupted. Any other means the output directory holds files we know
+/// nothing about, and nothing is touched.
+fn reopen_output(
+    format: &str,
+    options: &OutputOptions,
+    checkpoint: &Checkpoint,
+    started: &[u64],
+) -> io::Result<File> {
+    let start_block = match options.file_blocks(format, &checkpoint.file_name) {
+        Some((start_block, None)) => start_block,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "checkpoint is for {}, not {} output",
+                    checkpoint.file_name, format
+                ),
+            ))
+        }
+    };
+
+    // the checkpoint's file and every later one are in the same directory
+    let sub_dir = Path::new(&checkpoint.file_name)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .to_path_buf();
+    let mut siblings = Vec::new();
+    for entry in fs::read_dir(options.dir.join(&sub_dir))? {
+        let file_name = sub_dir.join(entry?.file_name());
+        siblings.push(file_name.to_string_lossy().into_owned());
+    }
+
+    let path = options.path(&checkpoint.file_name);
+
+    // rotated after the checkpoint was taken; blocks since are about to be discarded
+    let finished = if path.exists() {
+        None
+    } else {
+        siblings.iter().find(|name| match options.file_blocks(format, name) {
+            Some((start, Some(_))) => start == start_block,
+            _ => false,
+        })
+    };
+
+    let mut later = Vec::new();
+    for file_name in &siblings {
+        match options.file_blocks(format, file_name) {
+            Some((start, _))
+                if start >= checkpoint.next_block
+                    && *file_name != checkpoint.file_name
+                    && Some(file_name) != finished =>
+            {
+                if !started.contains(&start) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "{} follows the checkpoint but wasn't written by this run",
+                            file_name
+                        ),
+                    ));
+                }
+                later.push(file_name);
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(finished) = finished {
+        warn!("Reopening {} as {}", finished, checkpoint.file_name);
+        fs::rename(options.path(finished), &path)?;
+    }
+
+    let mut file = OpenOptions::new().write(true).open(&path)?;
+
+    let len = file.metadata()?.len();
+    if len < checkpoint.file_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has {} bytes, fewer than the {} of its checkpoint",
+                checkpoint.file_name, len, checkpoint.file_len
+            ),
+        ));
+    }
+    if len > checkpoint.file_len {
+        warn!(
+            "Truncating {} bytes of an unfinished block from {}",
+            len - checkpoint.file_len,
+            checkpoint.file_name
+        );
+        file.set_len(checkpoint.file_len)?;
+    }
+    file.seek(SeekFrom::End(0))?;
+
+    for file_name in later {
+        warn!("Removing {}, written after the checkpoint", file_name);
+        fs::remove_file(options.path(file_name))?;
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(template: &str, dir: PathBuf) -> OutputOptions {
+        OutputOptions {
+            dir,
+            rotate_rows: 1,
+            template: FileNameTemplate::parse(template).unwrap(),
+            chain: "mainnet".to_string(),
+        }
+    }
+
+    fn out_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("csvfiles-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn frame(block_num: u64) -> CallFrame {
+        CallFrame {
+            block_num,
+            txn_index: 0,
+            frame_index: 0,
+            depth: 1,
+            parent: None,
+            call_type: "CALL".to_string(),
+            from: "0xaa".to_string(),
+            to: "0xbb".to_string(),
+            value: Some(0),
+            gas: 30_000,
+            gas_used: 21_000,
+            error: None,
+        }
+    }
+
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn template_errors() {
+        let rejected = [
+            "{format}.{blocks}.csv",
+            "{chain:3}-{start_block}.csv",
+            "{format}.{start_block.csv",
+            "{start_block:wide}.csv",
+            "{format}.csv",
+            "{start_block}-{start_block}.csv",
+            "{start_block}-{end_block}-{end_block}.csv",
+            "{start_block}/counts.csv",
+            "../{start_block}.csv",
+            "/tmp/{start_block}.csv",
+        ];
+
+        for template in rejected.iter() {
+            assert!(
+                FileNameTemplate::parse(template).is_err(),
+                "{} accepted",
+                template
+            );
         }
-        writer.write(b"\n").unwrap();
-        writer.flush().unwrap();
 
-        writer
+        let err = FileNameTemplate::parse("{format}.{blocks}.csv").unwrap_err();
+        assert_eq!(err, "Unknown placeholder {blocks} in {format}.{blocks}.csv");
+    }
+
+    #[test]
+    fn file_names() {
+        let ranged = options(
+            "{chain}/{start_block:010}-{end_block:010}.csv",
+            PathBuf::new(),
+        );
+        assert_eq!(
+            ranged.file_name("counts", 4_370_000, None),
+            "mainnet/0004370000-open.csv"
+        );
+        assert_eq!(
+            ranged.file_name("counts", 4_370_000, Some(4_370_213)),
+            "mainnet/0004370000-0004370213.csv"
+        );
+
+        let default = options(FileNameTemplate::DEFAULT, PathBuf::new());
+        assert_eq!(default.file_name("calls", 123, Some(456)), "calls.123.csv");
+        assert!(!default.template.has_chain());
+        assert!(ranged.template.has_chain());
+    }
+
+    #[test]
+    fn file_blocks() {
+        let ranged = options(
+            "{chain}/{start_block:010}-{end_block:010}.csv",
+            PathBuf::new(),
+        );
+        assert_eq!(
+            ranged.file_blocks("counts", "mainnet/0004370000-open.csv"),
+            Some((4_370_000, None))
+        );
+        assert_eq!(
+            ranged.file_blocks("counts", "mainnet/0004370000-0004370213.csv"),
+            Some((4_370_000, Some(4_370_213)))
+        );
+
+        let default = options(FileNameTemplate::DEFAULT, PathBuf::new());
+        assert_eq!(default.file_blocks("counts", "counts.12.csv"), Some((12, None)));
+
+        // files that aren't ours must never be taken for output and removed
+        let not_ours = [
+            (&ranged, "mainnet/0004370000-open.csv.tmp"),
+            (&ranged, "sepolia/0004370000-open.csv"),
+            (&ranged, "mainnet/0004370000-.csv"),
+            (&ranged, "mainnet/0004370000-closed.csv"),
+            (&default, "calls.12.csv"),
+            (&default, "counts.twelve.csv"),
+            (&default, "counts..csv"),
+            (&default, "counts.12.csv~"),
+            (&default, "counts.-12.csv"),
+            (&default, Checkpoint::FILE_NAME),
+        ];
+        for &(options, name) in not_ours.iter() {
+            assert_eq!(options.file_blocks("counts", name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn rotation_renames_finished_file() {
+        let dir = out_dir("rotation");
+        let options = options("{format}-{start_block}-{end_block}.csv", dir.clone());
+
+        let mut calls = CallOutFile::new(5, options).unwrap();
+        calls.write_frames(&[frame(5)]).unwrap();
+        calls.write_frames(&[frame(5)]).unwrap();
+        assert_eq!(listing(&dir), ["calls-5-open.csv"]);
+
+        calls.write_frames(&[frame(7)]).unwrap();
+        assert_eq!(listing(&dir), ["calls-5-6.csv", "calls-7-open.csv"]);
+
+        let finished = fs::read_to_string(dir.join("calls-5-6.csv")).unwrap();
+        assert_eq!(finished.lines().count(), 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_rotation_is_an_error() {
+        let dir = out_dir("failed-rotation");
+        let options = options(FileNameTemplate::DEFAULT, dir.clone());
+
+        let mut calls = CallOutFile::new(5, options).unwrap();
+        calls.write_frames(&[frame(5)]).unwrap();
+
+        // the next file can't be created
+        fs::create_dir(dir.join("calls.7.csv")).unwrap();
+        assert!(calls.write_frames(&[frame(7)]).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn counts_read_back() {
+        use evminst::EvmInst;
+        use evmtrace;
+        use gethrpc::ReceiptInfo;
+
+        let dir = out_dir("read-back");
+        let mut counts = TraceOutFile::new(5, options(FileNameTemplate::DEFAULT, dir.clone()))
+            .unwrap();
+
+        let block = BlockInfo {
+            block_num: 5,
+            time_stamp: 1_700_000_000,
+            gas_limit: 30_000_000,
+            gas_used: 50_000,
+            miner: "0xcc".to_string(),
+            hash: "0x05".to_string(),
+            parent_hash: "0x04".to_string(),
+            base_fee: Some(7),
+            difficulty: 0,
+            size: 1_000,
+            txn_count: 2,
+            txns: Vec::new(),
+        };
+        let mut txn = TxnInfo {
+            block_num: 5,
+            block_index: 0,
+            gas_price: 9,
+            from: "0xaa".to_string(),
+            to: "0xbb".to_string(),
+            hash: "0x01".to_string(),
+            nonce: 0,
+            value: 0,
+            input_size: 0,
+            input_zero_bytes: 0,
+            access_list_addresses: 0,
+            access_list_keys: 0,
+            authorizations: 0,
+            gas_limit: 100_000,
+            txn_type: 2,
+            max_fee: Some(10),
+            max_priority_fee: Some(2),
+            effective_gas_price: 9,
+            receipt: Some(ReceiptInfo {
+                status: Some(true),
+                gas_used: 30_000,
+                cumulative_gas_used: 30_000,
+                log_count: 1,
+            }),
+        };
+        let gas = GasAccount {
+            intrinsic: 21_000,
+            execution: 9_000,
+            refund: 0,
+            delta: Some(0),
+        };
+
+        let mut inst_count = InstCount::new();
+        inst_count.add_count(EvmInst::TLOAD, 2);
+        inst_count.add_gas(EvmInst::TLOAD, 200);
+        counts.write_count(&inst_count, Some(&gas), &txn, &block).unwrap();
+
+        // a legacy transaction without a receipt, on a chain without gas accounting
+        txn.block_index = 1;
+        txn.txn_type = 0;
+        txn.max_fee = None;
+        txn.max_priority_fee = None;
+        txn.receipt = None;
+        counts.write_count(&InstCount::new(), None, &txn, &block).unwrap();
+        counts.sync().unwrap();
+
+        let mut reader = csv::Reader::from_path(dir.join("counts.5.csv")).unwrap();
+        let layout = CountsLayout::from_header(reader.byte_headers().unwrap());
+        let records: Vec<_> = reader.byte_records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+
+        let tload = evminst::VALUES.iter().position(|&op| op == EvmInst::TLOAD).unwrap();
+        assert_eq!(evmtrace::get_inst_fields(&records[0], &layout, tload), (2, 200));
+        assert_eq!(evmtrace::get_inst_fields(&records[1], &layout, tload), (0, 0));
+
+        let fees = evmtrace::get_fee_fields(&records[0], &layout).unwrap();
+        assert_eq!(fees.txn_type, 2);
+        assert_eq!(fees.max_fee, Some(10));
+        assert_eq!(fees.max_prio_fee, Some(2));
+        assert_eq!(fees.eff_gas_px, 9);
+        assert_eq!(fees.base_fee, Some(7));
+
+        let receipt = evmtrace::get_receipt_fields(&records[0], &layout).unwrap();
+        assert_eq!(receipt.status, Some(1));
+        assert_eq!(receipt.gas_used, 30_000);
+        assert_eq!(receipt.log_count, 1);
+
+        let gas = evmtrace::get_gas_fields(&records[0], &layout).unwrap();
+        assert_eq!(gas.intrinsic_gas, 21_000);
+        assert_eq!(gas.exec_gas, 9_000);
+        assert_eq!(gas.gas_delta, Some(0));
+
+        let fees = evmtrace::get_fee_fields(&records[1], &layout).unwrap();
+        assert_eq!(fees.txn_type, 0);
+        assert_eq!(fees.max_fee, None);
+        assert!(evmtrace::get_receipt_fields(&records[1], &layout).is_none());
+        assert!(evmtrace::get_gas_fields(&records[1], &layout).is_none());
+
+        let _ = fs::remove_dir_all(&dir);
     }
 }
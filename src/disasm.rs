@@ -0,0 +1,361 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Decodes contract bytecode into instructions
+//!
+
+use evminst::EvmInst;
+use gethrpc::{GethRpc, GethRpcError};
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::Path;
+use util::hex_to_bytes;
+
+///
+/// One instruction of disassembled code
+///
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    /// Byte offset in the code, as seen by the program counter
+    pub offset: usize,
+
+    pub opcode: u8,
+
+    /// `None` for a byte that no fork has made an instruction
+    pub inst: Option<EvmInst>,
+
+    /// PUSHn data; fewer than n bytes when the code ends first
+    pub immediate: Vec<u8>,
+}
+
+impl Instruction {
+    /// True if the code ended before all of this PUSHn's data
+    pub fn is_truncated(&self) -> bool {
+        match self.inst {
+            Some(inst) => self.immediate.len() < inst.immediate_size(),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.inst {
+            Some(inst) => write!(f, "{:06x}  {}", self.offset, inst)?,
+            None => write!(f, "{:06x}  UNKNOWN({:#04x})", self.offset, self.opcode)?,
+        }
+
+        if !self.immediate.is_empty() {
+            write!(f, " 0x")?;
+            for byte in self.immediate.iter() {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+
+        if let Some(inst) = self.inst {
+            if self.is_truncated() {
+                write!(
+                    f,
+                    " (truncated, {} of {} bytes)",
+                    self.immediate.len(),
+                    inst.immediate_size()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The CBOR encoded metadata solc appends to contract code, after the last instruction
+///
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// Where it starts in the code; its 2 byte length follows it at the end of the code
+    pub offset: usize,
+    pub len: usize,
+
+    /// Keys of the CBOR map: `ipfs`, `bzzr0`, `bzzr1`, `solc`, `experimental`
+    pub keys: Vec<String>,
+
+    /// Compiler version, e.g. `0.8.19`; `None` before solc 0.5.9
+    pub solc: Option<String>,
+}
+
+///
+/// Decoded code: the instructions, and the metadata trailer if there is one
+///
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    pub instructions: Vec<Instruction>,
+    pub metadata: Option<Metadata>,
+}
+
+/// Decode `code`. The solc metadata trailer isn't decoded as instructions.
+pub fn disassemble(code: &[u8]) -> Disassembly {
+    let metadata = find_metadata(code);
+    let end = metadata
+        .as_ref()
+        .map_or(code.len(), |metadata| metadata.offset);
+
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < end {
+        let opcode = code[offset];
+        let inst = EvmInst::from_opcode(opcode);
+
+        // as in the EVM, push data can run into the trailer
+        let data_start = offset + 1;
+        let data_end = match inst {
+            Some(inst) => (data_start + inst.immediate_size()).min(code.len()),
+            None => data_start,
+        };
+
+        instructions.push(Instruction {
+            offset,
+            opcode,
+            inst,
+            immediate: code[data_start..data_end].to_vec(),
+        });
+
+        offset = data_end;
+    }
+
+    Disassembly {
+        instructions,
+        metadata,
+    }
+}
+
+/// Code saved in a file, as hex text (what `eth_getCode` returns) or raw bytes
+pub fn read_code<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+
+    let hex = hex_to_bytes(String::from_utf8_lossy(&bytes).trim());
+    Ok(hex.unwrap_or(bytes))
+}
+
+/// Code of the contract at `address` according to geth at `rpc_url`, as of `block_num` or
+/// the latest block
+pub fn fetch_code(
+    rpc_url: &str,
+    address: &str,
+    block_num: Option<u64>,
+) -> Result<Vec<u8>, GethRpcError> {
+    GethRpc::new(rpc_url)?.get_code(address, block_num)
+}
+
+/// The solc metadata at the end of `code`, if its length suffix points at a CBOR map of
+/// exactly that length
+fn find_metadata(code: &[u8]) -> Option<Metadata> {
+    if code.len() < 2 {
+        return None;
+    }
+
+    let len = ((code[code.len() - 2] as usize) << 8) | code[code.len() - 1] as usize;
+    if len == 0 || len + 2 > code.len() {
+        return None;
+    }
+
+    let offset = code.len() - 2 - len;
+    let mut cbor = Cbor {
+        bytes: &code[offset..code.len() - 2],
+        pos: 0,
+    };
+
+    let entries = match cbor.next()? {
+        (5, n) if n > 0 => n,
+        _ => return None,
+    };
+
+    let mut keys = Vec::new();
+    let mut solc = None;
+
+    for _ in 0..entries {
+        let key = match cbor.next()? {
+            (3, n) => String::from_utf8(cbor.take(n)?.to_vec()).ok()?,
+            _ => return None,
+        };
+
+        let value = match cbor.next()? {
+            (2, n) => {
+                let bytes = cbor.take(n)?;
+                // release builds encode the version as 3 bytes
+                if bytes.len() == 3 {
+                    Some(format!("{}.{}.{}", bytes[0], bytes[1], bytes[2]))
+                } else {
+                    None
+                }
+            }
+            (3, n) => Some(String::from_utf8_lossy(cbor.take(n)?).into_owned()),
+            // true, false
+            (7, 20) | (7, 21) => None,
+            _ => return None,
+        };
+
+        if key == "solc" {
+            solc = value;
+        }
+        keys.push(key);
+    }
+
+    if cbor.pos != cbor.bytes.len() || !keys.iter().any(|key| is_metadata_key(key)) {
+        return None;
+    }
+
+    Some(Metadata {
+        offset,
+        len: len + 2,
+        keys,
+        solc,
+    })
+}
+
+fn is_metadata_key(key: &str) -> bool {
+    matches!(key, "ipfs" | "bzzr0" | "bzzr1" | "solc" | "experimental")
+}
+
+///
+/// Just enough of a CBOR reader for solc metadata: item headers with short lengths
+///
+struct Cbor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cbor<'a> {
+    /// Major type and length (or simple value) of the next item header
+    fn next(&mut self) -> Option<(u8, usize)> {
+        let initial = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+
+        let major = initial >> 5;
+        let info = (initial & 0x1f) as usize;
+
+        match info {
+            0..=23 => Some((major, info)),
+            24 => {
+                let n = *self.bytes.get(self.pos)? as usize;
+                self.pos += 1;
+                Some((major, n))
+            }
+            _ => None,
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What solc 0.8.19 appends to runtime code: ipfs hash and compiler version
+    const SOLC_0_8_19: &str = "a264697066735822122036a2db1f1d3c8b6a73a5c8b70f3d4e3e4f1b66\
+                               d42c7b8dbe0b1d7f3a1c5a6e2f64736f6c63430008130033";
+
+    fn code(hex: &str) -> Vec<u8> {
+        hex_to_bytes(hex).unwrap()
+    }
+
+    #[test]
+    fn truncated_push() {
+        let disassembly = disassemble(&code("60017faabb"));
+
+        let insts = &disassembly.instructions;
+        assert_eq!(insts.len(), 2);
+        assert!(!insts[0].is_truncated());
+        assert_eq!(insts[1].inst, Some(EvmInst::PUSH32));
+        assert_eq!(insts[1].immediate, vec![0xaa, 0xbb]);
+        assert!(insts[1].is_truncated());
+        assert_eq!(
+            insts[1].to_string(),
+            "000002  PUSH32 0xaabb (truncated, 2 of 32 bytes)"
+        );
+        assert!(disassembly.metadata.is_none());
+    }
+
+    #[test]
+    fn unknown_byte() {
+        let disassembly = disassemble(&code("0c00"));
+
+        let insts = &disassembly.instructions;
+        assert_eq!(insts.len(), 2);
+        assert_eq!((insts[0].opcode, insts[0].inst), (0x0c, None));
+        assert!(!insts[0].is_truncated());
+        assert_eq!(insts[0].to_string(), "000000  UNKNOWN(0x0c)");
+        assert_eq!(insts[1].inst, Some(EvmInst::STOP));
+    }
+
+    #[test]
+    fn solc_metadata() {
+        let runtime = format!("6080604052600080fdfe{}", SOLC_0_8_19);
+        let disassembly = disassemble(&code(&runtime));
+
+        let metadata = disassembly.metadata.expect("metadata");
+        assert_eq!(metadata.offset, 10);
+        assert_eq!(metadata.len, 0x33 + 2);
+        assert_eq!(metadata.keys, vec!["ipfs", "solc"]);
+        assert_eq!(metadata.solc.as_deref(), Some("0.8.19"));
+
+        let ops: Vec<_> = disassembly.instructions.iter().map(|i| i.inst).collect();
+        assert_eq!(
+            ops,
+            vec![
+                Some(EvmInst::PUSH1),
+                Some(EvmInst::PUSH1),
+                Some(EvmInst::MSTORE),
+                Some(EvmInst::PUSH1),
+                Some(EvmInst::DUP1),
+                Some(EvmInst::REVERT),
+                Some(EvmInst::INVALID),
+            ]
+        );
+    }
+
+    #[test]
+    fn push_runs_into_metadata() {
+        let disassembly = disassemble(&code(&format!("6101{}", SOLC_0_8_19)));
+
+        assert_eq!(disassembly.metadata.expect("metadata").offset, 2);
+
+        let insts = &disassembly.instructions;
+        assert_eq!(insts.len(), 1);
+        assert_eq!(insts[0].inst, Some(EvmInst::PUSH2));
+        assert_eq!(insts[0].immediate, vec![0x01, 0xa2]);
+        assert!(!insts[0].is_truncated());
+    }
+
+    #[test]
+    fn length_suffix_without_metadata() {
+        // the last two bytes read as a length of 3, which covers `60 40 52`, not a CBOR map
+        let disassembly = disassemble(&code("60806040520003"));
+
+        assert!(disassembly.metadata.is_none());
+        assert_eq!(disassembly.instructions.len(), 5);
+
+        // a CBOR map, but of nothing solc writes
+        let map = "a1636b6579f50006";
+        assert!(disassemble(&code(map)).metadata.is_none());
+    }
+}
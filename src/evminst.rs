@@ -17,9 +17,15 @@
 //!
 //!
 
+use fork::Fork;
 use std::fmt;
 use std::fmt::Formatter;
 
+///
+/// Every opcode enabled on mainnet through the Osaka fork. Opcodes that were later renamed
+/// (KECCAK256, PREVRANDAO, SELFDESTRUCT) keep their original names, which are also the names
+/// of their columns in counts files.
+///
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy)]
 pub enum EvmInst {
@@ -46,6 +52,10 @@ pub enum EvmInst {
     XOR = 0x18,
     NOT = 0x19,
     BYTE = 0x1a,
+    SHL = 0x1b,
+    SHR = 0x1c,
+    SAR = 0x1d,
+    CLZ = 0x1e,
     SHA3 = 0x20,
     ADDRESS = 0x30,
     BALANCE = 0x31,
@@ -62,12 +72,18 @@ pub enum EvmInst {
     EXTCODECOPY = 0x3c,
     RETURNDATASIZE = 0x3d,
     RETURNDATACOPY = 0x3e,
+    EXTCODEHASH = 0x3f,
     BLOCKHASH = 0x40,
     COINBASE = 0x41,
     TIMESTAMP = 0x42,
     NUMBER = 0x43,
     DIFFICULTY = 0x44,
     GASLIMIT = 0x45,
+    CHAINID = 0x46,
+    SELFBALANCE = 0x47,
+    BASEFEE = 0x48,
+    BLOBHASH = 0x49,
+    BLOBBASEFEE = 0x4a,
     POP = 0x50,
     MLOAD = 0x51,
     MSTORE = 0x52,
@@ -80,6 +96,10 @@ pub enum EvmInst {
     MSIZE = 0x59,
     GAS = 0x5a,
     JUMPDEST = 0x5b,
+    TLOAD = 0x5c,
+    TSTORE = 0x5d,
+    MCOPY = 0x5e,
+    PUSH0 = 0x5f,
     PUSH1 = 0x60,
     PUSH2 = 0x61,
     PUSH3 = 0x62,
@@ -154,9 +174,9 @@ pub enum EvmInst {
     CALLCODE = 0xf2,
     RETURN = 0xf3,
     DELEGATECALL = 0xf4,
+    STATICCALL = 0xfa,
     CREATE2 = 0xfb,
     REVERT = 0xfd,
-    STATICCALL = 0xfa,
     INVALID = 0xfe,
     SUICIDE = 0xff,
 }
@@ -187,6 +207,10 @@ impl EvmInst {
             "XOR" => EvmInst::XOR,
             "NOT" => EvmInst::NOT,
             "BYTE" => EvmInst::BYTE,
+            "SHL" => EvmInst::SHL,
+            "SHR" => EvmInst::SHR,
+            "SAR" => EvmInst::SAR,
+            "CLZ" => EvmInst::CLZ,
             "SHA3" => EvmInst::SHA3,
             "ADDRESS" => EvmInst::ADDRESS,
             "BALANCE" => EvmInst::BALANCE,
@@ -203,12 +227,18 @@ impl EvmInst {
             "EXTCODECOPY" => EvmInst::EXTCODECOPY,
             "RETURNDATASIZE" => EvmInst::RETURNDATASIZE,
             "RETURNDATACOPY" => EvmInst::RETURNDATACOPY,
+            "EXTCODEHASH" => EvmInst::EXTCODEHASH,
             "BLOCKHASH" => EvmInst::BLOCKHASH,
             "COINBASE" => EvmInst::COINBASE,
             "TIMESTAMP" => EvmInst::TIMESTAMP,
             "NUMBER" => EvmInst::NUMBER,
             "DIFFICULTY" => EvmInst::DIFFICULTY,
             "GASLIMIT" => EvmInst::GASLIMIT,
+            "CHAINID" => EvmInst::CHAINID,
+            "SELFBALANCE" => EvmInst::SELFBALANCE,
+            "BASEFEE" => EvmInst::BASEFEE,
+            "BLOBHASH" => EvmInst::BLOBHASH,
+            "BLOBBASEFEE" => EvmInst::BLOBBASEFEE,
             "POP" => EvmInst::POP,
             "MLOAD" => EvmInst::MLOAD,
             "MSTORE" => EvmInst::MSTORE,
@@ -221,6 +251,10 @@ impl EvmInst {
             "MSIZE" => EvmInst::MSIZE,
             "GAS" => EvmInst::GAS,
             "JUMPDEST" => EvmInst::JUMPDEST,
+            "TLOAD" => EvmInst::TLOAD,
+            "TSTORE" => EvmInst::TSTORE,
+            "MCOPY" => EvmInst::MCOPY,
+            "PUSH0" => EvmInst::PUSH0,
             "PUSH1" => EvmInst::PUSH1,
             "PUSH2" => EvmInst::PUSH2,
             "PUSH3" => EvmInst::PUSH3,
@@ -295,11 +329,14 @@ impl EvmInst {
             "CALLCODE" => EvmInst::CALLCODE,
             "RETURN" => EvmInst::RETURN,
             "DELEGATECALL" => EvmInst::DELEGATECALL,
+            "STATICCALL" => EvmInst::STATICCALL,
             "CREATE2" => EvmInst::CREATE2,
             "REVERT" => EvmInst::REVERT,
-            "STATICCALL" => EvmInst::STATICCALL,
             "INVALID" => EvmInst::INVALID,
             "SUICIDE" => EvmInst::SUICIDE,
+            // later names for the same opcodes
+            "KECCAK256" => EvmInst::SHA3,
+            "PREVRANDAO" | "RANDOM" => EvmInst::DIFFICULTY,
             "SELFDESTRUCT" => EvmInst::SUICIDE,
             "Missing opcode 0xfe" => EvmInst::INVALID, // EIP 141
             inst => {
@@ -312,6 +349,88 @@ impl EvmInst {
     pub fn from_opt_str(s: Option<&str>) -> EvmInst {
         EvmInst::from_str(s.expect("missing envinst?"))
     }
+
+    /// The instruction with opcode `op`
+    pub fn from_u8(op: u8) -> EvmInst {
+        match EvmInst::from_opcode(op) {
+            Some(inst) => inst,
+            None => {
+                error!("unknown opcode {:#04x}", op);
+                EvmInst::INVALID
+            }
+        }
+    }
+
+    /// The instruction with opcode `op`, `None` if no fork has enabled one
+    pub fn from_opcode(op: u8) -> Option<EvmInst> {
+        VALUES.iter().find(|inst| **inst as u8 == op).cloned()
+    }
+
+    /// Bytes of code following the opcode that are its argument (PUSHn), not instructions
+    pub fn immediate_size(&self) -> usize {
+        if *self >= EvmInst::PUSH1 && *self <= EvmInst::PUSH32 {
+            (*self as u8 - EvmInst::PUSH0 as u8) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The mainnet fork that enabled this instruction; it executes as INVALID before then
+    pub fn since(&self) -> Fork {
+        match *self {
+            EvmInst::DELEGATECALL => Fork::Homestead,
+            EvmInst::RETURNDATASIZE
+            | EvmInst::RETURNDATACOPY
+            | EvmInst::STATICCALL
+            | EvmInst::REVERT => Fork::Byzantium,
+            EvmInst::SHL
+            | EvmInst::SHR
+            | EvmInst::SAR
+            | EvmInst::EXTCODEHASH
+            | EvmInst::CREATE2 => Fork::Constantinople,
+            EvmInst::CHAINID | EvmInst::SELFBALANCE => Fork::Istanbul,
+            EvmInst::BASEFEE => Fork::London,
+            EvmInst::PUSH0 => Fork::Shanghai,
+            EvmInst::BLOBHASH
+            | EvmInst::BLOBBASEFEE
+            | EvmInst::TLOAD
+            | EvmInst::TSTORE
+            | EvmInst::MCOPY => Fork::Cancun,
+            EvmInst::CLZ => Fork::Osaka,
+            _ => Fork::Frontier,
+        }
+    }
+
+    /// True if this instruction is enabled in `fork`
+    pub fn enabled_in(&self, fork: Fork) -> bool {
+        self.since() <= fork
+    }
+
+    /// Gas, stack and grouping of this instruction under `fork`; `None` before it was enabled
+    pub fn info(&self, fork: Fork) -> Option<InstInfo> {
+        if !self.enabled_in(fork) {
+            return None;
+        }
+
+        let &(repriced, static_gas) = gas_schedule(*self)
+            .iter()
+            .rev()
+            .find(|&&(changed, _)| changed <= fork)
+            .unwrap();
+        let (stack_in, stack_out) = stack_io(*self);
+
+        Some(InstInfo {
+            inst: *self,
+            static_gas,
+            dynamic_gas: has_dynamic_gas(*self, fork),
+            stack_in,
+            stack_out,
+            immediate: self.immediate_size() as u8,
+            category: category(*self),
+            since: self.since(),
+            repriced: if repriced > self.since() { Some(repriced) } else { None },
+        })
+    }
 }
 
 impl fmt::Display for EvmInst {
@@ -345,6 +464,10 @@ pub fn as_str(op: &EvmInst) -> &str {
         EvmInst::XOR => "XOR",
         EvmInst::NOT => "NOT",
         EvmInst::BYTE => "BYTE",
+        EvmInst::SHL => "SHL",
+        EvmInst::SHR => "SHR",
+        EvmInst::SAR => "SAR",
+        EvmInst::CLZ => "CLZ",
         EvmInst::SHA3 => "SHA3",
         EvmInst::ADDRESS => "ADDRESS",
         EvmInst::BALANCE => "BALANCE",
@@ -361,12 +484,18 @@ pub fn as_str(op: &EvmInst) -> &str {
         EvmInst::EXTCODECOPY => "EXTCODECOPY",
         EvmInst::RETURNDATASIZE => "RETURNDATASIZE",
         EvmInst::RETURNDATACOPY => "RETURNDATACOPY",
+        EvmInst::EXTCODEHASH => "EXTCODEHASH",
         EvmInst::BLOCKHASH => "BLOCKHASH",
         EvmInst::COINBASE => "COINBASE",
         EvmInst::TIMESTAMP => "TIMESTAMP",
         EvmInst::NUMBER => "NUMBER",
         EvmInst::DIFFICULTY => "DIFFICULTY",
         EvmInst::GASLIMIT => "GASLIMIT",
+        EvmInst::CHAINID => "CHAINID",
+        EvmInst::SELFBALANCE => "SELFBALANCE",
+        EvmInst::BASEFEE => "BASEFEE",
+        EvmInst::BLOBHASH => "BLOBHASH",
+        EvmInst::BLOBBASEFEE => "BLOBBASEFEE",
         EvmInst::POP => "POP",
         EvmInst::MLOAD => "MLOAD",
         EvmInst::MSTORE => "MSTORE",
@@ -379,6 +508,10 @@ pub fn as_str(op: &EvmInst) -> &str {
         EvmInst::MSIZE => "MSIZE",
         EvmInst::GAS => "GAS",
         EvmInst::JUMPDEST => "JUMPDEST",
+        EvmInst::TLOAD => "TLOAD",
+        EvmInst::TSTORE => "TSTORE",
+        EvmInst::MCOPY => "MCOPY",
+        EvmInst::PUSH0 => "PUSH0",
         EvmInst::PUSH1 => "PUSH1",
         EvmInst::PUSH2 => "PUSH2",
         EvmInst::PUSH3 => "PUSH3",
@@ -453,15 +586,18 @@ pub fn as_str(op: &EvmInst) -> &str {
         EvmInst::CALLCODE => "CALLCODE",
         EvmInst::RETURN => "RETURN",
         EvmInst::DELEGATECALL => "DELEGATECALL",
+        EvmInst::STATICCALL => "STATICCALL",
         EvmInst::CREATE2 => "CREATE2",
         EvmInst::REVERT => "REVERT",
         EvmInst::INVALID => "INVALID",
-        EvmInst::STATICCALL => "STATICCALL",
         EvmInst::SUICIDE => "SUICIDE",
     }
 }
 
-pub static VALUES: [EvmInst; 136] = [
+/// Number of instructions in `VALUES`
+pub const INST_COUNT: usize = 150;
+
+pub static VALUES: [EvmInst; INST_COUNT] = [
     EvmInst::STOP,
     EvmInst::ADD,
     EvmInst::MUL,
@@ -485,6 +621,10 @@ pub static VALUES: [EvmInst; 136] = [
     EvmInst::XOR,
     EvmInst::NOT,
     EvmInst::BYTE,
+    EvmInst::SHL,
+    EvmInst::SHR,
+    EvmInst::SAR,
+    EvmInst::CLZ,
     EvmInst::SHA3,
     EvmInst::ADDRESS,
     EvmInst::BALANCE,
@@ -501,12 +641,18 @@ pub static VALUES: [EvmInst; 136] = [
     EvmInst::EXTCODECOPY,
     EvmInst::RETURNDATASIZE,
     EvmInst::RETURNDATACOPY,
+    EvmInst::EXTCODEHASH,
     EvmInst::BLOCKHASH,
     EvmInst::COINBASE,
     EvmInst::TIMESTAMP,
     EvmInst::NUMBER,
     EvmInst::DIFFICULTY,
     EvmInst::GASLIMIT,
+    EvmInst::CHAINID,
+    EvmInst::SELFBALANCE,
+    EvmInst::BASEFEE,
+    EvmInst::BLOBHASH,
+    EvmInst::BLOBBASEFEE,
     EvmInst::POP,
     EvmInst::MLOAD,
     EvmInst::MSTORE,
@@ -519,6 +665,10 @@ pub static VALUES: [EvmInst; 136] = [
     EvmInst::MSIZE,
     EvmInst::GAS,
     EvmInst::JUMPDEST,
+    EvmInst::TLOAD,
+    EvmInst::TSTORE,
+    EvmInst::MCOPY,
+    EvmInst::PUSH0,
     EvmInst::PUSH1,
     EvmInst::PUSH2,
     EvmInst::PUSH3,
@@ -593,9 +743,350 @@ pub static VALUES: [EvmInst; 136] = [
     EvmInst::CALLCODE,
     EvmInst::RETURN,
     EvmInst::DELEGATECALL,
+    EvmInst::STATICCALL,
     EvmInst::CREATE2,
     EvmInst::REVERT,
-    EvmInst::STATICCALL,
     EvmInst::INVALID,
     EvmInst::SUICIDE,
 ];
+
+/// What an instruction works on, for grouping instructions in reports
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Category {
+    Arithmetic,
+    Comparison,
+    Bitwise,
+    Keccak,
+    /// The executing account, its caller and call data, other accounts' code and balance
+    Environment,
+    /// The block being executed
+    Block,
+    /// POP, PUSHn, DUPn and SWAPn
+    Stack,
+    Memory,
+    /// Persistent and transient storage
+    Storage,
+    Flow,
+    Log,
+    Call,
+    Create,
+    /// Ends execution of the call frame
+    Halt,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// What an instruction costs and does under one fork, see `EvmInst::info`
+///
+#[derive(Debug, Clone)]
+pub struct InstInfo {
+    pub inst: EvmInst,
+
+    /// Gas charged on every execution. Where an access list (EIP-2929) decides the cost this
+    /// is the warm cost, and cold access is part of the dynamic gas.
+    pub static_gas: u64,
+
+    /// True if it can be charged more than `static_gas`: memory expansion, copied or hashed
+    /// words, cold access, value transfer, account creation, ...
+    pub dynamic_gas: bool,
+
+    /// Stack items taken and pushed
+    pub stack_in: u8,
+    pub stack_out: u8,
+
+    /// Push data bytes, see `EvmInst::immediate_size`
+    pub immediate: u8,
+
+    pub category: Category,
+
+    /// The fork that enabled it
+    pub since: Fork,
+
+    /// The latest fork after `since`, up to the one asked about, that changed its gas
+    pub repriced: Option<Fork>,
+}
+
+///
+/// Static gas of `inst` from the fork that enabled it, followed by every later fork that
+/// changed its gas. A fork that changed only the dynamic part (or refunds) repeats the
+/// static gas.
+///
+fn gas_schedule(inst: EvmInst) -> &'static [(Fork, u64)] {
+    use self::EvmInst::*;
+    use fork::Fork::*;
+
+    match inst {
+        STOP | RETURN | REVERT | INVALID => &[(Frontier, 0)],
+        JUMPDEST => &[(Frontier, 1)],
+        ADDRESS | ORIGIN | CALLER | CALLVALUE | CALLDATASIZE | CODESIZE | GASPRICE
+        | RETURNDATASIZE | COINBASE | TIMESTAMP | NUMBER | DIFFICULTY | GASLIMIT | CHAINID
+        | BASEFEE | BLOBBASEFEE | POP | PC | MSIZE | GAS | PUSH0 => &[(Frontier, 2)],
+        SELFBALANCE => &[(Istanbul, 5)],
+        MUL | DIV | SDIV | MOD | SMOD | SIGNEXTEND | CLZ => &[(Frontier, 5)],
+        JUMP => &[(Frontier, 8)],
+        ADDMOD | MULMOD => &[(Frontier, 8)],
+        JUMPI => &[(Frontier, 10)],
+        // EIP-160 raised the cost per exponent byte
+        EXP => &[(Frontier, 10), (SpuriousDragon, 10)],
+        BLOCKHASH => &[(Frontier, 20)],
+        SHA3 => &[(Frontier, 30)],
+        TLOAD | TSTORE => &[(Cancun, 100)],
+        // EIP-150, then EIP-1884, then EIP-2929 with cold access made dynamic
+        BALANCE => &[(Frontier, 20), (TangerineWhistle, 400), (Istanbul, 700), (Berlin, 100)],
+        SLOAD => &[(Frontier, 50), (TangerineWhistle, 200), (Istanbul, 800), (Berlin, 100)],
+        EXTCODESIZE | EXTCODECOPY => &[(Frontier, 20), (TangerineWhistle, 700), (Berlin, 100)],
+        EXTCODEHASH => &[(Constantinople, 400), (Istanbul, 700), (Berlin, 100)],
+        CALL | CALLCODE => &[(Frontier, 40), (TangerineWhistle, 700), (Berlin, 100)],
+        DELEGATECALL => &[(Homestead, 40), (TangerineWhistle, 700), (Berlin, 100)],
+        STATICCALL => &[(Byzantium, 700), (Berlin, 100)],
+        // the cheapest case: EIP-2200 (Istanbul) charges a no-op store like SLOAD, EIP-2929
+        // (Berlin) like a warm SLOAD; EIP-3529 (London) lowered refunds
+        SSTORE => &[(Frontier, 5000), (Istanbul, 800), (Berlin, 100), (London, 100)],
+        // EIP-150 made it cost 5,000, EIP-2929 added cold access, EIP-3529 removed its refund
+        SUICIDE => &[
+            (Frontier, 0),
+            (TangerineWhistle, 5000),
+            (Berlin, 5000),
+            (London, 5000),
+        ],
+        // EIP-3860 charges for init code
+        CREATE => &[(Frontier, 32000), (Shanghai, 32000)],
+        CREATE2 => &[(Constantinople, 32000), (Shanghai, 32000)],
+        LOG0 => &[(Frontier, 375)],
+        LOG1 => &[(Frontier, 750)],
+        LOG2 => &[(Frontier, 1125)],
+        LOG3 => &[(Frontier, 1500)],
+        LOG4 => &[(Frontier, 1875)],
+        // ADD, SUB, comparisons, bitwise, CALLDATALOAD, *COPY, BLOBHASH, memory, PUSHn, DUPn,
+        // SWAPn
+        _ => &[(Frontier, 3)],
+    }
+}
+
+/// True if `inst` can cost more than its static gas under `fork`
+fn has_dynamic_gas(inst: EvmInst, fork: Fork) -> bool {
+    use self::EvmInst::*;
+
+    match inst {
+        EXP | SHA3 | CALLDATACOPY | CODECOPY | EXTCODECOPY | RETURNDATACOPY | MLOAD | MSTORE
+        | MSTORE8 | MCOPY | SSTORE | LOG0 | LOG1 | LOG2 | LOG3 | LOG4 | CREATE | CALL
+        | CALLCODE | RETURN | DELEGATECALL | CREATE2 | STATICCALL | REVERT => true,
+        // cold access, EIP-2929
+        BALANCE | EXTCODESIZE | EXTCODEHASH | SLOAD => fork >= Fork::Berlin,
+        // creating the beneficiary, EIP-150
+        SUICIDE => fork >= Fork::TangerineWhistle,
+        _ => false,
+    }
+}
+
+/// Stack items taken and pushed by `inst`
+fn stack_io(inst: EvmInst) -> (u8, u8) {
+    use self::EvmInst::*;
+
+    let op = inst as u8;
+    match inst {
+        STOP | JUMPDEST | INVALID => (0, 0),
+        ADD | MUL | SUB | DIV | SDIV | MOD | SMOD | EXP | SIGNEXTEND | LT | GT | SLT | SGT
+        | EQ | AND | OR | XOR | BYTE | SHL | SHR | SAR | SHA3 => (2, 1),
+        ADDMOD | MULMOD => (3, 1),
+        ISZERO | NOT | CLZ | BALANCE | CALLDATALOAD | EXTCODESIZE | EXTCODEHASH | BLOCKHASH
+        | BLOBHASH | MLOAD | SLOAD | TLOAD => (1, 1),
+        CALLDATACOPY | CODECOPY | RETURNDATACOPY | MCOPY => (3, 0),
+        EXTCODECOPY => (4, 0),
+        POP | JUMP | SUICIDE => (1, 0),
+        MSTORE | MSTORE8 | SSTORE | TSTORE | JUMPI | RETURN | REVERT => (2, 0),
+        CREATE => (3, 1),
+        CREATE2 => (4, 1),
+        CALL | CALLCODE => (7, 1),
+        DELEGATECALL | STATICCALL => (6, 1),
+        _ if inst >= DUP1 && inst <= DUP16 => {
+            let n = op - DUP1 as u8 + 1;
+            (n, n + 1)
+        }
+        _ if inst >= SWAP1 && inst <= SWAP16 => {
+            let n = op - SWAP1 as u8 + 2;
+            (n, n)
+        }
+        _ if inst >= LOG0 && inst <= LOG4 => (op - LOG0 as u8 + 2, 0),
+        // ADDRESS, CALLER, block values, PC, MSIZE, GAS, PUSHn, ...
+        _ => (0, 1),
+    }
+}
+
+fn category(inst: EvmInst) -> Category {
+    use self::EvmInst::*;
+
+    match inst {
+        ADD | MUL | SUB | DIV | SDIV | MOD | SMOD | ADDMOD | MULMOD | EXP | SIGNEXTEND => {
+            Category::Arithmetic
+        }
+        LT | GT | SLT | SGT | EQ | ISZERO => Category::Comparison,
+        AND | OR | XOR | NOT | BYTE | SHL | SHR | SAR | CLZ => Category::Bitwise,
+        SHA3 => Category::Keccak,
+        BLOCKHASH | COINBASE | TIMESTAMP | NUMBER | DIFFICULTY | GASLIMIT | CHAINID | BASEFEE
+        | BLOBHASH | BLOBBASEFEE => Category::Block,
+        MLOAD | MSTORE | MSTORE8 | MSIZE | MCOPY => Category::Memory,
+        SLOAD | SSTORE | TLOAD | TSTORE => Category::Storage,
+        JUMP | JUMPI | PC | JUMPDEST => Category::Flow,
+        CALL | CALLCODE | DELEGATECALL | STATICCALL => Category::Call,
+        CREATE | CREATE2 => Category::Create,
+        STOP | RETURN | REVERT | INVALID | SUICIDE => Category::Halt,
+        _ if inst >= LOG0 && inst <= LOG4 => Category::Log,
+        _ if inst == POP || (inst >= PUSH0 && inst <= SWAP16) => Category::Stack,
+        // ADDRESS through EXTCODEHASH, SELFBALANCE, GAS
+        _ => Category::Environment,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let mut known = 0;
+
+        for op in 0..=255u8 {
+            match EvmInst::from_opcode(op) {
+                Some(inst) => {
+                    known += 1;
+                    assert_eq!(inst as u8, op);
+                    assert_eq!(EvmInst::from_str(as_str(&inst)), inst);
+                    assert_eq!(EvmInst::from_u8(op), inst);
+                    assert!(VALUES.contains(&inst));
+                }
+                None => assert_eq!(EvmInst::from_u8(op), EvmInst::INVALID, "{:#04x}", op),
+            }
+        }
+
+        assert_eq!(known, INST_COUNT);
+        assert_eq!(EvmInst::from_opcode(0x0c), None);
+        assert_eq!(EvmInst::from_opcode(0x1e), Some(EvmInst::CLZ));
+        assert_eq!(EvmInst::from_opcode(0xfe), Some(EvmInst::INVALID));
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(EvmInst::from_str("KECCAK256"), EvmInst::SHA3);
+        assert_eq!(EvmInst::from_str("PREVRANDAO"), EvmInst::DIFFICULTY);
+        assert_eq!(EvmInst::from_str("RANDOM"), EvmInst::DIFFICULTY);
+        assert_eq!(EvmInst::from_str("SELFDESTRUCT"), EvmInst::SUICIDE);
+        assert_eq!(EvmInst::from_str("Missing opcode 0xfe"), EvmInst::INVALID);
+
+        // the names written to counts files stay the old ones
+        assert_eq!(as_str(&EvmInst::SHA3), "SHA3");
+        assert_eq!(as_str(&EvmInst::DIFFICULTY), "DIFFICULTY");
+        assert_eq!(as_str(&EvmInst::SUICIDE), "SUICIDE");
+    }
+
+    #[test]
+    fn immediate_size() {
+        assert_eq!(EvmInst::PUSH0.immediate_size(), 0);
+        assert_eq!(EvmInst::PUSH1.immediate_size(), 1);
+        assert_eq!(EvmInst::PUSH20.immediate_size(), 20);
+        assert_eq!(EvmInst::PUSH32.immediate_size(), 32);
+        assert_eq!(EvmInst::DUP1.immediate_size(), 0);
+        assert_eq!(EvmInst::JUMPDEST.immediate_size(), 0);
+
+        let pushes = VALUES.iter().filter(|inst| inst.immediate_size() > 0).count();
+        assert_eq!(pushes, 32);
+    }
+
+    #[test]
+    fn since() {
+        let enabled = [
+            (EvmInst::ADD, Fork::Frontier),
+            (EvmInst::DELEGATECALL, Fork::Homestead),
+            (EvmInst::REVERT, Fork::Byzantium),
+            (EvmInst::SHL, Fork::Constantinople),
+            (EvmInst::CREATE2, Fork::Constantinople),
+            (EvmInst::CHAINID, Fork::Istanbul),
+            (EvmInst::SELFBALANCE, Fork::Istanbul),
+            (EvmInst::BASEFEE, Fork::London),
+            (EvmInst::PUSH0, Fork::Shanghai),
+            (EvmInst::TLOAD, Fork::Cancun),
+            (EvmInst::MCOPY, Fork::Cancun),
+            (EvmInst::BLOBBASEFEE, Fork::Cancun),
+            (EvmInst::CLZ, Fork::Osaka),
+        ];
+
+        for &(inst, fork) in enabled.iter() {
+            assert_eq!(inst.since(), fork, "{}", inst);
+            assert!(inst.enabled_in(fork));
+        }
+
+        assert!(!EvmInst::PUSH0.enabled_in(Fork::Paris));
+        assert!(!EvmInst::CLZ.enabled_in(Fork::Prague));
+    }
+
+    #[test]
+    fn repricing() {
+        // (instruction, fork, static gas, dynamic gas, latest repricing)
+        let table = [
+            (EvmInst::SLOAD, Fork::Frontier, 50, false, None),
+            (EvmInst::SLOAD, Fork::Homestead, 50, false, None),
+            // EIP-150
+            (EvmInst::SLOAD, Fork::TangerineWhistle, 200, false, Some(Fork::TangerineWhistle)),
+            (EvmInst::SLOAD, Fork::Constantinople, 200, false, Some(Fork::TangerineWhistle)),
+            // EIP-1884
+            (EvmInst::SLOAD, Fork::Istanbul, 800, false, Some(Fork::Istanbul)),
+            (EvmInst::BALANCE, Fork::TangerineWhistle, 400, false, Some(Fork::TangerineWhistle)),
+            (EvmInst::BALANCE, Fork::Istanbul, 700, false, Some(Fork::Istanbul)),
+            // EIP-2929, cold access is dynamic
+            (EvmInst::SLOAD, Fork::Berlin, 100, true, Some(Fork::Berlin)),
+            (EvmInst::SLOAD, Fork::Prague, 100, true, Some(Fork::Berlin)),
+            (EvmInst::BALANCE, Fork::Berlin, 100, true, Some(Fork::Berlin)),
+            (EvmInst::EXTCODEHASH, Fork::Constantinople, 400, false, None),
+            (EvmInst::EXTCODEHASH, Fork::Berlin, 100, true, Some(Fork::Berlin)),
+            (EvmInst::CALL, Fork::Frontier, 40, true, None),
+            (EvmInst::CALL, Fork::Berlin, 100, true, Some(Fork::Berlin)),
+            (EvmInst::SUICIDE, Fork::Frontier, 0, false, None),
+            (EvmInst::SUICIDE, Fork::TangerineWhistle, 5000, true, Some(Fork::TangerineWhistle)),
+            (EvmInst::SSTORE, Fork::London, 100, true, Some(Fork::London)),
+            (EvmInst::ADD, Fork::Osaka, 3, false, None),
+            (EvmInst::TLOAD, Fork::Cancun, 100, false, None),
+        ];
+
+        for &(inst, fork, static_gas, dynamic_gas, repriced) in table.iter() {
+            let info = inst.info(fork).unwrap();
+            assert_eq!(info.static_gas, static_gas, "{} in {:?}", inst, fork);
+            assert_eq!(info.dynamic_gas, dynamic_gas, "{} in {:?}", inst, fork);
+            assert_eq!(info.repriced, repriced, "{} in {:?}", inst, fork);
+        }
+    }
+
+    #[test]
+    fn no_info_before_enabled() {
+        for inst in VALUES.iter() {
+            let since = inst.since();
+            assert_eq!(inst.info(since).unwrap().since, since);
+
+            if since > Fork::Frontier {
+                assert!(inst.info(Fork::Frontier).is_none(), "{}", inst);
+            }
+        }
+
+        assert!(EvmInst::PUSH0.info(Fork::Paris).is_none());
+        assert!(EvmInst::CLZ.info(Fork::Prague).is_none());
+        assert!(EvmInst::CLZ.info(Fork::Osaka).is_some());
+    }
+
+    #[test]
+    fn gas_schedules_cover_enabled_forks() {
+        // instructions sharing a price with older ones may share their Frontier entry
+        for inst in VALUES.iter() {
+            let schedule = gas_schedule(*inst);
+            assert!(schedule[0].0 <= inst.since(), "{}", inst);
+            assert!(
+                schedule.windows(2).all(|pair| pair[0].0 < pair[1].0),
+                "{} out of order",
+                inst
+            );
+        }
+    }
+}
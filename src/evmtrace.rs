@@ -13,8 +13,12 @@
 // limitations under the License.
 
 use csv::ByteRecord;
+use evminst;
+use std::collections::HashMap;
+use std::fmt;
 use std::str;
 
+// The leading columns are the same in every counts file
 pub const TS_IDX: usize = 0;
 pub const BLOCK_NUM_IDX: usize = 1;
 pub const TXN_INDEX_IDX: usize = 2;
@@ -78,6 +82,14 @@ pub struct EvmTrace {
     pub NOT_gas: u32,
     pub BYTE_count: u16,
     pub BYTE_gas: u32,
+    pub SHL_count: u16,
+    pub SHL_gas: u32,
+    pub SHR_count: u16,
+    pub SHR_gas: u32,
+    pub SAR_count: u16,
+    pub SAR_gas: u32,
+    pub CLZ_count: u16,
+    pub CLZ_gas: u32,
     pub SHA3_count: u16,
     pub SHA3_gas: u32,
     pub ADDRESS_count: u16,
@@ -110,6 +122,8 @@ pub struct EvmTrace {
     pub RETURNDATASIZE_gas: u32,
     pub RETURNDATACOPY_count: u16,
     pub RETURNDATACOPY_gas: u32,
+    pub EXTCODEHASH_count: u16,
+    pub EXTCODEHASH_gas: u32,
     pub BLOCKHASH_count: u16,
     pub BLOCKHASH_gas: u32,
     pub COINBASE_count: u16,
@@ -122,6 +136,16 @@ pub struct EvmTrace {
     pub DIFFICULTY_gas: u32,
     pub GASLIMIT_count: u16,
     pub GASLIMIT_gas: u32,
+    pub CHAINID_count: u16,
+    pub CHAINID_gas: u32,
+    pub SELFBALANCE_count: u16,
+    pub SELFBALANCE_gas: u32,
+    pub BASEFEE_count: u16,
+    pub BASEFEE_gas: u32,
+    pub BLOBHASH_count: u16,
+    pub BLOBHASH_gas: u32,
+    pub BLOBBASEFEE_count: u16,
+    pub BLOBBASEFEE_gas: u32,
     pub POP_count: u16,
     pub POP_gas: u32,
     pub MLOAD_count: u16,
@@ -146,6 +170,14 @@ pub struct EvmTrace {
     pub GAS_gas: u32,
     pub JUMPDEST_count: u16,
     pub JUMPDEST_gas: u32,
+    pub TLOAD_count: u16,
+    pub TLOAD_gas: u32,
+    pub TSTORE_count: u16,
+    pub TSTORE_gas: u32,
+    pub MCOPY_count: u16,
+    pub MCOPY_gas: u32,
+    pub PUSH0_count: u16,
+    pub PUSH0_gas: u32,
     pub PUSH1_count: u16,
     pub PUSH1_gas: u32,
     pub PUSH2_count: u16,
@@ -294,16 +326,61 @@ pub struct EvmTrace {
     pub RETURN_gas: u32,
     pub DELEGATECALL_count: u16,
     pub DELEGATECALL_gas: u32,
+    pub STATICCALL_count: u16,
+    pub STATICCALL_gas: u32,
     pub CREATE2_count: u16,
     pub CREATE2_gas: u32,
     pub REVERT_count: u16,
     pub REVERT_gas: u32,
-    pub STATICCALL_count: u16,
-    pub STATICCALL_gas: u32,
     pub INVALID_count: u16,
     pub INVALID_gas: u32,
     pub SUICIDE_count: u16,
     pub SUICIDE_gas: u32,
+    pub txn_type: u8,
+    pub max_fee: Option<u64>,
+    pub max_prio_fee: Option<u64>,
+    pub eff_gas_px: u64,
+    pub base_fee: Option<u64>,
+    pub status: Option<u8>,
+    pub gas_used: Option<u64>,
+    pub cum_gas_used: Option<u64>,
+    pub log_count: Option<u32>,
+    pub intrinsic_gas: u64,
+    pub exec_gas: u64,
+    pub refund: u64,
+    pub gas_delta: Option<i64>,
+}
+
+/// Fee columns of a trace record, see `get_fee_fields`
+#[derive(Debug, Clone)]
+pub struct FeeFields {
+    pub txn_type: u8,
+    pub max_fee: Option<u64>,
+    pub max_prio_fee: Option<u64>,
+    pub eff_gas_px: u64,
+    pub base_fee: Option<u64>,
+}
+
+/// Receipt columns of a trace record, see `get_receipt_fields`
+#[derive(Debug, Clone)]
+pub struct ReceiptFields {
+    /// 1 for success, 0 for reverted; `None` before Byzantium
+    pub status: Option<u8>,
+    pub gas_used: u64,
+    pub cum_gas_used: u64,
+    pub log_count: u32,
+}
+
+/// Gas accounting columns of a trace record, see `get_gas_fields`
+#[derive(Debug, Clone)]
+pub struct GasFields {
+    pub intrinsic_gas: u64,
+    pub exec_gas: u64,
+    pub refund: u64,
+
+    /// Receipt gasUsed less the accounted gas; `None` without a receipt. For a successful
+    /// contract creation it's the code deposit cost, see `gasacct::GasAccount::delta`.
+    pub gas_delta: Option<i64>,
 }
 
 pub fn get_field_u16(record: &ByteRecord, idx: usize) -> u16 {
@@ -321,21 +398,202 @@ pub fn get_field_u64(record: &ByteRecord, idx: usize) -> u64 {
     unsafe { str::from_utf8_unchecked(tmp).parse().unwrap() }
 }
 
+/// An optional numeric field, empty when absent
+pub fn get_field_opt_u64(record: &ByteRecord, idx: usize) -> Option<u64> {
+    let tmp = record.get(idx).unwrap();
+    if tmp.is_empty() {
+        None
+    } else {
+        Some(unsafe { str::from_utf8_unchecked(tmp).parse().unwrap() })
+    }
+}
+
 pub fn get_field_str(record: &ByteRecord, idx: usize) -> &str {
     let tmp = record.get(idx).unwrap();
     unsafe { str::from_utf8_unchecked(tmp) }
 }
 
-const FIELD_OFFSET: usize = 6;
+///
+/// Where the columns of a counts file are, found by name in its header row. Files written
+/// before an instruction or a group of columns was added simply don't have those columns.
+///
+#[derive(Debug, Clone)]
+pub struct CountsLayout {
+    /// The `{OP}_count` and `{OP}_gas` columns of each instruction in `VALUES`
+    insts: Vec<Option<(usize, usize)>>,
+
+    /// The fee, receipt and gas accounting columns, in the order of `FEE_COLUMNS`,
+    /// `RECEIPT_COLUMNS` and `GAS_COLUMNS`; `None` unless the header has the whole group
+    fees: Option<Vec<usize>>,
+    receipts: Option<Vec<usize>>,
+    gas: Option<Vec<usize>>,
+}
+
+const FEE_COLUMNS: [&str; 5] = ["txn_type", "max_fee", "max_prio_fee", "eff_gas_px", "base_fee"];
+const RECEIPT_COLUMNS: [&str; 4] = ["status", "gas_used", "cum_gas_used", "log_count"];
+const GAS_COLUMNS: [&str; 4] = ["intrinsic_gas", "exec_gas", "refund", "gas_delta"];
+
+impl CountsLayout {
+    pub fn from_header(header: &ByteRecord) -> Self {
+        let columns: HashMap<&[u8], usize> =
+            header.iter().enumerate().map(|(idx, name)| (name, idx)).collect();
+        let column = |name: &str| columns.get(name.as_bytes()).cloned();
+        let group = |names: &[&str]| names.iter().map(|name| column(name)).collect();
 
-pub fn get_inst_fields(record: &ByteRecord, op_idx: usize) -> (u32, u64) {
-    let idx = FIELD_OFFSET + (op_idx * 2);
+        CountsLayout {
+            insts: evminst::VALUES
+                .iter()
+                .map(|inst| {
+                    let op = evminst::as_str(inst);
+                    let count = column(&format!("{}_count", op))?;
+                    let gas = column(&format!("{}_gas", op))?;
+                    Some((count, gas))
+                })
+                .collect(),
+            fees: group(&FEE_COLUMNS),
+            receipts: group(&RECEIPT_COLUMNS),
+            gas: group(&GAS_COLUMNS),
+        }
+    }
+}
+
+/// The fields of `record` in `columns`, or `None` without the columns or if the record is
+/// too short to have them
+fn get_fields<'r>(record: &'r ByteRecord, columns: &Option<Vec<usize>>) -> Option<Vec<&'r str>> {
+    columns
+        .as_ref()?
+        .iter()
+        .map(|&idx| record.get(idx).map(|tmp| unsafe { str::from_utf8_unchecked(tmp) }))
+        .collect()
+}
+
+/// An optional numeric field, empty when absent
+fn parse_opt<T: str::FromStr>(field: &str) -> Option<T>
+where
+    T::Err: fmt::Debug,
+{
+    if field.is_empty() {
+        None
+    } else {
+        Some(field.parse().unwrap())
+    }
+}
 
-    let count_tmp = record.get(idx).unwrap();
-    let gas_tmp = record.get(idx + 1).unwrap();
+pub fn get_inst_fields(record: &ByteRecord, layout: &CountsLayout, op_idx: usize) -> (u32, u64) {
+    let (count_idx, gas_idx) = match layout.insts[op_idx] {
+        Some(idx) => idx,
+        None => return (0, 0),
+    };
+
+    let (count_tmp, gas_tmp) = match (record.get(count_idx), record.get(gas_idx)) {
+        (Some(count_tmp), Some(gas_tmp)) => (count_tmp, gas_tmp),
+        _ => return (0, 0),
+    };
 
     let count: u32 = unsafe { str::from_utf8_unchecked(count_tmp).parse().unwrap() };
     let gas: u64 = unsafe { str::from_utf8_unchecked(gas_tmp).parse().unwrap() };
 
     (count, gas)
 }
+
+/// The fee columns, or `None` for files written before they existed
+pub fn get_fee_fields(record: &ByteRecord, layout: &CountsLayout) -> Option<FeeFields> {
+    let fields = get_fields(record, &layout.fees)?;
+
+    Some(FeeFields {
+        txn_type: fields[0].parse().unwrap(),
+        max_fee: parse_opt(fields[1]),
+        max_prio_fee: parse_opt(fields[2]),
+        eff_gas_px: fields[3].parse().unwrap(),
+        base_fee: parse_opt(fields[4]),
+    })
+}
+
+/// The receipt columns, or `None` for files written before they existed or records without
+/// a receipt
+pub fn get_receipt_fields(record: &ByteRecord, layout: &CountsLayout) -> Option<ReceiptFields> {
+    let fields = get_fields(record, &layout.receipts)?;
+
+    Some(ReceiptFields {
+        status: parse_opt(fields[0]),
+        gas_used: parse_opt(fields[1])?,
+        cum_gas_used: fields[2].parse().unwrap(),
+        log_count: fields[3].parse().unwrap(),
+    })
+}
+
+/// The gas accounting columns, or `None` for files written before they existed or on a
+/// chain extracted without gas accounting
+pub fn get_gas_fields(record: &ByteRecord, layout: &CountsLayout) -> Option<GasFields> {
+    let fields = get_fields(record, &layout.gas)?;
+
+    Some(GasFields {
+        intrinsic_gas: parse_opt(fields[0])?,
+        exec_gas: fields[1].parse().unwrap(),
+        refund: fields[2].parse().unwrap(),
+        gas_delta: parse_opt(fields[3]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evminst::EvmInst;
+
+    fn record(fields: &str) -> ByteRecord {
+        ByteRecord::from(fields.split(',').collect::<Vec<_>>())
+    }
+
+    fn op_idx(inst: EvmInst) -> usize {
+        evminst::VALUES.iter().position(|&op| op == inst).unwrap()
+    }
+
+    #[test]
+    fn old_layout() {
+        // opcode columns only, and none for instructions added since
+        let header = record(
+            "ts,block_num,txn_index,addr_from,addr_to,gas_px,\
+             STOP_count,STOP_gas,ADD_count,ADD_gas,SHA3_count,SHA3_gas,SUICIDE_count,SUICIDE_gas",
+        );
+        let layout = CountsLayout::from_header(&header);
+        let trace = record("1530000000,5900000,3,0xaa,0xbb,20000000000,1,0,4,12,2,84,0,0");
+
+        assert_eq!(get_inst_fields(&trace, &layout, op_idx(EvmInst::ADD)), (4, 12));
+        assert_eq!(get_inst_fields(&trace, &layout, op_idx(EvmInst::SHA3)), (2, 84));
+        assert_eq!(get_inst_fields(&trace, &layout, op_idx(EvmInst::PUSH0)), (0, 0));
+        assert_eq!(get_inst_fields(&trace, &layout, op_idx(EvmInst::TLOAD)), (0, 0));
+
+        assert!(get_fee_fields(&trace, &layout).is_none());
+        assert!(get_receipt_fields(&trace, &layout).is_none());
+        assert!(get_gas_fields(&trace, &layout).is_none());
+    }
+
+    #[test]
+    fn columns_found_by_name() {
+        // a group is only used when all of it is there, wherever it is
+        let header = record(
+            "ts,block_num,txn_index,addr_from,addr_to,gas_px,\
+             status,log_count,cum_gas_used,gas_used,TLOAD_gas,TLOAD_count,\
+             txn_type,max_fee,max_prio_fee,eff_gas_px,intrinsic_gas,exec_gas,refund",
+        );
+        let layout = CountsLayout::from_header(&header);
+        let trace = record("1,2,3,0xaa,0xbb,7,1,2,50000,30000,300,3,0,,,7,21000,9000,0");
+
+        assert_eq!(get_inst_fields(&trace, &layout, op_idx(EvmInst::TLOAD)), (3, 300));
+
+        let receipt = get_receipt_fields(&trace, &layout).unwrap();
+        assert_eq!(receipt.status, Some(1));
+        assert_eq!(receipt.gas_used, 30_000);
+        assert_eq!(receipt.cum_gas_used, 50_000);
+        assert_eq!(receipt.log_count, 2);
+
+        // no base_fee or gas_delta column
+        assert!(get_fee_fields(&trace, &layout).is_none());
+        assert!(get_gas_fields(&trace, &layout).is_none());
+
+        // a short record doesn't have the columns either
+        let short = record("1,2,3,0xaa,0xbb,7,1,2");
+        assert_eq!(get_inst_fields(&short, &layout, op_idx(EvmInst::TLOAD)), (0, 0));
+        assert!(get_receipt_fields(&short, &layout).is_none());
+    }
+}
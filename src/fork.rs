@@ -0,0 +1,241 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Ethereum hard forks, for rules that changed over time (gas schedule, refunds), and when
+//! mainnet and the public testnets activated them
+//!
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+/// Mainnet hard forks that changed EVM or gas rules, in activation order
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Fork {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    /// Constantinople and Petersburg activated together on mainnet
+    Constantinople,
+    Istanbul,
+    Berlin,
+    London,
+    /// The merge
+    Paris,
+    Shanghai,
+    Cancun,
+    Prague,
+    Osaka,
+}
+
+/// When a chain's forks activated: forks through the merge by block number, later ones by
+/// block timestamp
+#[derive(Debug)]
+pub struct ForkSchedule {
+    by_block: &'static [(u64, Fork)],
+    by_time: &'static [(u64, Fork)],
+}
+
+pub static MAINNET: ForkSchedule = ForkSchedule {
+    by_block: &[
+        (1_150_000, Fork::Homestead),
+        (2_463_000, Fork::TangerineWhistle),
+        (2_675_000, Fork::SpuriousDragon),
+        (4_370_000, Fork::Byzantium),
+        (7_280_000, Fork::Constantinople),
+        (9_069_000, Fork::Istanbul),
+        (12_244_000, Fork::Berlin),
+        (12_965_000, Fork::London),
+        (15_537_394, Fork::Paris),
+    ],
+    by_time: &[
+        (1_681_338_455, Fork::Shanghai),
+        (1_710_338_135, Fork::Cancun),
+        (1_746_612_311, Fork::Prague),
+        (1_764_798_551, Fork::Osaka),
+    ],
+};
+
+/// Started with every fork through London
+pub static SEPOLIA: ForkSchedule = ForkSchedule {
+    by_block: &[(0, Fork::London), (1_450_409, Fork::Paris)],
+    by_time: &[
+        (1_677_557_088, Fork::Shanghai),
+        (1_706_655_072, Fork::Cancun),
+        (1_741_159_776, Fork::Prague),
+        (1_760_427_360, Fork::Osaka),
+    ],
+};
+
+/// Started after the merge
+pub static HOLESKY: ForkSchedule = ForkSchedule {
+    by_block: &[(0, Fork::Paris)],
+    by_time: &[
+        (1_696_000_704, Fork::Shanghai),
+        (1_707_305_664, Fork::Cancun),
+        (1_740_434_112, Fork::Prague),
+        (1_759_308_480, Fork::Osaka),
+    ],
+};
+
+/// Started with Cancun
+pub static HOODI: ForkSchedule = ForkSchedule {
+    by_block: &[(0, Fork::Paris)],
+    by_time: &[
+        (0, Fork::Cancun),
+        (1_742_999_832, Fork::Prague),
+        (1_761_677_592, Fork::Osaka),
+    ],
+};
+
+impl ForkSchedule {
+    /// The schedule of the chain with id `chain_id`, `None` for a chain not known here
+    pub fn for_chain(chain_id: u64) -> Option<&'static ForkSchedule> {
+        match chain_id {
+            1 => Some(&MAINNET),
+            17000 => Some(&HOLESKY),
+            560048 => Some(&HOODI),
+            11155111 => Some(&SEPOLIA),
+            _ => None,
+        }
+    }
+
+    /// The fork in effect for block `block_num` with timestamp `time_stamp`
+    pub fn fork_at(&self, block_num: u64, time_stamp: u64) -> Fork {
+        let mut fork = Fork::Frontier;
+
+        for &(activation, f) in self.by_block.iter() {
+            if block_num >= activation {
+                fork = f;
+            }
+        }
+
+        if fork == Fork::Paris {
+            for &(activation, f) in self.by_time.iter() {
+                if time_stamp >= activation {
+                    fork = f;
+                }
+            }
+        }
+
+        fork
+    }
+}
+
+impl Fork {
+    /// The fork in effect for the mainnet block `block_num` with timestamp `time_stamp`
+    pub fn at(block_num: u64, time_stamp: u64) -> Fork {
+        MAINNET.fork_at(block_num, time_stamp)
+    }
+}
+
+impl fmt::Display for Fork {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for Fork {
+    type Err = String;
+
+    /// A fork by name, ignoring case, spaces, dashes and underscores (`tangerine-whistle`)
+    fn from_str(name: &str) -> Result<Fork, String> {
+        let key: String = name
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect::<String>()
+            .to_lowercase();
+
+        let fork = match key.as_str() {
+            "frontier" => Fork::Frontier,
+            "homestead" => Fork::Homestead,
+            "tangerinewhistle" => Fork::TangerineWhistle,
+            "spuriousdragon" => Fork::SpuriousDragon,
+            "byzantium" => Fork::Byzantium,
+            "constantinople" | "petersburg" => Fork::Constantinople,
+            "istanbul" => Fork::Istanbul,
+            "berlin" => Fork::Berlin,
+            "london" => Fork::London,
+            "paris" | "merge" => Fork::Paris,
+            "shanghai" => Fork::Shanghai,
+            "cancun" => Fork::Cancun,
+            "prague" => Fork::Prague,
+            "osaka" => Fork::Osaka,
+            _ => return Err(format!("Unknown fork {}", name)),
+        };
+
+        Ok(fork)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mainnet_by_block() {
+        assert_eq!(Fork::at(0, 0), Fork::Frontier);
+        assert_eq!(Fork::at(1_149_999, 0), Fork::Frontier);
+        assert_eq!(Fork::at(1_150_000, 0), Fork::Homestead);
+        assert_eq!(Fork::at(9_068_999, 0), Fork::Constantinople);
+        assert_eq!(Fork::at(9_069_000, 0), Fork::Istanbul);
+        assert_eq!(Fork::at(12_243_999, 0), Fork::Istanbul);
+        assert_eq!(Fork::at(12_244_000, 0), Fork::Berlin);
+        assert_eq!(Fork::at(12_964_999, 0), Fork::Berlin);
+        assert_eq!(Fork::at(12_965_000, 0), Fork::London);
+        assert_eq!(Fork::at(15_537_393, 0), Fork::London);
+        assert_eq!(Fork::at(15_537_394, 0), Fork::Paris);
+    }
+
+    #[test]
+    fn mainnet_by_time() {
+        let block = 20_000_000;
+
+        assert_eq!(Fork::at(block, 1_681_338_454), Fork::Paris);
+        assert_eq!(Fork::at(block, 1_681_338_455), Fork::Shanghai);
+        assert_eq!(Fork::at(block, 1_710_338_134), Fork::Shanghai);
+        assert_eq!(Fork::at(block, 1_710_338_135), Fork::Cancun);
+        assert_eq!(Fork::at(block, 1_746_612_310), Fork::Cancun);
+        assert_eq!(Fork::at(block, 1_746_612_311), Fork::Prague);
+        assert_eq!(Fork::at(block, 1_764_798_551), Fork::Osaka);
+
+        // timestamps only count once the block number says the merge happened
+        assert_eq!(Fork::at(12_965_000, 1_800_000_000), Fork::London);
+    }
+
+    #[test]
+    fn testnets() {
+        let sepolia = ForkSchedule::for_chain(11155111).unwrap();
+        assert_eq!(sepolia.fork_at(0, 1_655_000_000), Fork::London);
+        assert_eq!(sepolia.fork_at(1_450_409, 1_656_000_000), Fork::Paris);
+        assert_eq!(sepolia.fork_at(3_000_000, 1_706_655_072), Fork::Cancun);
+
+        let holesky = ForkSchedule::for_chain(17000).unwrap();
+        assert_eq!(holesky.fork_at(0, 1_695_902_400), Fork::Paris);
+        assert_eq!(holesky.fork_at(1_000_000, 1_740_434_112), Fork::Prague);
+
+        let hoodi = ForkSchedule::for_chain(560048).unwrap();
+        assert_eq!(hoodi.fork_at(0, 1_742_212_800), Fork::Cancun);
+        assert_eq!(hoodi.fork_at(100_000, 1_742_999_832), Fork::Prague);
+
+        assert_eq!(
+            ForkSchedule::for_chain(1).unwrap().fork_at(12_965_000, 0),
+            Fork::London
+        );
+        assert!(ForkSchedule::for_chain(137).is_none());
+    }
+}
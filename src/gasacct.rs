@@ -0,0 +1,291 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Gas a transaction pays outside of its opcodes' `gasCost`: intrinsic gas charged up
+//! front and refunds credited at the end
+//!
+
+use fork::Fork;
+use gethrpc::{GethRpcError, TxnInfo};
+use json::JsonValue;
+use std::cmp;
+
+/// Every transaction
+const TX_GAS: u64 = 21_000;
+/// Contract creation, on top of `TX_GAS` (Homestead)
+const TX_CREATE_GAS: u64 = 32_000;
+const TX_DATA_ZERO_GAS: u64 = 4;
+const TX_DATA_NON_ZERO_GAS: u64 = 68;
+/// EIP-2028 (Istanbul)
+const TX_DATA_NON_ZERO_GAS_ISTANBUL: u64 = 16;
+/// EIP-2930 (Berlin)
+const TX_ACCESS_LIST_ADDRESS_GAS: u64 = 2_400;
+const TX_ACCESS_LIST_KEY_GAS: u64 = 1_900;
+/// EIP-3860 (Shanghai), per 32 byte word of init code
+const INIT_CODE_WORD_GAS: u64 = 2;
+/// EIP-7702 (Prague), per authorization
+const PER_EMPTY_ACCOUNT_GAS: u64 = 25_000;
+/// EIP-7623 (Prague), per calldata token
+const TX_DATA_FLOOR_TOKEN_GAS: u64 = 10;
+
+///
+/// Where a transaction's gas went, beyond what its opcodes were charged
+///
+#[derive(Debug, Clone)]
+pub struct GasAccount {
+    /// Charged before execution: base cost, calldata, contract creation, access list, ...
+    pub intrinsic: u64,
+
+    /// Consumed by execution. Unlike summed opcode `gasCost` this counts gas forwarded to
+    /// inner calls only as far as they used it.
+    pub execution: u64,
+
+    /// Credited back after execution, after capping
+    pub refund: u64,
+
+    /// The receipt's `gasUsed` minus `intrinsic + execution - refund` (or the EIP-7623
+    /// floor); `None` without a receipt. Anything but 0 means the accounting missed something,
+    /// with one known exception: a successful contract creation pays 200 gas per byte of the
+    /// code it deploys after its last step, which no trace shows, so its delta is that cost.
+    pub delta: Option<i64>,
+}
+
+/// Gas charged for `txn` before any code runs
+pub fn intrinsic_gas(txn: &TxnInfo, fork: Fork) -> u64 {
+    let creation = txn.to.is_empty();
+    let zeros = txn.input_zero_bytes as u64;
+    let non_zeros = txn.input_size as u64 - zeros;
+
+    let mut gas = TX_GAS;
+
+    if creation && fork >= Fork::Homestead {
+        gas += TX_CREATE_GAS;
+    }
+
+    let non_zero_gas = if fork >= Fork::Istanbul {
+        TX_DATA_NON_ZERO_GAS_ISTANBUL
+    } else {
+        TX_DATA_NON_ZERO_GAS
+    };
+    gas += zeros * TX_DATA_ZERO_GAS + non_zeros * non_zero_gas;
+
+    gas += txn.access_list_addresses as u64 * TX_ACCESS_LIST_ADDRESS_GAS;
+    gas += txn.access_list_keys as u64 * TX_ACCESS_LIST_KEY_GAS;
+
+    if creation && fork >= Fork::Shanghai {
+        gas += INIT_CODE_WORD_GAS * (txn.input_size as u64).div_ceil(32);
+    }
+
+    gas + txn.authorizations as u64 * PER_EMPTY_ACCOUNT_GAS
+}
+
+/// EIP-7623 minimum gas `txn` is charged, however little it executes; 0 before Prague
+pub fn floor_gas(txn: &TxnInfo, fork: Fork) -> u64 {
+    if fork < Fork::Prague {
+        return 0;
+    }
+
+    let zeros = txn.input_zero_bytes as u64;
+    let tokens = zeros + 4 * (txn.input_size as u64 - zeros);
+
+    TX_GAS + tokens * TX_DATA_FLOOR_TOKEN_GAS
+}
+
+///
+/// How a transaction's execution began and ended, as far as gas accounting cares
+///
+#[derive(Debug, Clone)]
+pub struct ExecSummary {
+    /// Gas left for execution once intrinsic gas was charged
+    pub available_gas: u64,
+
+    /// Final step of the top-level call frame: opcode name, gas before it and its cost
+    pub last_op: String,
+    pub last_gas: u64,
+    pub last_cost: u64,
+
+    /// Refund counter at the final step
+    pub refund: u64,
+}
+
+impl ExecSummary {
+    /// Summarize the result of `gethrpc::HISTOGRAM_TRACER`
+    pub fn from_histogram(result: &JsonValue) -> Result<Self, GethRpcError> {
+        Ok(ExecSummary {
+            available_gas: result["firstGas"]
+                .as_u64()
+                .ok_or_else(|| GethRpcError::missing("firstGas"))?,
+            last_op: result["lastOp"]
+                .as_str()
+                .ok_or_else(|| GethRpcError::missing("lastOp"))?
+                .to_string(),
+            last_gas: result["lastGas"].as_u64().unwrap_or(0),
+            last_cost: result["lastCost"].as_u64().unwrap_or(0),
+            refund: result["refund"].as_u64().unwrap_or(0),
+        })
+    }
+}
+
+/// Account for the gas of `txn`, executed under `fork`
+pub fn account(txn: &TxnInfo, fork: Fork, exec: &ExecSummary) -> GasAccount {
+    let intrinsic = intrinsic_gas(txn, fork);
+
+    let left_after_last = exec.last_gas.saturating_sub(exec.last_cost);
+
+    // an exceptional halt (out of gas, invalid opcode, ...) consumes everything
+    let (remaining, succeeded) = match exec.last_op.as_str() {
+        "STOP" | "RETURN" | "SELFDESTRUCT" | "SUICIDE" => (left_after_last, true),
+        "REVERT" => (left_after_last, false),
+        _ => (0, false),
+    };
+    let execution = exec.available_gas.saturating_sub(remaining);
+
+    // refunds only survive successful execution
+    let refund = if succeeded {
+        // EIP-3529 (London) lowered the cap from 1/2 to 1/5 of gas used
+        let quotient = if fork >= Fork::London { 5 } else { 2 };
+        cmp::min(exec.refund, (intrinsic + execution) / quotient)
+    } else {
+        0
+    };
+
+    let charged = cmp::max(intrinsic + execution - refund, floor_gas(txn, fork));
+
+    let delta = txn
+        .receipt
+        .as_ref()
+        .map(|receipt| receipt.gas_used as i64 - charged as i64);
+
+    GasAccount {
+        intrinsic,
+        execution,
+        refund,
+        delta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gethrpc::ReceiptInfo;
+
+    const CONTRACT: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    /// A transaction with `input_size` bytes of input, `zeros` of them zero, to `to` (empty
+    /// for a creation) that used `gas_used`
+    fn txn(to: &str, input_size: usize, zeros: usize, gas_used: u64) -> TxnInfo {
+        TxnInfo {
+            block_num: 21_000_000,
+            block_index: 0,
+            gas_price: 1_000_000_000,
+            from: "0x4838b106fce9647bdf1e7877bf73ce8b0bad5f97".to_string(),
+            to: to.to_string(),
+            hash: String::new(),
+            nonce: 0,
+            value: 0,
+            input_size,
+            input_zero_bytes: zeros,
+            access_list_addresses: 0,
+            access_list_keys: 0,
+            authorizations: 0,
+            gas_limit: 1_000_000,
+            txn_type: 2,
+            max_fee: None,
+            max_priority_fee: None,
+            effective_gas_price: 1_000_000_000,
+            receipt: Some(ReceiptInfo {
+                status: Some(true),
+                gas_used,
+                cumulative_gas_used: gas_used,
+                log_count: 0,
+            }),
+        }
+    }
+
+    fn exec(available_gas: u64, last_op: &str, last_gas: u64, refund: u64) -> ExecSummary {
+        ExecSummary {
+            available_gas,
+            last_op: last_op.to_string(),
+            last_gas,
+            last_cost: 0,
+            refund,
+        }
+    }
+
+    #[test]
+    fn intrinsic_gas_by_fork() {
+        let call = txn(CONTRACT, 68, 4, 0);
+        assert_eq!(
+            intrinsic_gas(&call, Fork::Byzantium),
+            21_000 + 4 * 4 + 64 * 68
+        );
+        assert_eq!(
+            intrinsic_gas(&call, Fork::Istanbul),
+            21_000 + 4 * 4 + 64 * 16
+        );
+
+        // 33 bytes of init code are two words
+        let create = txn("", 33, 0, 0);
+        assert_eq!(intrinsic_gas(&create, Fork::Frontier), 21_000 + 33 * 68);
+        assert_eq!(intrinsic_gas(&create, Fork::Paris), 53_000 + 33 * 16);
+        assert_eq!(
+            intrinsic_gas(&create, Fork::Shanghai),
+            53_000 + 33 * 16 + 2 * 2
+        );
+    }
+
+    #[test]
+    fn call_is_fully_accounted() {
+        let intrinsic = intrinsic_gas(&txn(CONTRACT, 68, 4, 0), Fork::Prague);
+        // 30,000 used by execution, 10,000 of it refunded (under the 1/5 cap)
+        let used = intrinsic + 30_000 - 10_000;
+        let call = txn(CONTRACT, 68, 4, used);
+
+        let gas = account(&call, Fork::Prague, &exec(100_000, "STOP", 70_000, 10_000));
+
+        assert_eq!(gas.intrinsic, intrinsic);
+        assert_eq!(gas.execution, 30_000);
+        assert_eq!(gas.refund, 10_000);
+        assert_eq!(gas.delta, Some(0));
+    }
+
+    #[test]
+    fn creation_delta_is_code_deposit() {
+        let intrinsic = intrinsic_gas(&txn("", 7, 2, 0), Fork::Prague);
+        // init code that ran for 40,000 gas and returned 100 bytes of code to deploy
+        let deposit = 200 * 100;
+        let create = txn("", 7, 2, intrinsic + 40_000 + deposit);
+
+        let gas = account(&create, Fork::Prague, &exec(100_000, "RETURN", 60_000, 0));
+
+        assert_eq!(gas.execution, 40_000);
+        assert_eq!(gas.delta, Some(deposit as i64));
+    }
+
+    #[test]
+    fn exceptional_halt_consumes_all_gas() {
+        let call = txn(CONTRACT, 0, 0, 121_000);
+
+        let gas = account(
+            &call,
+            Fork::Prague,
+            &exec(100_000, "INVALID", 60_000, 5_000),
+        );
+
+        assert_eq!(gas.execution, 100_000);
+        assert_eq!(gas.refund, 0);
+        assert_eq!(gas.delta, Some(0));
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Errors from the geth RPC layer
+//!
+
+use gethrpc::framer::FrameError;
+use json;
+use json::JsonValue;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum GethRpcError {
+    /// Connecting, reading or writing the connection to geth failed
+    Io(io::Error),
+
+    /// Geth sent nothing for `waited` while we were expecting a response
+    Timeout { waited: Duration },
+
+    /// Geth answered with a JSON-RPC error object
+    Rpc { code: i64, message: String },
+
+    /// Geth's HTTP endpoint (or a proxy in front of it) refused the request; asking again
+    /// won't help
+    HttpStatus { status: u16, message: String },
+
+    /// The response couldn't be framed or parsed
+    Malformed(FrameError),
+
+    /// A field the response must have is absent, `null` or not the expected type
+    MissingField(String),
+
+    /// A field is present but its value can't be right, e.g. out of range
+    InvalidField { field: String, value: String },
+}
+
+impl GethRpcError {
+    /// Interpret the `error` member of a JSON-RPC response
+    pub fn from_rpc_error(error: &JsonValue) -> Self {
+        GethRpcError::Rpc {
+            code: error["code"].as_i64().unwrap_or(0),
+            message: error["message"].as_str().unwrap_or("").to_string(),
+        }
+    }
+
+    pub fn missing(field: &str) -> Self {
+        GethRpcError::MissingField(field.to_string())
+    }
+
+    pub fn invalid(field: &str, value: &str) -> Self {
+        GethRpcError::InvalidField {
+            field: field.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Geth couldn't trace transaction `idx` of a block (e.g. the tracer timed out) and said
+    /// why in place of its trace. Like a JSON-RPC error it fails the whole block, so a block
+    /// is never written without some of its transactions.
+    pub fn untraced(idx: usize, error: &str) -> Self {
+        GethRpcError::Rpc {
+            code: 0,
+            message: format!("transaction {}: {}", idx, error),
+        }
+    }
+}
+
+impl fmt::Display for GethRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GethRpcError::Io(ref e) => write!(f, "geth connection: {}", e),
+            GethRpcError::Timeout { waited } => {
+                write!(f, "no response from geth after {:?}", waited)
+            }
+            GethRpcError::Rpc { code, ref message } => {
+                write!(f, "geth error {}: {}", code, message)
+            }
+            GethRpcError::HttpStatus {
+                status,
+                ref message,
+            } => write!(f, "HTTP {}: {}", status, message),
+            GethRpcError::Malformed(ref e) => write!(f, "malformed response: {}", e),
+            GethRpcError::MissingField(ref name) => write!(f, "response missing '{}'", name),
+            GethRpcError::InvalidField {
+                ref field,
+                ref value,
+            } => write!(f, "response has invalid '{}': {}", field, value),
+        }
+    }
+}
+
+impl Error for GethRpcError {
+    fn description(&self) -> &str {
+        "geth RPC error"
+    }
+}
+
+impl From<io::Error> for GethRpcError {
+    fn from(e: io::Error) -> Self {
+        GethRpcError::Io(e)
+    }
+}
+
+impl From<FrameError> for GethRpcError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            // the connection went away mid-response; that's an I/O problem, not a bad message
+            FrameError::UnexpectedEof { .. } => {
+                GethRpcError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, e.to_string()))
+            }
+            _ => GethRpcError::Malformed(e),
+        }
+    }
+}
+
+impl From<json::Error> for GethRpcError {
+    fn from(e: json::Error) -> Self {
+        GethRpcError::Malformed(FrameError::Json(e))
+    }
+}
@@ -0,0 +1,436 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Splits a stream of bytes into complete JSON-RPC messages
+//!
+//! Geth's IPC socket is a plain byte stream: a response may arrive over many reads, a
+//! read may end anywhere (mid-string, mid-escape, mid-UTF-8 sequence) and several
+//! messages may arrive in one read. `JsonFramer` tracks just enough JSON structure
+//! (nesting and string state) to find where each top-level object or array ends.
+//!
+
+use json;
+use json::JsonValue;
+use std::error::Error;
+use std::fmt;
+use std::str;
+
+/// Problems turning bytes from geth into JSON messages
+#[derive(Debug)]
+pub enum FrameError {
+    /// A byte that can't appear at this point of a message; `offset` counts from the
+    /// start of the stream
+    UnexpectedByte { byte: u8, offset: u64 },
+
+    /// A complete message that isn't valid UTF-8; `offset` is within the message
+    InvalidUtf8 { offset: usize },
+
+    /// A complete message whose brackets balance but which isn't valid JSON
+    Json(json::Error),
+
+    /// The stream ended with `buffered` bytes of an incomplete message outstanding
+    UnexpectedEof { buffered: usize },
+
+    /// A response to some request other than the one we sent (HTTP has no way to skip it)
+    MismatchedId { expected: u64, received: String },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameError::UnexpectedByte { byte, offset } => {
+                write!(
+                    f,
+                    "unexpected byte {:#04x} at stream offset {}",
+                    byte, offset
+                )
+            }
+            FrameError::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 at message offset {}", offset)
+            }
+            FrameError::Json(ref e) => write!(f, "malformed JSON: {}", e),
+            FrameError::UnexpectedEof { buffered } => {
+                write!(
+                    f,
+                    "stream closed with {} bytes of a partial message",
+                    buffered
+                )
+            }
+            FrameError::MismatchedId {
+                expected,
+                ref received,
+            } => write!(
+                f,
+                "expected response id {}, received {}",
+                expected, received
+            ),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn description(&self) -> &str {
+        "JSON-RPC framing error"
+    }
+}
+
+impl From<json::Error> for FrameError {
+    fn from(e: json::Error) -> Self {
+        FrameError::Json(e)
+    }
+}
+
+pub struct JsonFramer {
+    /// Received bytes not yet returned as a message
+    buf: Vec<u8>,
+
+    /// How far into `buf` the scanner has progressed
+    scanned: usize,
+
+    /// Closing bracket expected for each currently open object/array
+    closers: Vec<u8>,
+
+    in_string: bool,
+    escaped: bool,
+
+    /// Stream offset of `buf[0]`, for error reporting
+    stream_offset: u64,
+}
+
+impl JsonFramer {
+    pub fn new() -> Self {
+        JsonFramer {
+            buf: Vec::new(),
+            scanned: 0,
+            closers: Vec::new(),
+            in_string: false,
+            escaped: false,
+            stream_offset: 0,
+        }
+    }
+
+    /// Create a framer that pre-allocates `capacity` bytes of buffer
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut framer = JsonFramer::new();
+        framer.buf.reserve(capacity);
+        framer
+    }
+
+    /// Append bytes read from the stream
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes received that aren't yet part of a returned message
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Remove and return all buffered bytes, e.g. to hand them to a streaming parser
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        let buf = self.buf.clone();
+        self.reset();
+        buf
+    }
+
+    /// Discard all buffered bytes and scanner state, e.g. after an error or reconnect
+    pub fn reset(&mut self) {
+        self.stream_offset += self.buf.len() as u64;
+        self.buf.clear();
+        self.scanned = 0;
+        self.closers.clear();
+        self.in_string = false;
+        self.escaped = false;
+    }
+
+    /// Parse and return the next complete message, `Ok(None)` if more bytes are needed.
+    ///
+    /// A message that isn't valid UTF-8 or JSON is dropped and the framer carries on with
+    /// the bytes after it. An `UnexpectedByte` means the stream is out of sync, so
+    /// everything buffered is discarded.
+    pub fn next_message(&mut self) -> Result<Option<JsonValue>, FrameError> {
+        let end = match self.scan() {
+            Ok(Some(end)) => end,
+            Ok(None) => {
+                // nothing but whitespace between messages so far; it isn't a partial message
+                if self.closers.is_empty() {
+                    self.buf.drain(..self.scanned);
+                    self.stream_offset += self.scanned as u64;
+                    self.scanned = 0;
+                }
+                return Ok(None);
+            }
+            Err(e) => {
+                self.reset();
+                return Err(e);
+            }
+        };
+
+        let result = match str::from_utf8(&self.buf[..end]) {
+            Ok(text) => json::parse(text).map_err(FrameError::from),
+            Err(e) => Err(FrameError::InvalidUtf8 {
+                offset: e.valid_up_to(),
+            }),
+        };
+
+        self.buf.drain(..end);
+        self.stream_offset += end as u64;
+        self.scanned = 0;
+
+        result.map(Some)
+    }
+
+    /// Advance the scanner over unscanned bytes, returning the end offset of the first
+    /// complete top-level value
+    fn scan(&mut self) -> Result<Option<usize>, FrameError> {
+        while self.scanned < self.buf.len() {
+            let idx = self.scanned;
+            let byte = self.buf[idx];
+            self.scanned += 1;
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+
+            if self.closers.is_empty() {
+                // between messages only whitespace may precede the opening bracket
+                match byte {
+                    b'{' => self.closers.push(b'}'),
+                    b'[' => self.closers.push(b']'),
+                    b if is_whitespace(b) => continue,
+                    _ => return Err(self.unexpected(idx)),
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' => self.closers.push(b'}'),
+                b'[' => self.closers.push(b']'),
+                b'}' | b']' => {
+                    if self.closers.pop() != Some(byte) {
+                        return Err(self.unexpected(idx));
+                    }
+                    if self.closers.is_empty() {
+                        return Ok(Some(self.scanned));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn unexpected(&self, idx: usize) -> FrameError {
+        FrameError::UnexpectedByte {
+            byte: self.buf[idx],
+            offset: self.stream_offset + idx as u64,
+        }
+    }
+}
+
+impl Default for JsonFramer {
+    fn default() -> Self {
+        JsonFramer::new()
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\n' || b == b'\r' || b == b'\t'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gethrpc::{is_response_to, GethRpcError};
+    use std::io;
+
+    /// Every message framed from `bytes` fed `piece` bytes at a time
+    fn frame_in_pieces(bytes: &[u8], piece: usize) -> Vec<JsonValue> {
+        let mut framer = JsonFramer::new();
+        let mut msgs = Vec::new();
+
+        for chunk in bytes.chunks(piece) {
+            framer.push(chunk);
+            while let Some(msg) = framer.next_message().unwrap() {
+                msgs.push(msg);
+            }
+        }
+
+        assert_eq!(framer.buffered(), 0);
+        msgs
+    }
+
+    /// `text` frames to the same single message however it's split in two
+    fn assert_any_split(text: &str) {
+        let bytes = text.as_bytes();
+        let expected = json::parse(text).unwrap();
+
+        for split in 1..bytes.len() {
+            let mut framer = JsonFramer::new();
+
+            framer.push(&bytes[..split]);
+            assert!(
+                framer.next_message().unwrap().is_none(),
+                "split at {}",
+                split
+            );
+
+            framer.push(&bytes[split..]);
+            assert_eq!(framer.next_message().unwrap(), Some(expected.clone()));
+            assert!(framer.next_message().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn split_inside_string() {
+        assert_any_split(r#"{"id":1,"result":"not } the ] end {["}"#);
+    }
+
+    #[test]
+    fn split_inside_escape() {
+        let text = r#"{"id":1,"result":"a \" quote, a \\ backslash\\","u":"é\"}"}"#;
+        assert_any_split(text);
+
+        let msgs = frame_in_pieces(text.as_bytes(), 1);
+        assert_eq!(msgs[0]["result"], "a \" quote, a \\ backslash\\");
+        assert_eq!(msgs[0]["u"], "\u{e9}\"}");
+    }
+
+    #[test]
+    fn split_inside_utf8() {
+        let text = "{\"id\":1,\"result\":\"caf\u{e9} \u{20ac}5 \u{1f600}\"}";
+        assert_any_split(text);
+
+        let msgs = frame_in_pieces(text.as_bytes(), 3);
+        assert_eq!(msgs[0]["result"], "caf\u{e9} \u{20ac}5 \u{1f600}");
+    }
+
+    #[test]
+    fn several_messages_in_one_read() {
+        let text = "{\"id\":1}\n{\"id\":2,\"result\":[1,2]} [{\"id\":3},{\"id\":4}]\r\n";
+
+        let msgs = frame_in_pieces(text.as_bytes(), text.len());
+
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0]["id"], 1);
+        assert_eq!(msgs[1]["result"][1], 2);
+        assert!(msgs[2].is_array());
+        assert_eq!(frame_in_pieces(text.as_bytes(), 2), msgs);
+    }
+
+    #[test]
+    fn stale_and_mismatched_ids() {
+        // the late answer to request 1, which we gave up on, then the one to request 2
+        let text =
+            r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}{"jsonrpc":"2.0","id":2,"result":"0x2"}"#;
+        let msgs = frame_in_pieces(text.as_bytes(), 7);
+
+        assert!(!is_response_to(&msgs[0], 2));
+        assert!(is_response_to(&msgs[1], 2));
+
+        // geth can only answer a request it couldn't parse with a null id
+        let error = json::parse(r#"{"id":null,"error":{"code":-32700,"message":"parse error"}}"#);
+        assert!(is_response_to(&error.unwrap(), 2));
+        let result = json::parse(r#"{"id":null,"result":"0x2"}"#).unwrap();
+        assert!(!is_response_to(&result, 2));
+
+        // a batch is answered if any member carries its first id
+        let batch = json::parse(r#"[{"id":4,"result":1},{"id":3,"result":2}]"#).unwrap();
+        assert!(is_response_to(&batch, 3));
+        assert!(!is_response_to(&batch, 2));
+    }
+
+    #[test]
+    fn eof_mid_message() {
+        let mut framer = JsonFramer::new();
+        framer.push(br#"{"id":1}{"id":2,"result":"trunc"#);
+
+        assert_eq!(framer.next_message().unwrap().unwrap()["id"], 1);
+        assert!(framer.next_message().unwrap().is_none());
+        assert_eq!(framer.buffered(), 23);
+
+        // the transports report what's left when the stream closes; it's worth a retry
+        let err: GethRpcError = FrameError::UnexpectedEof {
+            buffered: framer.buffered(),
+        }
+        .into();
+        match err {
+            GethRpcError::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            ref e => panic!("expected an I/O error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn stray_closing_brackets() {
+        let mut framer = JsonFramer::new();
+
+        framer.push(b"\n}{\"id\":1}");
+        match framer.next_message() {
+            Err(FrameError::UnexpectedByte { byte, offset }) => {
+                assert_eq!(byte, b'}');
+                assert_eq!(offset, 1);
+            }
+            other => panic!("expected an unexpected byte, got {:?}", other),
+        }
+
+        // out of sync: everything buffered was discarded, offsets carry on
+        assert_eq!(framer.buffered(), 0);
+        framer.push(b"]");
+        match framer.next_message() {
+            Err(FrameError::UnexpectedByte { byte, offset }) => {
+                assert_eq!(byte, b']');
+                assert_eq!(offset, 10);
+            }
+            other => panic!("expected an unexpected byte, got {:?}", other),
+        }
+
+        framer.push(br#"{"id":[1}"#);
+        match framer.next_message() {
+            Err(FrameError::UnexpectedByte { byte, offset }) => {
+                assert_eq!(byte, b'}');
+                assert_eq!(offset, 19);
+            }
+            other => panic!("expected an unexpected byte, got {:?}", other),
+        }
+
+        framer.push(br#"{"id":2}"#);
+        assert_eq!(framer.next_message().unwrap().unwrap()["id"], 2);
+    }
+
+    #[test]
+    fn invalid_message_is_dropped() {
+        let mut framer = JsonFramer::new();
+        framer.push(b"{\"id\":1,}{\"id\":\"\xff\"}{\"id\":2}");
+
+        match framer.next_message() {
+            Err(FrameError::Json(_)) => (),
+            other => panic!("expected malformed JSON, got {:?}", other),
+        }
+        match framer.next_message() {
+            Err(FrameError::InvalidUtf8 { offset }) => assert_eq!(offset, 7),
+            other => panic!("expected invalid UTF-8, got {:?}", other),
+        }
+        assert_eq!(framer.next_message().unwrap().unwrap()["id"], 2);
+    }
+}
@@ -0,0 +1,44 @@
+// Tallies executed opcodes and their gas inside geth, returning 256-entry histograms
+// indexed by opcode instead of every step. Also reports what gas accounting needs to
+// know about how execution began and ended.
+{
+    counts: [],
+    gas: [],
+    firstGas: null,
+    last: null,
+    refund: 0,
+
+    step: function(log, db) {
+        var op = log.op.toNumber();
+
+        this.counts[op] = (this.counts[op] || 0) + 1;
+        this.gas[op] = (this.gas[op] || 0) + log.getCost();
+
+        if (this.firstGas === null) {
+            this.firstGas = log.getGas();
+        }
+        if (log.getDepth() == 1) {
+            this.last = {op: log.op.toString(), gas: log.getGas(), cost: log.getCost()};
+        }
+        this.refund = log.getRefund();
+    },
+
+    fault: function(log, db) {},
+
+    result: function(ctx, db) {
+        for (var i = 0; i < 256; i++) {
+            this.counts[i] = this.counts[i] || 0;
+            this.gas[i] = this.gas[i] || 0;
+        }
+
+        return {
+            counts: this.counts,
+            gas: this.gas,
+            firstGas: this.firstGas,
+            lastOp: this.last && this.last.op,
+            lastGas: this.last && this.last.gas,
+            lastCost: this.last && this.last.cost,
+            refund: this.refund
+        };
+    }
+}
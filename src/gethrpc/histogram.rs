@@ -0,0 +1,206 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Reads the `debug_traceBlockByNumber` result of `HISTOGRAM_TRACER`: for each transaction
+//! 256 counts and 256 gas totals indexed by opcode, and how execution ended
+//!
+
+use evminst::EvmInst;
+use gasacct::ExecSummary;
+use gethrpc::{GethRpcError, TxnTally};
+use instcount::InstCount;
+use json::JsonValue;
+
+/// A tally per transaction of `traces`, in block order, the same as `StructLogStream` makes
+/// of a struct logger trace. A transaction geth couldn't trace fails the whole block.
+pub fn tallies(traces: &JsonValue) -> Result<Vec<TxnTally>, GethRpcError> {
+    if !traces.is_array() {
+        return Err(GethRpcError::missing("result"));
+    }
+
+    traces
+        .members()
+        .enumerate()
+        .map(|(idx, trace)| txn_tally(idx, trace))
+        .collect()
+}
+
+fn txn_tally(idx: usize, trace: &JsonValue) -> Result<TxnTally, GethRpcError> {
+    let mut counts = InstCount::new();
+
+    if let Some(error) = trace["error"].as_str() {
+        return Err(GethRpcError::untraced(idx, error));
+    }
+
+    let result = &trace["result"];
+    if !result.is_object() {
+        return Err(GethRpcError::MissingField(format!(
+            "trace of transaction {}",
+            idx
+        )));
+    }
+
+    // no steps, no code ran
+    if result["firstGas"].is_null() {
+        return Ok(TxnTally { counts, exec: None });
+    }
+
+    let op_counts = &result["counts"];
+    let op_gas = &result["gas"];
+
+    if op_counts.len() != 256 || op_gas.len() != 256 {
+        return Err(GethRpcError::missing("counts"));
+    }
+
+    for op in 0..256 {
+        let count = op_counts[op]
+            .as_u64()
+            .ok_or_else(|| GethRpcError::missing("counts"))?;
+        if count == 0 {
+            continue;
+        }
+
+        let inst = EvmInst::from_u8(op as u8);
+        counts.add_count(inst, count);
+        counts.add_gas(
+            inst,
+            op_gas[op]
+                .as_u64()
+                .ok_or_else(|| GethRpcError::missing("gas"))?,
+        );
+    }
+
+    Ok(TxnTally {
+        counts,
+        exec: Some(ExecSummary::from_histogram(result)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evminst;
+    use gethrpc::structlog::StructLogStream;
+    use gethrpc::ResponseSink;
+    use json;
+
+    const STRUCT_LOGS: &str = include_str!("../../tests/fixtures/trace_structlog.json");
+    const HISTOGRAMS: &str = include_str!("../../tests/fixtures/trace_histogram.json");
+
+    fn struct_log_tallies(response: &str) -> Result<Vec<TxnTally>, GethRpcError> {
+        let mut stream = StructLogStream::new(1);
+        stream.push(response.as_bytes())?;
+        stream.finish()
+    }
+
+    fn histogram_tallies(response: &str) -> Result<Vec<TxnTally>, GethRpcError> {
+        tallies(&json::parse(response).unwrap()["result"])
+    }
+
+    fn assert_same(a: &[TxnTally], b: &[TxnTally]) {
+        assert_eq!(a.len(), b.len());
+
+        for (idx, (a, b)) in a.iter().zip(b).enumerate() {
+            for &inst in evminst::VALUES.iter() {
+                assert_eq!(
+                    a.counts.get_count(inst),
+                    b.counts.get_count(inst),
+                    "{}",
+                    idx
+                );
+                assert_eq!(a.counts.get_gas(inst), b.counts.get_gas(inst), "{}", idx);
+            }
+
+            match (&a.exec, &b.exec) {
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.available_gas, b.available_gas);
+                    assert_eq!(a.last_op, b.last_op);
+                    assert_eq!(a.last_gas, b.last_gas);
+                    assert_eq!(a.last_cost, b.last_cost);
+                    assert_eq!(a.refund, b.refund);
+                }
+                (None, None) => (),
+                _ => panic!("transaction {}: only one ran code", idx),
+            }
+        }
+    }
+
+    #[test]
+    fn same_as_struct_logs() {
+        let histograms = histogram_tallies(HISTOGRAMS).unwrap();
+
+        assert_same(&histograms, &struct_log_tallies(STRUCT_LOGS).unwrap());
+
+        let ran_code: Vec<_> = histograms.iter().map(|t| t.exec.is_some()).collect();
+        assert_eq!(ran_code, vec![true, false, true, false, true]);
+        assert_eq!(histograms[0].counts.get_count(EvmInst::SSTORE), 2);
+        assert_eq!(histograms[0].counts.get_gas(EvmInst::SSTORE), 7900);
+    }
+
+    #[test]
+    fn untraced_transaction() {
+        let untraced = json::parse(r#"{"txHash":"0x03","result":null}"#).unwrap();
+
+        let mut histograms = json::parse(HISTOGRAMS).unwrap();
+        histograms["result"][2] = untraced.clone();
+        let mut struct_logs = json::parse(STRUCT_LOGS).unwrap();
+        struct_logs["result"][2] = untraced;
+
+        for result in [
+            histogram_tallies(&histograms.dump()),
+            struct_log_tallies(&struct_logs.dump()),
+        ] {
+            match result {
+                Err(GethRpcError::MissingField(what)) => {
+                    assert_eq!(what, "trace of transaction 2")
+                }
+                other => panic!("expected a missing trace, got {:?}", other.map(|t| t.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn failed_trace() {
+        let failed = json::parse(r#"{"txHash":"0x04","error":"execution timeout"}"#).unwrap();
+
+        let mut histograms = json::parse(HISTOGRAMS).unwrap();
+        histograms["result"][3] = failed.clone();
+        let mut struct_logs = json::parse(STRUCT_LOGS).unwrap();
+        struct_logs["result"][3] = failed;
+
+        for result in [
+            histogram_tallies(&histograms.dump()),
+            struct_log_tallies(&struct_logs.dump()),
+        ] {
+            match result {
+                Err(GethRpcError::Rpc { message, .. }) => {
+                    assert_eq!(message, "transaction 3: execution timeout")
+                }
+                other => panic!("expected a failed trace, got {:?}", other.map(|t| t.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn short_histogram() {
+        let mut traces = json::parse(HISTOGRAMS).unwrap();
+        traces["result"][0]["result"]["gas"].pop();
+
+        match tallies(&traces["result"]) {
+            Err(GethRpcError::MissingField(what)) => assert_eq!(what, "counts"),
+            other => panic!("expected short counts, got {:?}", other.map(|t| t.len())),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! JSON-RPC over HTTP(S), for nodes that aren't on the local host
+//!
+
+extern crate reqwest;
+
+use gethrpc::framer::FrameError;
+use gethrpc::{is_response_to, GethRpcError, ResponseSink, Transport};
+use json;
+use json::JsonValue;
+use serde_json;
+use std::io;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::io::Read;
+use std::time::Duration;
+
+/// JSON-RPC error code for a request that isn't valid JSON
+const PARSE_ERROR: i64 = -32700;
+
+pub struct HttpTransport {
+    /// Full `http://` or `https://` URL of the geth RPC endpoint
+    url: String,
+
+    client: reqwest::Client,
+
+    /// Give up on a response after this long
+    timeout: Duration,
+}
+
+impl HttpTransport {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, GethRpcError> {
+        Ok(HttpTransport {
+            url: url.to_string(),
+            client: Self::build_client(timeout)?,
+            timeout,
+        })
+    }
+
+    fn build_client(timeout: Duration) -> Result<reqwest::Client, GethRpcError> {
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| GethRpcError::Io(io::Error::other(e.to_string())))
+    }
+
+    /// POST `rpc` to geth, returning the response once its status shows success
+    fn post(&self, rpc: &str, note: &str) -> Result<reqwest::Response, GethRpcError> {
+        // round-trip through serde so reqwest sets the JSON content-type geth insists on.
+        // Geth would answer a request it can't parse with a parse error, so do the same.
+        let body: serde_json::Value = serde_json::from_str(rpc).map_err(|e| GethRpcError::Rpc {
+            code: PARSE_ERROR,
+            message: format!("malformed request in {}: {}", note, e),
+        })?;
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .map_err(|e| self.http_error(e))?;
+
+        if !response.status().is_success() {
+            return Err(GethRpcError::HttpStatus {
+                status: response.status().as_u16(),
+                message: format!("from {} in {}", self.url, note),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// reqwest failures amount to "couldn't talk to geth", unless geth was merely slow
+    fn http_error(&self, e: reqwest::Error) -> GethRpcError {
+        if e.is_timeout() {
+            GethRpcError::Timeout {
+                waited: self.timeout,
+            }
+        } else {
+            GethRpcError::Io(io::Error::other(e.to_string()))
+        }
+    }
+
+    /// As `http_error`, for reading the response body
+    fn read_error(&self, e: io::Error) -> GethRpcError {
+        match e.kind() {
+            TimedOut | WouldBlock => GethRpcError::Timeout {
+                waited: self.timeout,
+            },
+            _ => GethRpcError::Io(e),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn request(&mut self, id: u64, rpc: &str, note: &str) -> Result<JsonValue, GethRpcError> {
+        let mut response = self.post(rpc, note)?;
+
+        let text = response.text().map_err(|e| self.http_error(e))?;
+
+        debug!("{}: read {}", note, text.len());
+
+        let msg = json::parse(&text)?;
+
+        if is_response_to(&msg, id) {
+            Ok(msg)
+        } else {
+            Err(FrameError::MismatchedId {
+                expected: id,
+                received: msg["id"].dump(),
+            }
+            .into())
+        }
+    }
+
+    fn stream_request(
+        &mut self,
+        _id: u64,
+        rpc: &str,
+        note: &str,
+        sink: &mut dyn ResponseSink,
+    ) -> Result<(), GethRpcError> {
+        let mut response = self.post(rpc, note)?;
+
+        let mut buf = vec![0u8; 1024 * 1024];
+        let mut total_read = 0u64;
+
+        while !sink.is_complete() {
+            let size = response.read(&mut buf).map_err(|e| self.read_error(e))?;
+            if size == 0 {
+                return Err(FrameError::UnexpectedEof {
+                    buffered: total_read as usize,
+                }
+                .into());
+            }
+
+            total_read += size as u64;
+            debug!("{}: stream read {}, total {}", note, size, total_read);
+
+            sink.push(&buf[..size])?;
+        }
+
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        match Self::build_client(timeout) {
+            Ok(client) => {
+                self.client = client;
+                self.timeout = timeout;
+            }
+            Err(e) => warn!("keeping previous HTTP timeout: {}", e),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! JSON-RPC over geth's Unix domain socket
+//!
+
+use bytesize;
+use gethrpc::framer::{FrameError, JsonFramer};
+use gethrpc::{is_response_to, GethRpcError, ResponseSink, Transport};
+use json::JsonValue;
+use std::cmp;
+use std::io;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+pub struct IpcTransport {
+    /// Path of geth's IPC socket
+    path: String,
+
+    /// Unix Domain Socket for Geth IPC
+    stream: UnixStream,
+
+    /// Accumulate partial JSON reads from IPC socket for parsing
+    framer: JsonFramer,
+
+    /// Give up on a response after this long without receiving any bytes
+    timeout: Duration,
+}
+
+impl IpcTransport {
+    /// Open the IPC socket at `ipc_path`
+    pub fn new(ipc_path: &str, timeout: Duration) -> Result<Self, GethRpcError> {
+        Ok(IpcTransport {
+            path: ipc_path.to_string(),
+            stream: Self::open(ipc_path, timeout)?,
+            framer: JsonFramer::with_capacity((16 * bytesize::MIB) as usize),
+            timeout,
+        })
+    }
+
+    fn open(ipc_path: &str, timeout: Duration) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(ipc_path)?;
+        stream.set_read_timeout(Some(Self::read_timeout(timeout)))?;
+        Ok(stream)
+    }
+
+    /// Wake up at least this often while waiting on geth, to log that we're still waiting
+    fn read_timeout(timeout: Duration) -> Duration {
+        cmp::min(Duration::new(10, 0), timeout)
+    }
+
+    /// Geth may yet answer a request we gave up waiting on, and those late bytes would be
+    /// taken for the next response. Carry on over a fresh connection instead; failing that,
+    /// shut this one so the next request finds the connection lost.
+    fn abandon_connection(&mut self, note: &str) {
+        self.framer.reset();
+
+        match Self::open(&self.path, self.timeout) {
+            Ok(stream) => self.stream = stream,
+            Err(e) => {
+                warn!("{}: can't reopen {}: {}", note, self.path, e);
+                let _ = self.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    /// Read from the socket until the framer yields a complete message
+    fn read_message(&mut self, note: &str) -> Result<JsonValue, GethRpcError> {
+        let mut buf = [0u8; 1024 * 1024];
+        let mut total_read = 0u64;
+        let mut last_read = Instant::now();
+
+        loop {
+            if let Some(msg) = self.framer.next_message()? {
+                return Ok(msg);
+            }
+
+            let size = match self.read_some(&mut buf, &mut last_read, note) {
+                Ok(0) => {
+                    return Err(FrameError::UnexpectedEof {
+                        buffered: self.framer.buffered(),
+                    }
+                    .into())
+                }
+                Ok(size) => size,
+                Err(e) => {
+                    // whatever partial response we hold is useless now
+                    self.framer.reset();
+                    return Err(e);
+                }
+            };
+
+            total_read += size as u64;
+            debug!("{}: loop read {}, total {}", note, size, total_read);
+
+            self.framer.push(&buf[..size]);
+        }
+    }
+
+    /// Read whatever is available into `buf`, waiting out read timeouts until `self.timeout`
+    /// has passed since `last_read`. Returns 0 if geth closed the socket.
+    fn read_some(
+        &mut self,
+        buf: &mut [u8],
+        last_read: &mut Instant,
+        note: &str,
+    ) -> Result<usize, GethRpcError> {
+        loop {
+            match self.stream.read(buf) {
+                Ok(size) => {
+                    *last_read = Instant::now();
+                    return Ok(size);
+                }
+
+                Err(ref e) if e.kind() == TimedOut || e.kind() == WouldBlock => {
+                    let waited = last_read.elapsed();
+                    if waited >= self.timeout {
+                        self.abandon_connection(note);
+                        return Err(GethRpcError::Timeout { waited });
+                    }
+                    warn!("Read timeout in {}, continuing", note)
+                }
+
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Transport for IpcTransport {
+    fn request(&mut self, id: u64, rpc: &str, note: &str) -> Result<JsonValue, GethRpcError> {
+        self.stream.write_all(rpc.as_bytes())?;
+
+        loop {
+            let msg = self.read_message(note)?;
+
+            if is_response_to(&msg, id) {
+                return Ok(msg);
+            }
+
+            // most likely the answer to an earlier request we gave up on
+            warn!(
+                "{}: discarding response id {}, waiting for {}",
+                note, msg["id"], id
+            );
+        }
+    }
+
+    fn stream_request(
+        &mut self,
+        _id: u64,
+        rpc: &str,
+        note: &str,
+        sink: &mut dyn ResponseSink,
+    ) -> Result<(), GethRpcError> {
+        self.stream.write_all(rpc.as_bytes())?;
+
+        // bytes of stale responses may still be buffered; the sink skips those itself
+        let pending = self.framer.take_buffered();
+        let used = sink.push(&pending)?;
+        if sink.is_complete() {
+            self.framer.push(&pending[used..]);
+            return Ok(());
+        }
+
+        let mut buf = vec![0u8; 1024 * 1024];
+        let mut total_read = 0u64;
+        let mut last_read = Instant::now();
+
+        loop {
+            let size = self.read_some(&mut buf, &mut last_read, note)?;
+            if size == 0 {
+                return Err(FrameError::UnexpectedEof {
+                    buffered: total_read as usize,
+                }
+                .into());
+            }
+
+            total_read += size as u64;
+            debug!("{}: stream read {}, total {}", note, size, total_read);
+
+            let used = sink.push(&buf[..size])?;
+            if sink.is_complete() {
+                // the start of whatever geth sends next
+                self.framer.push(&buf[used..size]);
+                return Ok(());
+            }
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        match self
+            .stream
+            .set_read_timeout(Some(Self::read_timeout(timeout)))
+        {
+            Ok(()) => self.timeout = timeout,
+            Err(e) => warn!("keeping previous IPC timeout: {}", e),
+        }
+    }
+}
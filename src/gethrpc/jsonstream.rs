@@ -0,0 +1,622 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Event-based parsing of JSON as it arrives, for responses too large to hold in memory
+//!
+//! A block trace from the struct logger can run to gigabytes. `JsonStream` is fed bytes in
+//! whatever pieces they're read and reports each token to a `JsonHandler` instead of
+//! building a `JsonValue` tree. Only the token being read is buffered, so memory use is
+//! bounded by the longest string in the message rather than by the message itself.
+//!
+
+use gethrpc::framer::FrameError;
+use gethrpc::GethRpcError;
+use json;
+use std::str;
+
+/// A token of the JSON being parsed
+#[derive(Debug)]
+pub enum JsonEvent<'a> {
+    StartObject,
+    EndObject,
+    StartArray,
+    EndArray,
+
+    /// Name of the object member whose value follows
+    Key(&'a str),
+
+    Str(&'a str),
+
+    /// Number as it's written, left for the handler to parse
+    Number(&'a str),
+
+    Bool(bool),
+    Null,
+}
+
+/// Receives the tokens of a `JsonStream`
+pub trait JsonHandler {
+    fn event(&mut self, event: JsonEvent) -> Result<(), GethRpcError>;
+}
+
+/// What may come next, outside of a string, number or literal
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    /// The `{` or `[` starting a top-level value
+    Message,
+
+    /// A member name, or `}` right after `{`
+    FirstKey,
+    Key,
+    Colon,
+
+    /// A value, or `]` right after `[`
+    FirstValue,
+    Value,
+
+    /// `,` or the closing bracket after a value
+    Separator,
+}
+
+/// The token being read
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    None,
+    Str {
+        key: bool,
+        escaped: bool,
+        has_escapes: bool,
+    },
+    Number,
+
+    /// `true`, `false` or `null`
+    Literal,
+}
+
+/// What `JsonStream::byte` did with a byte
+enum Step {
+    Consumed,
+
+    /// The byte ended a number or literal and must be read again
+    Again,
+
+    /// The byte closed a top-level value
+    Complete,
+}
+
+pub struct JsonStream {
+    expect: Expect,
+    token: Token,
+
+    /// Text of the current token
+    scratch: Vec<u8>,
+
+    /// Closing bracket expected for each currently open object/array
+    closers: Vec<u8>,
+
+    /// Stream offset of the next byte, for error reporting
+    offset: u64,
+}
+
+impl JsonStream {
+    pub fn new() -> Self {
+        JsonStream {
+            expect: Expect::Message,
+            token: Token::None,
+            scratch: Vec::new(),
+            closers: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Discard any partial value, e.g. before a request is replayed
+    pub fn reset(&mut self) {
+        self.expect = Expect::Message;
+        self.token = Token::None;
+        self.scratch.clear();
+        self.closers.clear();
+    }
+
+    /// Parse `bytes`, passing each token to `handler`. Stops after the byte that completes a
+    /// top-level value and returns how many bytes were consumed, `bytes.len()` if the value
+    /// isn't complete yet.
+    pub fn push<H: JsonHandler>(
+        &mut self,
+        bytes: &[u8],
+        handler: &mut H,
+    ) -> Result<usize, GethRpcError> {
+        let mut idx = 0;
+
+        while idx < bytes.len() {
+            match self.byte(bytes[idx], handler)? {
+                Step::Again => continue,
+                Step::Consumed => (),
+                Step::Complete => {
+                    self.offset += 1;
+                    return Ok(idx + 1);
+                }
+            }
+
+            idx += 1;
+            self.offset += 1;
+        }
+
+        Ok(idx)
+    }
+
+    fn byte<H: JsonHandler>(&mut self, byte: u8, handler: &mut H) -> Result<Step, GethRpcError> {
+        match self.token {
+            Token::Str {
+                key,
+                escaped,
+                has_escapes,
+            } => {
+                if escaped {
+                    self.token = Token::Str {
+                        key,
+                        escaped: false,
+                        has_escapes,
+                    };
+                } else if byte == b'\\' {
+                    self.token = Token::Str {
+                        key,
+                        escaped: true,
+                        has_escapes: true,
+                    };
+                } else if byte == b'"' {
+                    self.token = Token::None;
+                    self.end_string(key, has_escapes, handler)?;
+                    return Ok(Step::Consumed);
+                }
+
+                self.scratch.push(byte);
+                return Ok(Step::Consumed);
+            }
+
+            Token::Number => {
+                if is_number_byte(byte) {
+                    self.scratch.push(byte);
+                    return Ok(Step::Consumed);
+                }
+
+                self.token = Token::None;
+                self.end_number(handler)?;
+                return Ok(Step::Again);
+            }
+
+            Token::Literal => {
+                if byte.is_ascii_lowercase() {
+                    self.scratch.push(byte);
+                    return Ok(Step::Consumed);
+                }
+
+                self.token = Token::None;
+                self.end_literal(handler)?;
+                return Ok(Step::Again);
+            }
+
+            Token::None => (),
+        }
+
+        if is_whitespace(byte) {
+            return Ok(Step::Consumed);
+        }
+
+        match self.expect {
+            Expect::Message => match byte {
+                b'{' | b'[' => self.open(byte, handler)?,
+                _ => return Err(self.unexpected(byte)),
+            },
+
+            Expect::FirstKey | Expect::Key => match byte {
+                b'"' => self.start_token(Token::Str {
+                    key: true,
+                    escaped: false,
+                    has_escapes: false,
+                }),
+                b'}' if self.expect == Expect::FirstKey => return self.close(byte, handler),
+                _ => return Err(self.unexpected(byte)),
+            },
+
+            Expect::Colon => match byte {
+                b':' => self.expect = Expect::Value,
+                _ => return Err(self.unexpected(byte)),
+            },
+
+            Expect::FirstValue | Expect::Value => match byte {
+                b'{' | b'[' => self.open(byte, handler)?,
+                b'"' => self.start_token(Token::Str {
+                    key: false,
+                    escaped: false,
+                    has_escapes: false,
+                }),
+                b'-' | b'0'..=b'9' => {
+                    self.start_token(Token::Number);
+                    self.scratch.push(byte);
+                }
+                b't' | b'f' | b'n' => {
+                    self.start_token(Token::Literal);
+                    self.scratch.push(byte);
+                }
+                b']' if self.expect == Expect::FirstValue => return self.close(byte, handler),
+                _ => return Err(self.unexpected(byte)),
+            },
+
+            Expect::Separator => match byte {
+                b',' => {
+                    self.expect = if self.closers.last() == Some(&b'}') {
+                        Expect::Key
+                    } else {
+                        Expect::Value
+                    }
+                }
+                b'}' | b']' => return self.close(byte, handler),
+                _ => return Err(self.unexpected(byte)),
+            },
+        }
+
+        Ok(Step::Consumed)
+    }
+
+    fn start_token(&mut self, token: Token) {
+        self.token = token;
+        self.scratch.clear();
+    }
+
+    fn open<H: JsonHandler>(&mut self, byte: u8, handler: &mut H) -> Result<(), GethRpcError> {
+        if byte == b'{' {
+            self.closers.push(b'}');
+            self.expect = Expect::FirstKey;
+            handler.event(JsonEvent::StartObject)
+        } else {
+            self.closers.push(b']');
+            self.expect = Expect::FirstValue;
+            handler.event(JsonEvent::StartArray)
+        }
+    }
+
+    fn close<H: JsonHandler>(&mut self, byte: u8, handler: &mut H) -> Result<Step, GethRpcError> {
+        if self.closers.pop() != Some(byte) {
+            return Err(self.unexpected(byte));
+        }
+
+        if byte == b'}' {
+            handler.event(JsonEvent::EndObject)?;
+        } else {
+            handler.event(JsonEvent::EndArray)?;
+        }
+
+        if self.closers.is_empty() {
+            self.expect = Expect::Message;
+            Ok(Step::Complete)
+        } else {
+            self.expect = Expect::Separator;
+            Ok(Step::Consumed)
+        }
+    }
+
+    fn end_string<H: JsonHandler>(
+        &mut self,
+        key: bool,
+        has_escapes: bool,
+        handler: &mut H,
+    ) -> Result<(), GethRpcError> {
+        self.expect = if key {
+            Expect::Colon
+        } else {
+            Expect::Separator
+        };
+
+        let unescaped;
+        let text = if has_escapes {
+            // the json crate already knows how to decode escapes and surrogate pairs
+            let quoted = format!("\"{}\"", self.text()?);
+            unescaped = json::parse(&quoted)?.take_string().unwrap_or_default();
+            &unescaped
+        } else {
+            self.text()?
+        };
+
+        if key {
+            handler.event(JsonEvent::Key(text))
+        } else {
+            handler.event(JsonEvent::Str(text))
+        }
+    }
+
+    fn end_number<H: JsonHandler>(&mut self, handler: &mut H) -> Result<(), GethRpcError> {
+        self.expect = Expect::Separator;
+
+        handler.event(JsonEvent::Number(self.text()?))
+    }
+
+    fn end_literal<H: JsonHandler>(&mut self, handler: &mut H) -> Result<(), GethRpcError> {
+        self.expect = Expect::Separator;
+
+        let event = match &self.scratch[..] {
+            b"true" => JsonEvent::Bool(true),
+            b"false" => JsonEvent::Bool(false),
+            b"null" => JsonEvent::Null,
+            _ => return Err(self.unexpected(self.scratch[0])),
+        };
+
+        handler.event(event)
+    }
+
+    fn text(&self) -> Result<&str, GethRpcError> {
+        str::from_utf8(&self.scratch).map_err(|e| {
+            FrameError::InvalidUtf8 {
+                offset: e.valid_up_to(),
+            }
+            .into()
+        })
+    }
+
+    fn unexpected(&self, byte: u8) -> GethRpcError {
+        FrameError::UnexpectedByte {
+            byte,
+            offset: self.offset,
+        }
+        .into()
+    }
+}
+
+impl Default for JsonStream {
+    fn default() -> Self {
+        JsonStream::new()
+    }
+}
+
+fn is_number_byte(b: u8) -> bool {
+    b.is_ascii_digit() || b"-+.eE".contains(&b)
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\n' || b == b'\r' || b == b'\t'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes down every event, e.g. `k:id` and `n:1`
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl JsonHandler for Recorder {
+        fn event(&mut self, event: JsonEvent) -> Result<(), GethRpcError> {
+            self.events.push(match event {
+                JsonEvent::StartObject => "{".to_string(),
+                JsonEvent::EndObject => "}".to_string(),
+                JsonEvent::StartArray => "[".to_string(),
+                JsonEvent::EndArray => "]".to_string(),
+                JsonEvent::Key(k) => format!("k:{}", k),
+                JsonEvent::Str(s) => format!("s:{}", s),
+                JsonEvent::Number(n) => format!("n:{}", n),
+                JsonEvent::Bool(b) => format!("b:{}", b),
+                JsonEvent::Null => "null".to_string(),
+            });
+            Ok(())
+        }
+    }
+
+    /// Events of a single message fed `piece` bytes at a time
+    fn events_in_pieces(bytes: &[u8], piece: usize) -> Vec<String> {
+        let mut stream = JsonStream::new();
+        let mut recorder = Recorder::default();
+
+        for chunk in bytes.chunks(piece) {
+            assert_eq!(stream.push(chunk, &mut recorder).unwrap(), chunk.len());
+        }
+
+        recorder.events
+    }
+
+    /// `text` gives the same events however it's split up
+    fn assert_any_split(text: &str, expected: &[&str]) {
+        for piece in 1..=text.len() {
+            assert_eq!(
+                events_in_pieces(text.as_bytes(), piece),
+                expected,
+                "pieces of {}",
+                piece
+            );
+        }
+    }
+
+    /// Bytes used by each push of `pieces` and the events they produced
+    fn push_pieces(stream: &mut JsonStream, pieces: &[&str]) -> Vec<(usize, Vec<String>)> {
+        pieces
+            .iter()
+            .map(|piece| {
+                let mut recorder = Recorder::default();
+                let used = stream.push(piece.as_bytes(), &mut recorder).unwrap();
+                (used, recorder.events)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nested_containers() {
+        assert_any_split(
+            r#"[[], [[1, [2]], {}], {"a": [{"b": []}], "c": {}}, "d"]"#,
+            &[
+                "[", "[", "]", "[", "[", "n:1", "[", "n:2", "]", "]", "{", "}", "]", "{", "k:a",
+                "[", "{", "k:b", "[", "]", "}", "]", "k:c", "{", "}", "}", "s:d", "]",
+            ],
+        );
+    }
+
+    #[test]
+    fn numbers_and_literals_split_across_reads() {
+        let mut stream = JsonStream::new();
+
+        // a number or literal is only known to have ended at the byte after it
+        let pushes = push_pieces(
+            &mut stream,
+            &[
+                r#"{"gas":12"#,
+                "3",
+                r#"45,"x":-1.5"#,
+                "e-7,",
+                r#""ok":tr"#,
+                "ue}",
+            ],
+        );
+        let events: Vec<Vec<&str>> = pushes
+            .iter()
+            .map(|(_, events)| events.iter().map(String::as_str).collect())
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                vec!["{", "k:gas"],
+                vec![],
+                vec!["n:12345", "k:x"],
+                vec!["n:-1.5e-7"],
+                vec!["k:ok"],
+                vec!["b:true", "}"],
+            ]
+        );
+
+        // and may end at a closing bracket, or be followed by whitespace
+        assert_any_split("[0,-2 ,3e2\n]", &["[", "n:0", "n:-2", "n:3e2", "]"]);
+        assert_any_split("[null,false ]", &["[", "null", "b:false", "]"]);
+    }
+
+    #[test]
+    fn keys_precede_their_values() {
+        // the shape of a struct logger trace: `StructLogStream` picks the scope of each
+        // object and array by the key just before it, and array members have none
+        assert_any_split(
+            concat!(
+                r#"{"id":1,"result":[{"error":"execution timeout"},"#,
+                r#"{"result":{"gas":21000,"structLogs":[]}},"#,
+                r#"{"result":{"structLogs":[{"op":"ADD","stack":["0x1"],"storage":{}}]}}]}"#
+            ),
+            &[
+                "{",
+                "k:id",
+                "n:1",
+                "k:result",
+                "[",
+                "{",
+                "k:error",
+                "s:execution timeout",
+                "}",
+                "{",
+                "k:result",
+                "{",
+                "k:gas",
+                "n:21000",
+                "k:structLogs",
+                "[",
+                "]",
+                "}",
+                "}",
+                "{",
+                "k:result",
+                "{",
+                "k:structLogs",
+                "[",
+                "{",
+                "k:op",
+                "s:ADD",
+                "k:stack",
+                "[",
+                "s:0x1",
+                "]",
+                "k:storage",
+                "{",
+                "}",
+                "}",
+                "]",
+                "}",
+                "}",
+                "]",
+                "}",
+            ],
+        );
+    }
+
+    #[test]
+    fn strings_unescaped() {
+        assert_any_split(
+            r#"{"k\"ey":"a \" quote, a \\ backslash, é 😀\n","op":"café €5"}"#,
+            &[
+                "{",
+                "k:k\"ey",
+                "s:a \" quote, a \\ backslash, \u{e9} \u{1f600}\n",
+                "k:op",
+                "s:caf\u{e9} \u{20ac}5",
+                "}",
+            ],
+        );
+    }
+
+    #[test]
+    fn push_stops_after_each_value() {
+        let bytes = "{\"id\":1} \n[2,3]{\"id\":4}";
+        let mut stream = JsonStream::new();
+
+        // whitespace before the next value is skipped
+        let pushes = push_pieces(&mut stream, &[bytes, &bytes[8..], &bytes[15..]]);
+        let used: Vec<usize> = pushes.iter().map(|&(used, _)| used).collect();
+
+        assert_eq!(used, vec![8, 7, 8]);
+        assert_eq!(pushes[1].1, vec!["[", "n:2", "n:3", "]"]);
+    }
+
+    #[test]
+    fn grammar_errors() {
+        for &(text, byte, offset) in &[
+            (&b"]"[..], b']', 0),
+            (&b"{1:2}"[..], b'1', 1),
+            (&b"{\"a\" 1}"[..], b'1', 5),
+            (&b"[1 2]"[..], b'2', 3),
+            (&b"[tru]"[..], b't', 4),
+            (&b"{\"a\":[1}"[..], b'}', 7),
+            (&b"{\"a\":1]"[..], b']', 6),
+            (&b"[1,]"[..], b']', 3),
+        ] {
+            let mut stream = JsonStream::new();
+
+            match stream.push(text, &mut Recorder::default()) {
+                Err(GethRpcError::Malformed(FrameError::UnexpectedByte { byte: b, offset: o })) => {
+                    assert_eq!((b, o), (byte, offset), "{:?}", str::from_utf8(text));
+                }
+                other => panic!("expected an unexpected byte, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn reset_discards_partial_message() {
+        let mut stream = JsonStream::new();
+        let mut recorder = Recorder::default();
+
+        stream
+            .push(br#"{"id":1,"result":[tr"#, &mut recorder)
+            .unwrap();
+        stream.reset();
+        recorder.events.clear();
+
+        stream.push(br#"{"id":2}"#, &mut recorder).unwrap();
+        assert_eq!(recorder.events, vec!["{", "k:id", "n:2", "}"]);
+    }
+}
@@ -0,0 +1,1298 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Provides Geth RPC calls over IPC, HTTP or WebSocket
+//!
+
+use json;
+use json::JsonValue;
+use std::cmp;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use std::u64;
+use util::{hex_to_bytes, hex_to_u128, hex_to_u64};
+
+pub mod error;
+pub mod framer;
+pub mod histogram;
+pub mod http;
+pub mod ipc;
+pub mod jsonstream;
+pub mod structlog;
+pub mod ws;
+
+pub use self::error::GethRpcError;
+pub use self::structlog::TxnTally;
+
+use self::http::HttpTransport;
+use self::ipc::IpcTransport;
+use self::structlog::StructLogStream;
+use self::ws::WsTransport;
+
+/// JavaScript tracer that makes geth return per-opcode count and gas histograms instead of
+/// every step, see `GethRpc::set_tracer`
+pub const HISTOGRAM_TRACER: &str = include_str!("histogram.js");
+
+/// Implementations carry JSON-RPC requests to geth over a specific channel
+pub trait Transport {
+    /// Send the JSON-RPC request `rpc` and return geth's response to it, identified by `id`;
+    /// `note` labels log output
+    fn request(&mut self, id: u64, rpc: &str, note: &str) -> Result<JsonValue, GethRpcError>;
+
+    /// Give up on a response after `timeout` without hearing from geth
+    fn set_timeout(&mut self, timeout: Duration);
+
+    /// Like `request`, but hand geth's response to `sink` piece by piece as it's received
+    /// instead of parsing it whole. Transports that only receive whole messages pass on the
+    /// complete response.
+    fn stream_request(
+        &mut self,
+        id: u64,
+        rpc: &str,
+        note: &str,
+        sink: &mut dyn ResponseSink,
+    ) -> Result<(), GethRpcError> {
+        let msg = self.request(id, rpc, note)?;
+
+        sink.push(msg.dump().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Can geth push `eth_subscription` notifications over this transport?
+    fn supports_subscriptions(&self) -> bool {
+        false
+    }
+
+    /// Wait up to `timeout` for the next `eth_subscription` notification. `Ok(None)` means
+    /// nothing arrived in time, an `Err` means the subscription is gone.
+    fn next_notification(&mut self, _timeout: Duration) -> Result<Option<JsonValue>, GethRpcError> {
+        Err(GethRpcError::Io(io::Error::other(
+            "transport does not support subscriptions",
+        )))
+    }
+}
+
+/// Consumes a response as it arrives, see `Transport::stream_request`
+pub trait ResponseSink {
+    /// Take the next bytes received from geth, returning how many were used. Once the
+    /// response is complete anything after it (the start of another message) is left over.
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, GethRpcError>;
+
+    /// Has the whole response arrived?
+    fn is_complete(&self) -> bool;
+
+    /// Forget a partial response, before the request is replayed
+    fn reset(&mut self);
+}
+
+/// Is `msg` geth's response to the request with the given `id`? Geth can only answer a
+/// request it couldn't parse with a `null` id, so such errors are taken to be ours. A batch
+/// is identified by its first id; the response array answers it if any member carries that id.
+pub fn is_response_to(msg: &JsonValue, id: u64) -> bool {
+    if msg.is_array() {
+        return msg.members().any(|m| m["id"] == id);
+    }
+
+    match msg["id"].as_u64() {
+        Some(v) => v == id,
+        None => msg["id"].is_null() && !msg["error"].is_null(),
+    }
+}
+
+/// Parse the `0x` hex quantity `name` of `obj`
+fn hex_field(obj: &JsonValue, name: &str) -> Result<u64, GethRpcError> {
+    obj[name]
+        .as_str()
+        .and_then(hex_to_u64)
+        .ok_or_else(|| GethRpcError::missing(name))
+}
+
+/// Text of a JSON-RPC request for `method` with `params` (a JSON array)
+fn rpc_text(method: &str, params: &str, id: u64) -> String {
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"{}\",\"params\":{},\"id\":{}}}",
+        method, params, id
+    )
+}
+
+/// The `result` of a JSON-RPC response, or its `error`
+fn take_result(mut response: JsonValue) -> Result<JsonValue, GethRpcError> {
+    if !response["error"].is_null() {
+        return Err(GethRpcError::from_rpc_error(&response["error"]));
+    }
+
+    Ok(response["result"].take())
+}
+
+/// `full_txns` asks for transaction objects rather than just their hashes
+fn block_params(block_num: u64, full_txns: bool) -> String {
+    format!("[\"{:#x}\",{}]", block_num, full_txns)
+}
+
+/// Default struct logger unless `tracer` (JavaScript code or a native tracer's name) is
+/// given. Geth stops tracing a transaction after `timeout`, 5 seconds unless told otherwise,
+/// which a heavy transaction under a JavaScript tracer easily exceeds.
+fn trace_params(block_num: u64, tracer: Option<&str>, timeout: Duration) -> String {
+    match tracer {
+        Some(tracer) => format!(
+            "[\"{:#x}\",{{\"tracer\":{},\"timeout\":\"{}ms\"}}]",
+            block_num,
+            json::stringify(tracer),
+            timeout.as_millis()
+        ),
+        None => format!(
+            "[\"{:#x}\",{{\"disableStorage\":true,\"disableStack\":true,\"disableMemory\":true,\
+             \"timeout\":\"{}ms\"}}]",
+            block_num,
+            timeout.as_millis()
+        ),
+    }
+}
+
+fn call_trace_params(block_num: u64) -> String {
+    format!("[\"{:#x}\",{{\"tracer\":\"callTracer\"}}]", block_num)
+}
+
+fn receipts_params(block_num: u64) -> String {
+    format!("[\"{:#x}\"]", block_num)
+}
+
+/// Parse a block header; `txns` is left empty
+fn parse_block_info(block_num: u64, block_info: &JsonValue) -> Result<BlockInfo, GethRpcError> {
+    if block_info.is_null() {
+        return Err(GethRpcError::MissingField(format!("block {}", block_num)));
+    }
+
+    // absent before London
+    let base_fee = opt_hex_field(block_info, "baseFeePerGas")?;
+    let difficulty = block_info["difficulty"]
+        .as_str()
+        .and_then(hex_to_u128)
+        .ok_or_else(|| GethRpcError::missing("difficulty"))?;
+
+    if !block_info["transactions"].is_array() {
+        return Err(GethRpcError::missing("transactions"));
+    }
+
+    Ok(BlockInfo {
+        block_num,
+        time_stamp: hex_field(block_info, "timestamp")?,
+        gas_limit: hex_field(block_info, "gasLimit")?,
+        gas_used: hex_field(block_info, "gasUsed")?,
+        miner: string_field(block_info, "miner")?,
+        hash: string_field(block_info, "hash")?,
+        parent_hash: string_field(block_info, "parentHash")?,
+        base_fee,
+        difficulty,
+        size: hex_field(block_info, "size")?,
+        txn_count: block_info["transactions"].len() as u32,
+        txns: Vec::new(),
+    })
+}
+
+/// Parse a block fetched with full transaction objects, including its `txns`
+fn parse_block_with_txns(
+    block_num: u64,
+    mut block_info: JsonValue,
+) -> Result<BlockInfo, GethRpcError> {
+    let mut block = parse_block_info(block_num, &block_info)?;
+    let base_fee = block.base_fee;
+
+    block.txns = block_info["transactions"]
+        .members_mut()
+        .enumerate()
+        .map(|(idx, txn)| parse_txn_info(block_num, idx as u32, txn.take(), base_fee))
+        .collect::<Result<_, _>>()?;
+
+    Ok(block)
+}
+
+/// Parse a transaction object. `base_fee` is that of the containing block, when known.
+fn parse_txn_info(
+    block_num: u64,
+    block_index: u32,
+    mut txn_info: JsonValue,
+    base_fee: Option<u64>,
+) -> Result<TxnInfo, GethRpcError> {
+    if txn_info.is_null() {
+        return Err(GethRpcError::MissingField(format!(
+            "transaction {} of block {}",
+            block_index, block_num
+        )));
+    }
+
+    let from = txn_info["from"]
+        .take_string()
+        .ok_or_else(|| GethRpcError::missing("from"))?;
+    // `to` is null for contract creation
+    let to = txn_info["to"].take_string().unwrap_or_default();
+    let hash = txn_info["hash"]
+        .take_string()
+        .ok_or_else(|| GethRpcError::missing("hash"))?;
+    let value = txn_info["value"]
+        .as_str()
+        .and_then(hex_to_u128)
+        .ok_or_else(|| GethRpcError::missing("value"))?;
+    let (input_size, input_zero_bytes) = match txn_info["input"].as_str() {
+        Some(input) if input.starts_with("0x") => {
+            let data = &input.as_bytes()[2..];
+            let zeros = data.chunks(2).filter(|byte| *byte == b"00").count();
+            (data.len() / 2, zeros)
+        }
+        _ => return Err(GethRpcError::missing("input")),
+    };
+    // access lists arrived with Berlin (type 1), authorizations with Prague (type 4)
+    let access_list_addresses = txn_info["accessList"].len() as u32;
+    let access_list_keys = txn_info["accessList"]
+        .members()
+        .map(|entry| entry["storageKeys"].len() as u32)
+        .sum();
+    let authorizations = txn_info["authorizationList"].len() as u32;
+    // pre-Berlin nodes don't report a type; everything was legacy then
+    let txn_type = match txn_info["type"].as_str() {
+        Some(t) => match hex_to_u64(t) {
+            Some(n) if n <= u64::from(u8::MAX) => n as u8,
+            _ => return Err(GethRpcError::invalid("type", t)),
+        },
+        None => 0,
+    };
+    let gas_price = hex_field(&txn_info, "gasPrice")?;
+    let max_fee = opt_hex_field(&txn_info, "maxFeePerGas")?;
+    let max_priority_fee = opt_hex_field(&txn_info, "maxPriorityFeePerGas")?;
+    // geth reports a mined transaction's `gasPrice` as the price it actually paid, but
+    // derive that ourselves whenever the block's base fee is at hand
+    let effective_gas_price = match (max_fee, max_priority_fee, base_fee) {
+        (Some(max), Some(priority), Some(base)) => cmp::min(max, base + priority),
+        _ => gas_price,
+    };
+
+    Ok(TxnInfo {
+        block_num,
+        block_index,
+        gas_price,
+        from,
+        to,
+        hash,
+        nonce: hex_field(&txn_info, "nonce")?,
+        value,
+        input_size,
+        input_zero_bytes,
+        access_list_addresses,
+        access_list_keys,
+        authorizations,
+        gas_limit: hex_field(&txn_info, "gas")?,
+        txn_type,
+        max_fee,
+        max_priority_fee,
+        effective_gas_price,
+        receipt: None,
+    })
+}
+
+/// Parse the receipts of all transactions in `block_info`, in order
+fn parse_receipts(
+    block_info: &BlockInfo,
+    mut receipts: JsonValue,
+) -> Result<Vec<ReceiptInfo>, GethRpcError> {
+    if !receipts.is_array() || receipts.len() != block_info.txns.len() {
+        return Err(GethRpcError::MissingField(format!(
+            "receipts of block {}",
+            block_info.block_num
+        )));
+    }
+
+    receipts
+        .members_mut()
+        .map(|receipt| parse_receipt(receipt.take()))
+        .collect()
+}
+
+fn parse_receipt(receipt: JsonValue) -> Result<ReceiptInfo, GethRpcError> {
+    if receipt.is_null() {
+        return Err(GethRpcError::missing("receipt"));
+    }
+
+    if !receipt["logs"].is_array() {
+        return Err(GethRpcError::missing("logs"));
+    }
+
+    Ok(ReceiptInfo {
+        // pre-Byzantium receipts carry a state root instead
+        status: opt_hex_field(&receipt, "status")?.map(|status| status == 1),
+        gas_used: hex_field(&receipt, "gasUsed")?,
+        cumulative_gas_used: hex_field(&receipt, "cumulativeGasUsed")?,
+        log_count: receipt["logs"].len() as u32,
+    })
+}
+
+/// Parse the `0x` hex quantity `name` of `obj` if present; only some blocks and transaction
+/// types have it
+fn opt_hex_field(obj: &JsonValue, name: &str) -> Result<Option<u64>, GethRpcError> {
+    if obj[name].is_null() {
+        return Ok(None);
+    }
+
+    hex_field(obj, name).map(Some)
+}
+
+/// Flatten the `callTracer` frame `frame` and its sub-calls, depth first, into `frames`
+fn parse_call_frames(
+    block_num: u64,
+    txn_index: u32,
+    frame: &JsonValue,
+    depth: u32,
+    parent: Option<u32>,
+    frames: &mut Vec<CallFrame>,
+) -> Result<(), GethRpcError> {
+    let frame_index = frames.len() as u32;
+
+    // DELEGATECALL and STATICCALL carry no value
+    let value = match frame["value"].as_str() {
+        Some(value) => Some(hex_to_u128(value).ok_or_else(|| GethRpcError::missing("value"))?),
+        None => None,
+    };
+
+    frames.push(CallFrame {
+        block_num,
+        txn_index,
+        frame_index,
+        depth,
+        parent,
+        call_type: string_field(frame, "type")?,
+        from: string_field(frame, "from")?,
+        // a failed CREATE has no address
+        to: frame["to"].as_str().unwrap_or("").to_string(),
+        value,
+        gas: hex_field(frame, "gas")?,
+        gas_used: hex_field(frame, "gasUsed")?,
+        error: frame["error"].as_str().map(|e| e.to_string()),
+    });
+
+    for call in frame["calls"].members() {
+        parse_call_frames(
+            block_num,
+            txn_index,
+            call,
+            depth + 1,
+            Some(frame_index),
+            frames,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The string `name` of `obj`, e.g. an address or hash
+fn string_field(obj: &JsonValue, name: &str) -> Result<String, GethRpcError> {
+    obj[name]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| GethRpcError::missing(name))
+}
+
+/// Open the transport for `url`, chosen by scheme
+fn connect(url: &str, timeout: Duration) -> Result<Box<dyn Transport>, GethRpcError> {
+    let transport: Box<dyn Transport> = if url.starts_with("http://") || url.starts_with("https://")
+    {
+        Box::new(HttpTransport::new(url, timeout)?)
+    } else if url.starts_with("ws://") || url.starts_with("wss://") {
+        Box::new(WsTransport::new(url, timeout)?)
+    } else if let Some(path) = url.strip_prefix("ipc://") {
+        Box::new(IpcTransport::new(path, timeout)?)
+    } else {
+        Box::new(IpcTransport::new(url, timeout)?)
+    };
+
+    Ok(transport)
+}
+
+/// How hard to try re-establishing a lost connection to geth
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    /// Connection attempts before giving up; 0 disables reconnecting
+    pub max_attempts: u32,
+
+    /// Wait before the first attempt, doubled after every failure
+    pub initial_delay: Duration,
+
+    /// Upper bound on the wait between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for Reconnect {
+    /// Ride out a geth restart: about 10 minutes of attempts
+    fn default() -> Self {
+        Reconnect {
+            max_attempts: 15,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+///
+/// Geth RPC interactions
+///
+pub struct GethRpc {
+    transport: Box<dyn Transport>,
+
+    /// Where geth was found, for reconnecting
+    url: String,
+
+    timeout: Duration,
+    reconnect: Reconnect,
+
+    /// Do we hold a `newHeads` subscription that has to survive reconnects?
+    new_heads: bool,
+
+    /// Cleared once geth turns out not to have `eth_getBlockReceipts`
+    block_receipts: bool,
+
+    /// Tracer for `trace_block` and `traced_block`, `None` for the struct logger
+    tracer: Option<String>,
+
+    /// JSON-RPC id of the next request
+    next_id: u64,
+}
+
+/// Interesting Block information
+#[derive(Debug)]
+pub struct BlockInfo {
+    pub block_num: u64,
+    pub time_stamp: u64,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+
+    /// Fee recipient (coinbase)
+    pub miner: String,
+    pub hash: String,
+    pub parent_hash: String,
+
+    /// EIP-1559 base fee in wei, `None` before London
+    pub base_fee: Option<u64>,
+
+    /// Zero since the merge
+    pub difficulty: u128,
+
+    /// RLP encoded size in bytes
+    pub size: u64,
+    pub txn_count: u32,
+
+    /// The block's transactions in order, when fetched with them (`block_with_txns`)
+    pub txns: Vec<TxnInfo>,
+}
+
+/// Interesting transaction info
+#[derive(Debug)]
+pub struct TxnInfo {
+    pub block_num: u64,
+    pub block_index: u32,
+
+    /// As reported by geth; for EIP-1559 transactions prefer `effective_gas_price`
+    pub gas_price: u64,
+    pub from: String,
+    pub to: String,
+    pub hash: String,
+    pub nonce: u64,
+
+    /// Wei transferred
+    pub value: u128,
+
+    /// Bytes of call data (or init code for contract creation)
+    pub input_size: usize,
+
+    /// How many of the `input_size` bytes are zero; they're cheaper
+    pub input_zero_bytes: usize,
+
+    /// EIP-2930 access list entries, and the storage keys listed across them
+    pub access_list_addresses: u32,
+    pub access_list_keys: u32,
+
+    /// EIP-7702 authorization list entries
+    pub authorizations: u32,
+
+    /// Gas the sender allowed the transaction, not what it used
+    pub gas_limit: u64,
+
+    /// EIP-2718 transaction type, 0 for legacy transactions
+    pub txn_type: u8,
+
+    /// `maxFeePerGas` of EIP-1559 (type 2 and later) transactions
+    pub max_fee: Option<u64>,
+
+    /// `maxPriorityFeePerGas` of EIP-1559 (type 2 and later) transactions
+    pub max_priority_fee: Option<u64>,
+
+    /// Wei per gas actually paid: `min(max_fee, base fee + max_priority_fee)`, or the gas
+    /// price of legacy transactions. Of this the block's base fee is burned, the rest
+    /// tips the miner.
+    pub effective_gas_price: u64,
+
+    /// Outcome of the transaction, when fetched (`traced_block` and `block_with_receipts`)
+    pub receipt: Option<ReceiptInfo>,
+}
+
+/// Interesting transaction receipt info
+#[derive(Debug)]
+pub struct ReceiptInfo {
+    /// `false` if the transaction reverted; `None` before Byzantium
+    pub status: Option<bool>,
+
+    /// Gas charged to the sender, after refunds
+    pub gas_used: u64,
+
+    /// Gas used by this and all preceding transactions of the block
+    pub cumulative_gas_used: u64,
+    pub log_count: u32,
+}
+
+/// One call frame of a transaction, from geth's `callTracer`
+#[derive(Debug)]
+pub struct CallFrame {
+    pub block_num: u64,
+    pub txn_index: u32,
+
+    /// Position in the transaction's call tree, depth first; the top-level call is 0
+    pub frame_index: u32,
+
+    /// 1 for the top-level call
+    pub depth: u32,
+
+    /// `frame_index` of the calling frame, `None` for the top-level call
+    pub parent: Option<u32>,
+
+    /// CALL, CALLCODE, DELEGATECALL, STATICCALL, CREATE, CREATE2 or SELFDESTRUCT
+    pub call_type: String,
+    pub from: String,
+    pub to: String,
+
+    /// Wei transferred, `None` for frame types that can't carry value
+    pub value: Option<u128>,
+
+    /// Gas given to the frame and gas it used, including its sub-calls
+    pub gas: u64,
+    pub gas_used: u64,
+
+    /// Why the frame failed, e.g. "execution reverted"
+    pub error: Option<String>,
+}
+
+impl GethRpc {
+    /// JSON-RPC error code for calling a method the node doesn't provide
+    const METHOD_NOT_FOUND: i64 = -32601;
+
+    /// How long to wait on a silent geth before giving up on a request. Tracing a heavy
+    /// block can keep geth busy for minutes before the first byte of the response.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+    /// Connect to geth at `url`, choosing the transport by scheme: `ipc://`, `http://`,
+    /// `https://`, `ws://` or `wss://`. A bare path is treated as an IPC socket.
+    pub fn new(url: &str) -> Result<Self, GethRpcError> {
+        let timeout = Self::DEFAULT_TIMEOUT;
+
+        Ok(GethRpc {
+            transport: connect(url, timeout)?,
+            url: url.to_string(),
+            timeout,
+            reconnect: Reconnect::default(),
+            new_heads: false,
+            block_receipts: true,
+            tracer: None,
+            next_id: 1,
+        })
+    }
+
+    /// Give up on requests after `timeout` without hearing from geth
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.transport.set_timeout(timeout);
+    }
+
+    /// Change how lost connections are re-established
+    pub fn set_reconnect(&mut self, reconnect: Reconnect) {
+        self.reconnect = reconnect;
+    }
+
+    /// Trace blocks with `tracer`, JavaScript code (e.g. `HISTOGRAM_TRACER`) or the name of
+    /// a native tracer built into geth, rather than the default struct logger
+    pub fn set_tracer(&mut self, tracer: Option<&str>) {
+        self.tracer = tracer.map(|t| t.to_string());
+    }
+
+    /// Latest synchronized block number from Geth `eth.blockNumber` call
+    pub fn get_latest_block(&mut self) -> Result<u64, GethRpcError> {
+        let result = self.call("eth_blockNumber", "[]", "eth.blockNumber")?;
+
+        result
+            .as_str()
+            .and_then(hex_to_u64)
+            .ok_or_else(|| GethRpcError::missing("result"))
+    }
+
+    /// EIP-155 id of the chain geth is on, from `eth_chainId`
+    pub fn chain_id(&mut self) -> Result<u64, GethRpcError> {
+        let result = self.call("eth_chainId", "[]", "eth_chainId")?;
+
+        result
+            .as_str()
+            .and_then(hex_to_u64)
+            .ok_or_else(|| GethRpcError::missing("result"))
+    }
+
+    /// Contract code at `address` as of block `block_num`, or the latest block; empty for
+    /// an account without code
+    pub fn get_code(
+        &mut self,
+        address: &str,
+        block_num: Option<u64>,
+    ) -> Result<Vec<u8>, GethRpcError> {
+        let block = match block_num {
+            Some(block_num) => format!("{:#x}", block_num),
+            None => "latest".to_string(),
+        };
+        let params = json::stringify(vec![address.to_string(), block]);
+
+        let result = self.call("eth_getCode", &params, "get_code")?;
+
+        result
+            .as_str()
+            .and_then(hex_to_bytes)
+            .ok_or_else(|| GethRpcError::missing("result"))
+    }
+
+    /// Subscribe to geth's `newHeads` feed. Returns the subscription id, or `None` if the
+    /// transport can't deliver notifications.
+    pub fn subscribe_new_heads(&mut self) -> Result<Option<String>, GethRpcError> {
+        if !self.transport.supports_subscriptions() {
+            return Ok(None);
+        }
+
+        let mut result = self.call("eth_subscribe", r#"["newHeads"]"#, "eth_subscribe")?;
+
+        match result.take_string() {
+            Some(id) => {
+                self.new_heads = true;
+                Ok(Some(id))
+            }
+            None => Err(GethRpcError::missing("result")),
+        }
+    }
+
+    /// Wait up to `timeout` for the next `newHeads` notification and return its block number.
+    /// `Ok(None)` if no new block arrived in time, or if the connection had to be
+    /// re-established (blocks may have been missed meanwhile); an `Err` means the
+    /// subscription is gone.
+    pub fn next_new_head(&mut self, timeout: Duration) -> Result<Option<u64>, GethRpcError> {
+        if !self.new_heads {
+            return Err(GethRpcError::missing("newHeads subscription"));
+        }
+
+        let msg = match self.transport.next_notification(timeout) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Ok(None),
+            Err(GethRpcError::Io(e)) => {
+                warn!("newHeads: lost connection to geth: {}", e);
+                self.reconnect()?;
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        hex_field(&msg["params"]["result"], "number").map(Some)
+    }
+
+    /// Obtain `BlockInfo` for the provided block number
+    pub fn block_info(&mut self, block_num: u64) -> Result<BlockInfo, GethRpcError> {
+        let block_info = self.get_block(block_num)?;
+
+        parse_block_info(block_num, &block_info)
+    }
+
+    /// Obtain `BlockInfo` for the provided block number along with all its transactions,
+    /// from a single `eth.getBlockByNumber` call
+    pub fn block_with_txns(&mut self, block_num: u64) -> Result<BlockInfo, GethRpcError> {
+        let block_info = self.call(
+            "eth_getBlockByNumber",
+            &block_params(block_num, true),
+            "block_with_txns",
+        )?;
+
+        parse_block_with_txns(block_num, block_info)
+    }
+
+    /// Call Geth `debug.traceBlockByNumber`, returning the per-transaction traces
+    pub fn trace_block(&mut self, block_num: u64) -> Result<JsonValue, GethRpcError> {
+        let params = trace_params(block_num, self.tracer.as_deref(), self.timeout);
+
+        self.call("debug_traceBlockByNumber", &params, "trace_block")
+    }
+
+    /// Trace `block_num` with the struct logger, tallying each transaction's steps as the
+    /// response streams in rather than parsing it whole, so memory use doesn't grow with
+    /// the size of the trace. Ignores `set_tracer`.
+    pub fn struct_log_tallies(&mut self, block_num: u64) -> Result<Vec<TxnTally>, GethRpcError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let rpc = rpc_text(
+            "debug_traceBlockByNumber",
+            &trace_params(block_num, None, self.timeout),
+            id,
+        );
+        let mut tallies = StructLogStream::new(id);
+
+        self.stream_request(id, &rpc, "struct_log_tallies", &mut tallies)?;
+
+        tallies.finish()
+    }
+
+    /// Trace `block_num` with geth's `callTracer`, returning each transaction's call frames.
+    /// Much faster than `trace_block`, but without per-opcode detail.
+    pub fn call_frames(&mut self, block_num: u64) -> Result<Vec<Vec<CallFrame>>, GethRpcError> {
+        let traces = self.call(
+            "debug_traceBlockByNumber",
+            &call_trace_params(block_num),
+            "call_frames",
+        )?;
+
+        let mut txns = Vec::with_capacity(traces.len());
+
+        for (idx, trace) in traces.members().enumerate() {
+            // geth reports a transaction it couldn't trace in place of its result
+            if let Some(error) = trace["error"].as_str() {
+                return Err(GethRpcError::untraced(idx, error));
+            }
+
+            let mut frames = Vec::new();
+            parse_call_frames(
+                block_num,
+                idx as u32,
+                &trace["result"],
+                1,
+                None,
+                &mut frames,
+            )?;
+            txns.push(frames);
+        }
+
+        Ok(txns)
+    }
+
+    /// `block_with_txns`, with each transaction's receipt, and `trace_block` for `block_num`
+    /// in a single batch request (two if geth lacks `eth_getBlockReceipts`)
+    pub fn traced_block(&mut self, block_num: u64) -> Result<(BlockInfo, JsonValue), GethRpcError> {
+        let mut calls = vec![
+            ("eth_getBlockByNumber", block_params(block_num, true)),
+            (
+                "debug_traceBlockByNumber",
+                trace_params(block_num, self.tracer.as_deref(), self.timeout),
+            ),
+        ];
+
+        if self.block_receipts {
+            calls.push(("eth_getBlockReceipts", receipts_params(block_num)));
+        }
+
+        let mut results = self.batch(&calls, "traced_block")?.into_iter();
+
+        // both answers are needed; geth refusing either means the block can't be done
+        let block_info = results.next().expect("batch result")?;
+        let trace = results.next().expect("batch result")?;
+
+        let mut block_info = parse_block_with_txns(block_num, block_info)?;
+        self.attach_receipts(&mut block_info, results.next())?;
+
+        Ok((block_info, trace))
+    }
+
+    /// `block_with_txns`, with each transaction's receipt, in a single batch request (two if
+    /// geth lacks `eth_getBlockReceipts`)
+    pub fn block_with_receipts(&mut self, block_num: u64) -> Result<BlockInfo, GethRpcError> {
+        let mut calls = vec![("eth_getBlockByNumber", block_params(block_num, true))];
+
+        if self.block_receipts {
+            calls.push(("eth_getBlockReceipts", receipts_params(block_num)));
+        }
+
+        let mut results = self.batch(&calls, "block_with_receipts")?.into_iter();
+
+        let block_info = results.next().expect("batch result")?;
+
+        let mut block_info = parse_block_with_txns(block_num, block_info)?;
+        self.attach_receipts(&mut block_info, results.next())?;
+
+        Ok(block_info)
+    }
+
+    /// Interpret `result`, geth's answer to `eth_getBlockReceipts` for `block_info` if we
+    /// asked, falling back to one `eth_getTransactionReceipt` per transaction if we didn't
+    /// or geth doesn't know the method
+    fn receipts_from(
+        &mut self,
+        block_info: &BlockInfo,
+        result: Option<Result<JsonValue, GethRpcError>>,
+    ) -> Result<Vec<ReceiptInfo>, GethRpcError> {
+        let receipts = match result {
+            Some(Ok(receipts)) => receipts,
+            Some(Err(GethRpcError::Rpc { code, ref message }))
+                if code == Self::METHOD_NOT_FOUND =>
+            {
+                warn!(
+                    "No eth_getBlockReceipts ({}), fetching receipts per transaction",
+                    message
+                );
+                self.block_receipts = false;
+                return self.txn_receipts(block_info);
+            }
+            Some(Err(e)) => return Err(e),
+            None => return self.txn_receipts(block_info),
+        };
+
+        parse_receipts(block_info, receipts)
+    }
+
+    /// Set the `receipt` of each transaction of `block_info` from `result`, as `receipts_from`
+    fn attach_receipts(
+        &mut self,
+        block_info: &mut BlockInfo,
+        result: Option<Result<JsonValue, GethRpcError>>,
+    ) -> Result<(), GethRpcError> {
+        let receipts = self.receipts_from(block_info, result)?;
+
+        for (txn, receipt) in block_info.txns.iter_mut().zip(receipts) {
+            txn.receipt = Some(receipt);
+        }
+
+        Ok(())
+    }
+
+    /// `eth.getTransactionReceipt` for each transaction of `block_info` in a single batch
+    fn txn_receipts(&mut self, block_info: &BlockInfo) -> Result<Vec<ReceiptInfo>, GethRpcError> {
+        if block_info.txns.len() != block_info.txn_count as usize {
+            return Err(GethRpcError::MissingField(format!(
+                "transactions of block {}",
+                block_info.block_num
+            )));
+        }
+
+        let calls: Vec<_> = block_info
+            .txns
+            .iter()
+            .map(|txn| ("eth_getTransactionReceipt", format!("[\"{}\"]", txn.hash)))
+            .collect();
+
+        let results = self.batch(&calls, "txn_receipts")?;
+
+        results
+            .into_iter()
+            .map(|result| parse_receipt(result?))
+            .collect()
+    }
+
+    /// Call Geth `eth.getBlockByNumber`
+    fn get_block(&mut self, block_num: u64) -> Result<JsonValue, GethRpcError> {
+        self.call(
+            "eth_getBlockByNumber",
+            &block_params(block_num, false),
+            "get_block",
+        )
+    }
+
+    /// Issue JSON-RPC `method` with `params` (a JSON array) under a fresh request id and
+    /// return the `result` of geth's response
+    fn call(&mut self, method: &str, params: &str, note: &str) -> Result<JsonValue, GethRpcError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let rpc = rpc_text(method, params, id);
+        let response = self.request(id, &rpc, note)?;
+
+        take_result(response)
+    }
+
+    /// Issue `calls`, (method, params) pairs, as one JSON-RPC batch under consecutive ids.
+    /// Returns each call's `result` in the order of `calls`, or its error if geth refused
+    /// that call. The outer `Err` is for the batch as a whole failing.
+    fn batch(
+        &mut self,
+        calls: &[(&str, String)],
+        note: &str,
+    ) -> Result<Vec<Result<JsonValue, GethRpcError>>, GethRpcError> {
+        // geth rejects an empty batch
+        if calls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let first_id = self.next_id;
+        self.next_id += calls.len() as u64;
+
+        let rpcs: Vec<String> = calls
+            .iter()
+            .zip(first_id..)
+            .map(|(&(method, ref params), id)| rpc_text(method, params, id))
+            .collect();
+        let rpc = format!("[{}]", rpcs.join(","));
+
+        let mut response = self.request(first_id, &rpc, note)?;
+
+        // geth answers a batch it can't parse with a single error object
+        if !response.is_array() {
+            take_result(response)?;
+            return Err(GethRpcError::missing("batch response"));
+        }
+
+        // members may arrive in any order
+        let mut results: Vec<Option<JsonValue>> = vec![None; calls.len()];
+
+        for member in response.members_mut() {
+            match member["id"].as_u64() {
+                Some(id) if id >= first_id && id - first_id < calls.len() as u64 => {
+                    results[(id - first_id) as usize] = Some(member.take())
+                }
+                _ => warn!("{}: ignoring batch member with id {}", note, member["id"]),
+            }
+        }
+
+        results
+            .into_iter()
+            .zip(calls)
+            .map(|(result, &(method, _))| match result {
+                Some(response) => Ok(take_result(response)),
+                None => Err(GethRpcError::MissingField(format!(
+                    "response to {} in batch",
+                    method
+                ))),
+            })
+            .collect()
+    }
+
+    /// Send `rpc` over the transport. If the connection breaks, reconnect and replay the
+    /// request (under the same id) until it succeeds or the `Reconnect` limit is reached.
+    fn request(&mut self, id: u64, rpc: &str, note: &str) -> Result<JsonValue, GethRpcError> {
+        let mut replays = 0;
+
+        loop {
+            match self.transport.request(id, rpc, note) {
+                Err(GethRpcError::Io(ref e)) if replays < self.reconnect.max_attempts => {
+                    warn!("{}: lost connection to geth: {}", note, e)
+                }
+                result => return result,
+            }
+
+            self.reconnect()?;
+            replays += 1;
+
+            info!("{}: replaying request {} after reconnect", note, id);
+        }
+    }
+
+    /// `request` for `Transport::stream_request`; `sink` is reset before each replay
+    fn stream_request(
+        &mut self,
+        id: u64,
+        rpc: &str,
+        note: &str,
+        sink: &mut dyn ResponseSink,
+    ) -> Result<(), GethRpcError> {
+        let mut replays = 0;
+
+        loop {
+            match self.transport.stream_request(id, rpc, note, sink) {
+                Err(GethRpcError::Io(ref e)) if replays < self.reconnect.max_attempts => {
+                    warn!("{}: lost connection to geth: {}", note, e)
+                }
+                result => return result,
+            }
+
+            self.reconnect()?;
+            sink.reset();
+            replays += 1;
+
+            info!("{}: replaying request {} after reconnect", note, id);
+        }
+    }
+
+    /// Replace the transport with a fresh connection, backing off exponentially between
+    /// attempts. Restores the `newHeads` subscription, if we had one.
+    fn reconnect(&mut self) -> Result<(), GethRpcError> {
+        let mut delay = self.reconnect.initial_delay;
+        let mut attempt = 1;
+
+        loop {
+            if attempt > self.reconnect.max_attempts {
+                return Err(GethRpcError::Io(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("gave up reconnecting to {}", self.url),
+                )));
+            }
+
+            thread::sleep(delay);
+
+            match connect(&self.url, self.timeout) {
+                Ok(transport) => {
+                    info!("Reconnected to {} (attempt {})", self.url, attempt);
+                    self.transport = transport;
+                    break;
+                }
+                Err(e) => warn!(
+                    "Reconnect to {} failed (attempt {} of {}): {}",
+                    self.url, attempt, self.reconnect.max_attempts, e
+                ),
+            }
+
+            delay = cmp::min(delay * 2, self.reconnect.max_delay);
+            attempt += 1;
+        }
+
+        if self.new_heads {
+            self.resubscribe_new_heads();
+        }
+
+        Ok(())
+    }
+
+    /// A new connection has no subscriptions; ask for `newHeads` again. Failure leaves the
+    /// caller to notice the subscription is gone via `next_new_head`.
+    fn resubscribe_new_heads(&mut self) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let rpc = rpc_text("eth_subscribe", r#"["newHeads"]"#, id);
+
+        // straight to the transport: a failure here mustn't trigger another reconnect
+        match self.transport.request(id, &rpc, "eth_subscribe") {
+            Ok(ref resp) if resp["result"].is_string() => {
+                info!("Restored newHeads subscription {}", resp["result"])
+            }
+            Ok(resp) => {
+                warn!("Couldn't restore newHeads subscription: {}", resp["error"]);
+                self.new_heads = false;
+            }
+            Err(e) => {
+                warn!("Couldn't restore newHeads subscription: {}", e);
+                self.new_heads = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = include_str!("../../tests/fixtures/block.json");
+    const RECEIPTS: &str = include_str!("../../tests/fixtures/receipts.json");
+    const BLOCK_NUM: u64 = 21_000_000;
+
+    fn fixture_block() -> JsonValue {
+        json::parse(BLOCK).unwrap()
+    }
+
+    fn fixture_txn(idx: usize) -> JsonValue {
+        fixture_block()["transactions"][idx].take()
+    }
+
+    #[test]
+    fn tracer_timeout() {
+        let timeout = Duration::from_secs(300);
+
+        let params = json::parse(&trace_params(0x10, Some("{}"), timeout)).unwrap();
+        assert_eq!(params[0], "0x10");
+        assert_eq!(params[1]["tracer"], "{}");
+        assert_eq!(params[1]["timeout"], "300000ms");
+
+        let params = json::parse(&trace_params(0x10, None, timeout)).unwrap();
+        assert_eq!(params[1]["disableStack"], true);
+        assert_eq!(params[1]["timeout"], "300000ms");
+    }
+
+    #[test]
+    fn block_info() {
+        let block = parse_block_info(BLOCK_NUM, &fixture_block()).unwrap();
+
+        assert_eq!(block.block_num, BLOCK_NUM);
+        assert_eq!(block.time_stamp, 0x6748_a2bb);
+        assert_eq!(block.gas_limit, 36_000_000);
+        assert_eq!(block.gas_used, 239_022);
+        assert_eq!(block.miner, "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5");
+        assert!(block.hash.starts_with("0x9b83c12c"));
+        assert!(block.parent_hash.starts_with("0xd4e56740"));
+        assert_eq!(block.base_fee, Some(500_000_000));
+        assert_eq!(block.difficulty, 0);
+        assert_eq!(block.size, 0x4a2);
+        assert_eq!(block.txn_count, 3);
+        assert!(block.txns.is_empty());
+    }
+
+    #[test]
+    fn block_before_london() {
+        let mut json = fixture_block();
+        json.remove("baseFeePerGas");
+        json["difficulty"] = "0xc70d815d562d3cfa955".into();
+
+        let block = parse_block_with_txns(BLOCK_NUM, json).unwrap();
+
+        assert_eq!(block.base_fee, None);
+        assert_eq!(block.difficulty, 0xc70d815d562d3cfa955);
+        // without a base fee the effective price is what geth reported
+        assert_eq!(block.txns[1].effective_gas_price, 0x5968_2f00);
+    }
+
+    #[test]
+    fn missing_block() {
+        match parse_block_info(BLOCK_NUM, &JsonValue::Null) {
+            Err(GethRpcError::MissingField(what)) => assert_eq!(what, "block 21000000"),
+            other => panic!("expected a missing block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn block_with_txns() {
+        let block = parse_block_with_txns(BLOCK_NUM, fixture_block()).unwrap();
+
+        assert_eq!(block.txns.len(), 3);
+        for (idx, txn) in block.txns.iter().enumerate() {
+            assert_eq!(txn.block_num, BLOCK_NUM);
+            assert_eq!(txn.block_index, idx as u32);
+            assert!(txn.receipt.is_none());
+        }
+    }
+
+    #[test]
+    fn legacy_creation() {
+        let txn = parse_txn_info(BLOCK_NUM, 0, fixture_txn(0), Some(500_000_000)).unwrap();
+
+        assert_eq!(txn.txn_type, 0);
+        assert_eq!(txn.from, "0x4838b106fce9647bdf1e7877bf73ce8b0bad5f97");
+        assert_eq!(txn.to, "");
+        assert_eq!(txn.nonce, 5);
+        assert_eq!(txn.value, 0);
+        assert_eq!(txn.input_size, 7);
+        assert_eq!(txn.input_zero_bytes, 2);
+        assert_eq!(txn.gas_limit, 200_000);
+        assert_eq!(txn.gas_price, 1_000_000_000);
+        assert_eq!(txn.max_fee, None);
+        assert_eq!(txn.max_priority_fee, None);
+        assert_eq!(txn.effective_gas_price, 1_000_000_000);
+        assert_eq!(txn.access_list_addresses, 0);
+        assert_eq!(txn.authorizations, 0);
+    }
+
+    #[test]
+    fn dynamic_fee_with_access_list() {
+        let txn = parse_txn_info(BLOCK_NUM, 1, fixture_txn(1), Some(500_000_000)).unwrap();
+
+        assert_eq!(txn.txn_type, 2);
+        assert_eq!(txn.to, "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+        assert_eq!(txn.value, 1_000_000_000_000_000_000);
+        assert_eq!(txn.input_size, 6);
+        assert_eq!(txn.input_zero_bytes, 2);
+        assert_eq!(txn.access_list_addresses, 2);
+        assert_eq!(txn.access_list_keys, 2);
+        assert_eq!(txn.max_fee, Some(2_000_000_000));
+        assert_eq!(txn.max_priority_fee, Some(1_000_000_000));
+        // base fee plus the full tip, under the cap
+        assert_eq!(txn.effective_gas_price, 1_500_000_000);
+    }
+
+    #[test]
+    fn set_code_authorizations() {
+        let txn = parse_txn_info(BLOCK_NUM, 2, fixture_txn(2), Some(500_000_000)).unwrap();
+
+        assert_eq!(txn.txn_type, 4);
+        assert_eq!(txn.authorizations, 1);
+        assert_eq!(txn.access_list_addresses, 0);
+        assert_eq!(txn.input_size, 0);
+        assert_eq!(txn.effective_gas_price, 500_000_000);
+    }
+
+    #[test]
+    fn txn_before_berlin() {
+        let mut json = fixture_txn(0);
+        json.remove("type");
+
+        assert_eq!(
+            parse_txn_info(BLOCK_NUM, 0, json, None).unwrap().txn_type,
+            0
+        );
+    }
+
+    #[test]
+    fn txn_type_out_of_range() {
+        let mut json = fixture_txn(0);
+        json["type"] = "0x104".into();
+
+        match parse_txn_info(BLOCK_NUM, 0, json, None) {
+            Err(e @ GethRpcError::InvalidField { .. }) => {
+                assert_eq!(e.to_string(), "response has invalid 'type': 0x104")
+            }
+            other => panic!("expected a bad type, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn txn_missing_field() {
+        let mut json = fixture_txn(1);
+        json.remove("nonce");
+
+        match parse_txn_info(BLOCK_NUM, 1, json, None) {
+            Err(GethRpcError::MissingField(name)) => assert_eq!(name, "nonce"),
+            other => panic!("expected a missing nonce, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn receipts() {
+        let block = parse_block_with_txns(BLOCK_NUM, fixture_block()).unwrap();
+        let receipts = parse_receipts(&block, json::parse(RECEIPTS).unwrap()).unwrap();
+
+        let summary: Vec<_> = receipts
+            .iter()
+            .map(|r| (r.status, r.gas_used, r.cumulative_gas_used, r.log_count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some(true), 108_242, 108_242, 0),
+                (Some(true), 51_144, 159_386, 1),
+                (Some(false), 79_636, 239_022, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn receipts_before_byzantium() {
+        let mut json = json::parse(RECEIPTS).unwrap();
+        json[0].remove("status");
+        json[0]["root"] =
+            "0x5d3b0bc5ab8f7c1e0d8a3f6c2e9b4d7a1c0f3e6b9d2a5c8f1e4b7d0a3c6f9e2b".into();
+
+        assert_eq!(parse_receipt(json[0].take()).unwrap().status, None);
+    }
+
+    #[test]
+    fn receipts_not_matching_block() {
+        let block = parse_block_with_txns(BLOCK_NUM, fixture_block()).unwrap();
+        let mut json = json::parse(RECEIPTS).unwrap();
+        json.array_remove(2);
+
+        match parse_receipts(&block, json) {
+            Err(GethRpcError::MissingField(what)) => assert_eq!(what, "receipts of block 21000000"),
+            other => panic!("expected missing receipts, got {:?}", other),
+        }
+    }
+}
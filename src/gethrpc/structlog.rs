@@ -0,0 +1,532 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Tallies a struct logger `debug_traceBlockByNumber` response while it streams in
+//!
+//! Each step is added to its transaction's `InstCount` as soon as it's parsed and then
+//! forgotten, so however long the trace, only one step is held at a time.
+//!
+
+use evminst::EvmInst;
+use gasacct::ExecSummary;
+use gethrpc::jsonstream::{JsonEvent, JsonHandler, JsonStream};
+use gethrpc::{GethRpcError, ResponseSink};
+use instcount::InstCount;
+
+/// One transaction's struct logs, reduced to its opcode counts and how execution ended
+pub struct TxnTally {
+    pub counts: InstCount,
+
+    /// `None` if the transaction ran no code (e.g. a plain transfer)
+    pub exec: Option<ExecSummary>,
+}
+
+/// Where in the response the parser is
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    /// The JSON-RPC response object
+    Response,
+
+    /// Its `error` object
+    Error,
+
+    /// Its `result`, an array with an entry per transaction
+    Txns,
+    Txn,
+
+    /// A transaction's `result`: `gas`, `failed`, `returnValue` and `structLogs`
+    TxnResult,
+    StructLogs,
+    Step,
+
+    /// Anything we don't need, e.g. a step's `stack`
+    Other,
+}
+
+/// Fields of the step being parsed
+#[derive(Default)]
+struct StepFields {
+    op: String,
+    gas: Option<u64>,
+    gas_cost: Option<u64>,
+    depth: Option<u64>,
+    refund: u64,
+}
+
+/// Running totals for the transaction being parsed
+struct TxnState {
+    counts: InstCount,
+    steps: u64,
+    first_gas: Option<u64>,
+
+    /// Last step of the top-level call frame: op, gas and gas cost
+    last_top: Option<(String, u64, u64)>,
+
+    /// Refund counter as of the last step
+    refund: u64,
+
+    /// Geth sent the transaction's trace, or said why it couldn't
+    traced: bool,
+}
+
+impl TxnState {
+    fn new() -> Self {
+        TxnState {
+            counts: InstCount::new(),
+            steps: 0,
+            first_gas: None,
+            last_top: None,
+            refund: 0,
+            traced: false,
+        }
+    }
+}
+
+/// `JsonHandler` that does the tallying for `StructLogStream`
+struct Tallier {
+    /// Request id of the response we're waiting for
+    id: u64,
+
+    scopes: Vec<Scope>,
+
+    /// Name of the object member most recently seen
+    key: String,
+
+    response_id: Option<u64>,
+    has_result: bool,
+    error: Option<(i64, String)>,
+
+    /// The first transaction geth couldn't trace, and why
+    untraced: Option<GethRpcError>,
+
+    step: StepFields,
+    txn: TxnState,
+    txns: Vec<TxnTally>,
+
+    complete: bool,
+}
+
+impl Tallier {
+    fn new(id: u64) -> Self {
+        Tallier {
+            id,
+            scopes: Vec::new(),
+            key: String::new(),
+            response_id: None,
+            has_result: false,
+            error: None,
+            untraced: None,
+            step: StepFields::default(),
+            txn: TxnState::new(),
+            txns: Vec::new(),
+            complete: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Tallier::new(self.id);
+    }
+
+    /// Scope of an object or array that starts in the current one
+    fn child_scope(&self, object: bool) -> Scope {
+        match (self.scopes.last(), self.key.as_str(), object) {
+            (None, _, true) => Scope::Response,
+            (Some(&Scope::Response), "result", false) => Scope::Txns,
+            (Some(&Scope::Response), "error", true) => Scope::Error,
+            (Some(&Scope::Txns), _, true) => Scope::Txn,
+            (Some(&Scope::Txn), "result", true) => Scope::TxnResult,
+            (Some(&Scope::TxnResult), "structLogs", false) => Scope::StructLogs,
+            (Some(&Scope::StructLogs), _, true) => Scope::Step,
+            _ => Scope::Other,
+        }
+    }
+
+    fn start(&mut self, object: bool) {
+        let scope = self.child_scope(object);
+
+        match scope {
+            Scope::Txns => self.has_result = true,
+            Scope::Txn => self.txn = TxnState::new(),
+            Scope::TxnResult => self.txn.traced = true,
+            Scope::Step => self.step = StepFields::default(),
+            _ => (),
+        }
+
+        self.scopes.push(scope);
+    }
+
+    fn end(&mut self) -> Result<(), GethRpcError> {
+        match self.scopes.pop() {
+            Some(Scope::Step) => self.end_step(),
+            Some(Scope::Txn) => self.end_txn(),
+            Some(_) if self.scopes.is_empty() => {
+                self.end_response();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn scalar(&mut self, event: &JsonEvent) -> Result<(), GethRpcError> {
+        let scope = match self.scopes.last() {
+            Some(&scope) => scope,
+            None => return Ok(()),
+        };
+
+        match (scope, self.key.as_str(), event) {
+            (Scope::Response, "id", &JsonEvent::Number(n)) => self.response_id = n.parse().ok(),
+            (Scope::Error, "code", &JsonEvent::Number(n)) => {
+                self.error_mut().0 = n.parse().unwrap_or(0)
+            }
+            (Scope::Error, "message", &JsonEvent::Str(s)) => self.error_mut().1 = s.to_string(),
+            // the rest of the response is still read, so the connection stays usable
+            (Scope::Txn, "error", &JsonEvent::Str(s)) => {
+                if self.untraced.is_none() {
+                    self.untraced = Some(GethRpcError::untraced(self.txns.len(), s));
+                }
+                self.txn.traced = true;
+            }
+            (Scope::Step, "op", &JsonEvent::Str(s)) => self.step.op.push_str(s),
+            (Scope::Step, "gas", &JsonEvent::Number(n)) => self.step.gas = n.parse().ok(),
+            (Scope::Step, "gasCost", &JsonEvent::Number(n)) => self.step.gas_cost = n.parse().ok(),
+            (Scope::Step, "depth", &JsonEvent::Number(n)) => self.step.depth = n.parse().ok(),
+            (Scope::Step, "refund", &JsonEvent::Number(n)) => {
+                self.step.refund = n.parse().unwrap_or(0)
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn error_mut(&mut self) -> &mut (i64, String) {
+        self.error.get_or_insert_with(|| (0, String::new()))
+    }
+
+    fn end_step(&mut self) -> Result<(), GethRpcError> {
+        if self.step.op.is_empty() {
+            return Err(GethRpcError::missing("op"));
+        }
+        let gas_cost = self
+            .step
+            .gas_cost
+            .ok_or_else(|| GethRpcError::missing("gasCost"))?;
+
+        let inst = EvmInst::from_str(&self.step.op);
+        self.txn.counts.inc_count(inst);
+        self.txn.counts.add_gas(inst, gas_cost);
+
+        if self.txn.steps == 0 {
+            self.txn.first_gas = Some(self.step.gas.ok_or_else(|| GethRpcError::missing("gas"))?);
+        }
+        self.txn.steps += 1;
+
+        if self.step.depth == Some(1) {
+            let op = self.step.op.clone();
+            self.txn.last_top = Some((op, self.step.gas.unwrap_or(0), gas_cost));
+        }
+
+        // geth omits a zero `refund`
+        self.txn.refund = self.step.refund;
+
+        Ok(())
+    }
+
+    fn end_txn(&mut self) -> Result<(), GethRpcError> {
+        let txn = ::std::mem::replace(&mut self.txn, TxnState::new());
+
+        if !txn.traced {
+            return Err(GethRpcError::MissingField(format!(
+                "trace of transaction {}",
+                self.txns.len()
+            )));
+        }
+
+        let exec = match (txn.first_gas, txn.last_top) {
+            (Some(available_gas), Some((last_op, last_gas, last_cost))) => Some(ExecSummary {
+                available_gas,
+                last_op,
+                last_gas,
+                last_cost,
+                refund: txn.refund,
+            }),
+            (Some(_), None) => return Err(GethRpcError::missing("depth")),
+            _ => None,
+        };
+
+        self.txns.push(TxnTally {
+            counts: txn.counts,
+            exec,
+        });
+
+        Ok(())
+    }
+
+    /// Geth can only answer a request it couldn't parse with a `null` id, so such errors
+    /// are taken to be ours
+    fn end_response(&mut self) {
+        if self.response_id == Some(self.id) || (self.response_id.is_none() && self.error.is_some())
+        {
+            self.complete = true;
+        } else {
+            // most likely the answer to an earlier request we gave up on
+            warn!(
+                "trace stream: discarding response id {:?}, waiting for {}",
+                self.response_id, self.id
+            );
+            self.reset();
+        }
+    }
+}
+
+impl JsonHandler for Tallier {
+    fn event(&mut self, event: JsonEvent) -> Result<(), GethRpcError> {
+        match event {
+            JsonEvent::Key(key) => {
+                self.key.clear();
+                self.key.push_str(key);
+                Ok(())
+            }
+            JsonEvent::StartObject => {
+                self.start(true);
+                Ok(())
+            }
+            JsonEvent::StartArray => {
+                self.start(false);
+                Ok(())
+            }
+            JsonEvent::EndObject | JsonEvent::EndArray => self.end(),
+            ref scalar => self.scalar(scalar),
+        }
+    }
+}
+
+///
+/// Consumes geth's response to a struct logger `debug_traceBlockByNumber` request as it
+/// arrives, see `GethRpc::struct_log_tallies`
+///
+pub struct StructLogStream {
+    json: JsonStream,
+    tallier: Tallier,
+}
+
+impl StructLogStream {
+    /// Wait for the response to the request with id `id`
+    pub fn new(id: u64) -> Self {
+        StructLogStream {
+            json: JsonStream::new(),
+            tallier: Tallier::new(id),
+        }
+    }
+
+    /// A tally per transaction, in block order, once the response is complete
+    pub fn finish(self) -> Result<Vec<TxnTally>, GethRpcError> {
+        let tallier = self.tallier;
+
+        if let Some((code, message)) = tallier.error {
+            return Err(GethRpcError::Rpc { code, message });
+        }
+
+        if !tallier.complete || !tallier.has_result {
+            return Err(GethRpcError::missing("result"));
+        }
+
+        if let Some(untraced) = tallier.untraced {
+            return Err(untraced);
+        }
+
+        Ok(tallier.txns)
+    }
+}
+
+impl ResponseSink for StructLogStream {
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, GethRpcError> {
+        let mut used = 0;
+
+        // keep going past responses to other requests
+        while used < bytes.len() && !self.tallier.complete {
+            used += self.json.push(&bytes[used..], &mut self.tallier)?;
+        }
+
+        Ok(used)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.tallier.complete
+    }
+
+    fn reset(&mut self) {
+        self.json.reset();
+        self.tallier.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evminst;
+
+    const STRUCT_LOGS: &str = include_str!("../../tests/fixtures/trace_structlog.json");
+
+    /// Feed `response` to a fresh stream for request 1 in pieces of `chunk` bytes, returning
+    /// how many bytes it took
+    fn stream_in(response: &str, chunk: usize) -> (StructLogStream, usize) {
+        let mut stream = StructLogStream::new(1);
+        let mut used = 0;
+
+        for piece in response.as_bytes().chunks(chunk) {
+            if stream.is_complete() {
+                break;
+            }
+            used += stream.push(piece).unwrap();
+        }
+
+        (stream, used)
+    }
+
+    fn total(counts: &InstCount) -> (u64, u64) {
+        evminst::VALUES.iter().fold((0, 0), |(steps, gas), &inst| {
+            (steps + counts.get_count(inst), gas + counts.get_gas(inst))
+        })
+    }
+
+    #[test]
+    fn tallies_in_small_chunks() {
+        for &chunk in &[1, 2, 7, 64, STRUCT_LOGS.len()] {
+            let (stream, used) = stream_in(STRUCT_LOGS, chunk);
+            let txns = stream.finish().unwrap();
+
+            // all but the trailing newline
+            assert_eq!(used, STRUCT_LOGS.trim_end().len(), "chunk {}", chunk);
+            assert_eq!(txns.len(), 5);
+
+            let call = &txns[0];
+            assert_eq!(total(&call.counts), (25, 50_068));
+            assert_eq!(call.counts.get_count(EvmInst::PUSH1), 8);
+            assert_eq!(call.counts.get_gas(EvmInst::PUSH1), 24);
+            assert_eq!(call.counts.get_count(EvmInst::SSTORE), 2);
+            assert_eq!(call.counts.get_gas(EvmInst::SSTORE), 7_900);
+            assert_eq!(call.counts.get_gas(EvmInst::CALL), 40_000);
+            // one STOP ends the inner call, one the transaction
+            assert_eq!(call.counts.get_count(EvmInst::STOP), 2);
+
+            let exec = call.exec.as_ref().expect("code ran");
+            assert_eq!(exec.available_gas, 78_612);
+            assert_eq!(exec.last_op, "STOP");
+            assert_eq!(exec.last_gas, 65_945);
+            assert_eq!(exec.last_cost, 0);
+            assert_eq!(exec.refund, 4_800);
+
+            // plain transfers
+            for &idx in &[1, 3] {
+                assert_eq!(total(&txns[idx].counts), (0, 0));
+                assert!(txns[idx].exec.is_none());
+            }
+
+            let reverted = txns[2].exec.as_ref().expect("code ran");
+            assert_eq!(total(&txns[2].counts), (3, 6));
+            assert_eq!(
+                (reverted.last_op.as_str(), reverted.last_gas),
+                ("REVERT", 29_458)
+            );
+
+            let invalid = txns[4].exec.as_ref().expect("code ran");
+            assert_eq!(total(&txns[4].counts), (2, 3));
+            assert_eq!(invalid.last_op, "INVALID");
+        }
+    }
+
+    #[test]
+    fn stale_response_is_skipped() {
+        let stale = STRUCT_LOGS.trim_end().replacen("\"id\": 1", "\"id\": 0", 1);
+        let response = format!("{}\n{}", stale, STRUCT_LOGS);
+
+        let (stream, used) = stream_in(&response, 5);
+
+        assert_eq!(used, response.trim_end().len());
+        assert_eq!(stream.finish().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn bytes_after_the_response_are_left() {
+        let response = format!("{}{{\"jsonrpc\":\"2.0\"", STRUCT_LOGS);
+
+        let (stream, used) = stream_in(&response, response.len());
+
+        assert!(stream.is_complete());
+        assert_eq!(&response[used..], "\n{\"jsonrpc\":\"2.0\"");
+    }
+
+    #[test]
+    fn rpc_error() {
+        let response =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"block not found"}}"#;
+
+        let (stream, _) = stream_in(response, 3);
+
+        match stream.finish() {
+            Err(GethRpcError::Rpc { code, message }) => {
+                assert_eq!(code, -32000);
+                assert_eq!(message, "block not found");
+            }
+            other => panic!("expected an RPC error, got {:?}", other.map(|t| t.len())),
+        }
+    }
+
+    #[test]
+    fn incomplete_response() {
+        let (stream, _) = stream_in(&STRUCT_LOGS[..STRUCT_LOGS.len() / 2], 64);
+
+        assert!(!stream.is_complete());
+        assert!(stream.finish().is_err());
+    }
+
+    #[test]
+    fn nested_values_keep_their_scope() {
+        // keys inside a step's `storage` and after it are not the step's, `returnValue`
+        // follows `structLogs`, and a transaction's `error` may come before its `result`
+        let response = concat!(
+            r#"{"id":1,"jsonrpc":"2.0","result":[{"result":{"gas":30000,"failed":false,"#,
+            r#""structLogs":[{"op":"SLOAD","gas":9000,"gasCost":2100,"depth":1,"#,
+            r#""storage":{"op":"ADD","gasCost":"0x3","result":[]},"stack":[["1"]]},"#,
+            r#"{"depth":1,"stack":[],"op":"STOP","gas":6900,"gasCost":0}],"#,
+            r#""returnValue":""}},{"error":"execution timeout","result":{"structLogs":[]}}]}"#
+        );
+
+        for chunk in 1..response.len() {
+            let (stream, used) = stream_in(response, chunk);
+            assert_eq!(used, response.len());
+
+            match stream.finish() {
+                Err(GethRpcError::Rpc { message, .. }) => {
+                    assert_eq!(message, "transaction 1: execution timeout")
+                }
+                other => panic!("expected the untraced transaction, got {:?}", other.is_ok()),
+            }
+        }
+
+        let traced = response.replace(r#""error":"execution timeout","#, "");
+        let (stream, _) = stream_in(&traced, 7);
+        let txns = stream.finish().unwrap();
+
+        assert_eq!(txns.len(), 2);
+        assert_eq!(total(&txns[0].counts), (2, 2100));
+        assert_eq!(txns[0].counts.get_count(EvmInst::ADD), 0);
+        let exec = txns[0].exec.as_ref().expect("code ran");
+        assert_eq!((exec.available_gas, exec.last_op.as_str()), (9000, "STOP"));
+        assert_eq!(exec.last_gas, 6900);
+        assert!(txns[1].exec.is_none());
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! JSON-RPC over WebSocket, the only transport that lets geth push `eth_subscription`
+//! notifications to us
+//!
+
+extern crate tungstenite;
+extern crate url;
+
+use self::tungstenite::client::AutoStream;
+use self::tungstenite::stream::Stream;
+use self::tungstenite::{Message, WebSocket};
+use self::url::Url;
+use gethrpc::{is_response_to, GethRpcError, Transport};
+use json;
+use json::JsonValue;
+use std::collections::VecDeque;
+use std::io;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::time::{Duration, Instant};
+
+pub struct WsTransport {
+    socket: WebSocket<AutoStream>,
+
+    /// Subscription notifications that arrived while waiting for a call's response
+    notifications: VecDeque<JsonValue>,
+
+    /// Give up on a response after this long without receiving any messages
+    timeout: Duration,
+}
+
+impl WsTransport {
+    /// Connect to the `ws://` or `wss://` endpoint at `url`
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, GethRpcError> {
+        let url = Url::parse(url)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let (socket, _) = tungstenite::connect(url).map_err(ws_error)?;
+
+        Ok(WsTransport {
+            socket,
+            notifications: VecDeque::new(),
+            timeout,
+        })
+    }
+
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        match *self.socket.get_mut() {
+            Stream::Plain(ref s) => s.set_read_timeout(Some(timeout)),
+            Stream::Tls(ref s) => s.get_ref().set_read_timeout(Some(timeout)),
+        }
+    }
+
+    /// Read the next text message, `Ok(None)` if nothing arrived before the read timeout
+    fn read_text(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.socket.read_message() {
+                Ok(Message::Text(text)) => return Ok(Some(text)),
+                Ok(Message::Binary(bytes)) => {
+                    return String::from_utf8(bytes)
+                        .map(Some)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                }
+                // tungstenite answers pings itself, nothing for us to do
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => continue,
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == TimedOut || e.kind() == WouldBlock =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+    }
+}
+
+fn ws_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+fn is_notification(msg: &JsonValue) -> bool {
+    msg["method"] == "eth_subscription"
+}
+
+impl Transport for WsTransport {
+    fn request(&mut self, id: u64, rpc: &str, note: &str) -> Result<JsonValue, GethRpcError> {
+        self.socket
+            .write_message(Message::Text(rpc.to_string()))
+            .map_err(ws_error)?;
+
+        self.set_read_timeout(Duration::new(10, 0))?;
+
+        let mut last_read = Instant::now();
+
+        loop {
+            let text = match self.read_text()? {
+                Some(text) => text,
+                None => {
+                    let waited = last_read.elapsed();
+                    if waited >= self.timeout {
+                        return Err(GethRpcError::Timeout { waited });
+                    }
+                    warn!("Read timeout in {}, continuing", note);
+                    continue;
+                }
+            };
+
+            last_read = Instant::now();
+
+            debug!("{}: read {}", note, text.len());
+
+            let msg = json::parse(&text)?;
+
+            if is_notification(&msg) {
+                self.notifications.push_back(msg);
+            } else if is_response_to(&msg, id) {
+                return Ok(msg);
+            } else {
+                warn!(
+                    "{}: discarding response id {}, waiting for {}",
+                    note, msg["id"], id
+                );
+            }
+        }
+    }
+
+    fn supports_subscriptions(&self) -> bool {
+        true
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn next_notification(&mut self, timeout: Duration) -> Result<Option<JsonValue>, GethRpcError> {
+        if let Some(msg) = self.notifications.pop_front() {
+            return Ok(Some(msg));
+        }
+
+        self.set_read_timeout(timeout)?;
+
+        loop {
+            let text = match self.read_text()? {
+                Some(text) => text,
+                None => return Ok(None),
+            };
+
+            let msg = json::parse(&text)?;
+
+            if is_notification(&msg) {
+                return Ok(Some(msg));
+            }
+
+            debug!("dropping unsolicited message {}", msg);
+        }
+    }
+}
@@ -36,6 +36,10 @@ impl InstCount {
         self.evm_counts[evm_inst as usize] += 1;
     }
 
+    pub fn add_count(&mut self, evm_inst: EvmInst, count: u64) {
+        self.evm_counts[evm_inst as usize] += count;
+    }
+
     pub fn add_gas(&mut self, evm_inst: EvmInst, gas_used: u64) {
         self.gas_counts[evm_inst as usize] += gas_used;
         self.gas_total += gas_used;
@@ -49,6 +53,15 @@ impl InstCount {
         self.gas_counts[evm_inst as usize]
     }
 
+    /// Add all of `other`'s counts and gas to this one
+    pub fn merge(&mut self, other: &InstCount) {
+        for i in 0..self.evm_counts.len() {
+            self.evm_counts[i] += other.evm_counts[i];
+            self.gas_counts[i] += other.gas_counts[i];
+        }
+        self.gas_total += other.gas_total;
+    }
+
     pub fn clear(&mut self) {
         // wtf Rust, no array::fill or equivalent?
         for i in 0..self.evm_counts.len() {
@@ -24,10 +24,16 @@ extern crate serde_derive;
 extern crate serde_json;
 extern crate simple_logger;
 extern crate chrono;
+extern crate toml;
 
+pub mod checkpoint;
+pub mod config;
 pub mod csvfiles;
+pub mod disasm;
 pub mod evminst;
 pub mod evmtrace;
+pub mod fork;
+pub mod gasacct;
 pub mod gethrpc;
 pub mod instcount;
 pub mod prices;
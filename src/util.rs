@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::Path;
+
 /// Convert a `0x` prefixed hex string to u64
 ///
 /// Example: `hex_to_u64("0xff") -> 255`
@@ -26,3 +28,56 @@ pub fn hex_to_u64(s: &str) -> Option<u64> {
         None
     }
 }
+
+/// Convert a `0x` prefixed hex string to u128, for quantities such as wei amounts that can
+/// overflow u64
+///
+/// Example: `hex_to_u128("0xde0b6b3a7640000") -> 1000000000000000000`
+pub fn hex_to_u128(s: &str) -> Option<u128> {
+    if s.len() > 2 {
+        // skip leading 0x
+        match u128::from_str_radix(&s[2..], 16) {
+            Ok(val) => Some(val),
+            Err(_) => None,
+        }
+    } else {
+        None
+    }
+}
+
+/// Convert a hex string, with or without a leading `0x`, to bytes
+///
+/// Example: `hex_to_bytes("0x6001") -> [0x60, 0x01]`
+pub fn hex_to_bytes(s: &str) -> Option<Vec<u8>> {
+    let digits = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return None;
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Command line validator for a `0x` prefixed 20 byte address
+pub fn is_address(arg: String) -> Result<(), String> {
+    match hex_to_bytes(&arg) {
+        Some(ref bytes) if arg.starts_with("0x") && bytes.len() == 20 => Ok(()),
+        _ => Err(format!("{} isn't a 0x prefixed 20 byte address", arg)),
+    }
+}
+
+/// Command line validator for the path of an existing file
+pub fn is_file(arg: String) -> Result<(), String> {
+    if Path::new(&arg).is_file() {
+        Ok(())
+    } else {
+        Err(format!("{} isn't a file", arg))
+    }
+}
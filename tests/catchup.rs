@@ -0,0 +1,177 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Runs `evmextract` against a minimal geth stand-in whose chain head moves, checking that
+//! consecutive catch-ups write each transaction exactly once and that a reorg while
+//! following the chain leaves what a run on the new chain writes
+//!
+
+extern crate evmobserver;
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::{block_hash, chain_response, reorganise, serve_http, txn_count};
+use evmobserver::checkpoint::Checkpoint;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wait until the checkpoint in `dir` says everything before `next_block` is written
+fn wait_for(dir: &Path, next_block: u64) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+
+    while Instant::now() < deadline {
+        if let Ok(checkpoint) = Checkpoint::load(dir.join(Checkpoint::FILE_NAME)) {
+            if checkpoint.next_block >= next_block {
+                return;
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("evmextract didn't reach block {}", next_block);
+}
+
+fn out_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("evmextract-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Start `evmextract` with `args`, writing small files to `dir`
+fn spawn_extract(rpc_url: &str, dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_evmextract"))
+        .args(["--rpc-url", rpc_url, "--rotate-rows", "3"])
+        .args(args)
+        .arg("--out-dir")
+        .arg(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+fn contents(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn consecutive_catchups_write_each_txn_once() {
+    let head = Arc::new(AtomicUsize::new(3));
+    let rpc_url = {
+        let head = head.clone();
+        serve_http(move |request| {
+            let head = head.load(Ordering::SeqCst) as u64;
+            (200, chain_response(request, head).dump())
+        })
+    };
+
+    let dir = std::env::temp_dir().join(format!("evmextract-catchup-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_evmextract"))
+        .args(["--start-block", "1", "--rpc-url", &rpc_url])
+        .arg("--out-dir")
+        .arg(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // both catch-ups end on a block with no transactions
+    wait_for(&dir, 4);
+    head.store(6, Ordering::SeqCst);
+    wait_for(&dir, 7);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let csv = fs::read_to_string(dir.join("counts.1.csv")).unwrap();
+    let rows: Vec<(u64, u64)> = csv
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            (fields[1].parse().unwrap(), fields[2].parse().unwrap())
+        })
+        .collect();
+
+    let unique: BTreeSet<(u64, u64)> = rows.iter().cloned().collect();
+    assert_eq!(rows.len(), unique.len(), "duplicate rows: {:?}", rows);
+
+    let expected: BTreeSet<(u64, u64)> = (1..7)
+        .flat_map(|n| (0..txn_count(n)).map(move |idx| (n, idx)))
+        .collect();
+    assert_eq!(unique, expected);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn reorg_while_following() {
+    let head = Arc::new(AtomicUsize::new(8));
+    let forked = Arc::new(AtomicBool::new(false));
+    let rpc_url = {
+        let head = head.clone();
+        let forked = forked.clone();
+        serve_http(move |request| {
+            let mut response = chain_response(request, head.load(Ordering::SeqCst) as u64);
+            if forked.load(Ordering::SeqCst) {
+                reorganise(&mut response, 5);
+            }
+            (200, response.dump())
+        })
+    };
+
+    let followed = out_dir("catchup-reorg-followed");
+    let mut child = spawn_extract(&rpc_url, &followed, &["--start-block", "1"]);
+
+    // blocks 5 to 8, spread over two files, are replaced while waiting for block 9
+    wait_for(&followed, 9);
+    forked.store(true, Ordering::SeqCst);
+    head.store(14, Ordering::SeqCst);
+    wait_for(&followed, 15);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let fresh = out_dir("catchup-reorg-fresh");
+    let args = ["--start-block", "1", "--end-block", "14"];
+    let status = spawn_extract(&rpc_url, &fresh, &args).wait().unwrap();
+    assert!(status.success());
+
+    let checkpoint = Checkpoint::load(followed.join(Checkpoint::FILE_NAME)).unwrap();
+    assert_eq!(checkpoint.last_hash, Some(block_hash(1_000_014)));
+    assert_eq!(contents(&followed), contents(&fresh));
+
+    let _ = fs::remove_dir_all(&followed);
+    let _ = fs::remove_dir_all(&fresh);
+}
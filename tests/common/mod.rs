@@ -0,0 +1,235 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A minimal geth stand-in shared by the integration tests: a chain whose odd blocks hold
+//! a couple of transactions, answered over HTTP
+//!
+
+// each test uses only some of these
+#![allow(dead_code)]
+
+use json;
+use json::JsonValue;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+/// Odd blocks have this many transactions, even blocks none
+pub const TXNS_PER_BLOCK: u64 = 2;
+
+pub fn hex(n: u64) -> String {
+    format!("0x{:x}", n)
+}
+
+pub fn block_hash(n: u64) -> String {
+    format!("0x{:064x}", n)
+}
+
+pub fn txn_count(block_num: u64) -> u64 {
+    if block_num % 2 == 1 {
+        TXNS_PER_BLOCK
+    } else {
+        0
+    }
+}
+
+fn param_block(params: &JsonValue) -> u64 {
+    let n = params[0].as_str().expect("block number param");
+    u64::from_str_radix(&n[2..], 16).expect("hex block number")
+}
+
+/// Geth's answer to `request`, a single request or a batch, with the chain head at `head`
+pub fn chain_response(request: &JsonValue, head: u64) -> JsonValue {
+    if request.is_array() {
+        JsonValue::Array(request.members().map(|r| answer(r, head)).collect())
+    } else {
+        answer(request, head)
+    }
+}
+
+fn answer(request: &JsonValue, head: u64) -> JsonValue {
+    let params = &request["params"];
+
+    let result = match request["method"].as_str().unwrap_or("") {
+        "eth_blockNumber" => JsonValue::from(hex(head)),
+
+        // mainnet
+        "eth_chainId" => JsonValue::from("0x1"),
+
+        "eth_getBlockByNumber" => {
+            let n = param_block(params);
+            let mut txns = JsonValue::new_array();
+            for idx in 0..txn_count(n) {
+                let txn = if params[1].as_bool() == Some(true) {
+                    object! {
+                        "hash" => block_hash(n * 100 + idx),
+                        "nonce" => hex(idx),
+                        "value" => "0x0",
+                        "input" => "0x",
+                        "gas" => "0x5208",
+                        "from" => "0xaa",
+                        "to" => "0xbb",
+                        "gasPrice" => "0x3b9aca00"
+                    }
+                } else {
+                    JsonValue::from(block_hash(n * 100 + idx))
+                };
+                txns.push(txn).unwrap();
+            }
+
+            object! {
+                "number" => hex(n),
+                "hash" => block_hash(n),
+                "parentHash" => block_hash(n - 1),
+                "timestamp" => "0x5b000000",
+                "gasLimit" => "0x7a1200",
+                "gasUsed" => "0xa410",
+                "miner" => "0xcc",
+                "difficulty" => "0x0",
+                "size" => "0x200",
+                "transactions" => txns
+            }
+        }
+
+        "debug_traceBlockByNumber" => {
+            let step = object! { "op" => "STOP", "gasCost" => 0, "gas" => 100, "depth" => 1 };
+            let mut traces = JsonValue::new_array();
+            for _ in 0..txn_count(param_block(params)) {
+                let trace = object! {
+                    "result" => object! {
+                        "gas" => 21000,
+                        "failed" => false,
+                        "structLogs" => array![step.clone()]
+                    }
+                };
+                traces.push(trace).unwrap();
+            }
+            traces
+        }
+
+        "eth_getBlockReceipts" => {
+            let mut receipts = JsonValue::new_array();
+            for idx in 0..txn_count(param_block(params)) {
+                let receipt = object! {
+                    "status" => "0x1",
+                    "gasUsed" => "0x5208",
+                    "cumulativeGasUsed" => hex(21000 * (idx + 1)),
+                    "logs" => array![]
+                };
+                receipts.push(receipt).unwrap();
+            }
+            receipts
+        }
+
+        method => {
+            return object! {
+                "jsonrpc" => "2.0",
+                "id" => request["id"].clone(),
+                "error" => object! { "code" => -32601, "message" => format!("no {}", method) }
+            }
+        }
+    };
+
+    object! { "jsonrpc" => "2.0", "id" => request["id"].clone(), "result" => result }
+}
+
+/// Give blocks from `fork` on the hashes and timestamps of a competing chain, so rows
+/// written from either chain can be told apart
+pub fn reorganise(response: &mut JsonValue, fork: u64) {
+    if response.is_array() {
+        for member in response.members_mut() {
+            reorganise(member, fork);
+        }
+        return;
+    }
+
+    let block = &mut response["result"];
+    let block_num = match block["number"].as_str() {
+        Some(n) if block["parentHash"].is_string() => u64::from_str_radix(&n[2..], 16).unwrap(),
+        _ => return,
+    };
+
+    let fork_hash = |n: u64| block_hash(n + 1_000_000);
+    if block_num >= fork {
+        block["hash"] = fork_hash(block_num).into();
+        block["timestamp"] = "0x5b000001".into();
+    }
+    if block_num > fork {
+        block["parentHash"] = fork_hash(block_num - 1).into();
+    }
+}
+
+/// Answer HTTP POSTs on a local port with whatever `respond` makes of each request body: an
+/// HTTP status and a response body. Returns the `http://` URL to reach it.
+pub fn serve_http<F>(respond: F) -> String
+where
+    F: Fn(&JsonValue) -> (u16, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let respond = Arc::new(respond);
+
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            let respond = respond.clone();
+            let conn = conn.unwrap();
+            thread::spawn(move || serve(conn, &*respond));
+        }
+    });
+
+    url
+}
+
+/// Answer requests on `conn` until the client hangs up
+fn serve(conn: TcpStream, respond: &dyn Fn(&JsonValue) -> (u16, String)) {
+    let mut reader = BufReader::new(conn.try_clone().unwrap());
+    let mut writer = conn;
+
+    loop {
+        let mut content_len = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end().to_ascii_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(len) = line.strip_prefix("content-length:") {
+                content_len = len.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0u8; content_len];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let request = json::parse(&String::from_utf8(body).unwrap()).unwrap();
+
+        let (status, body) = respond(&request);
+        let reply = format!(
+            "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Talks JSON-RPC over HTTP to a minimal geth stand-in
+//!
+
+extern crate evmobserver;
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::{chain_response, serve_http, TXNS_PER_BLOCK};
+use evmobserver::gethrpc::framer::FrameError;
+use evmobserver::gethrpc::http::HttpTransport;
+use evmobserver::gethrpc::{GethRpc, GethRpcError, Reconnect, Transport};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn chain_at(head: u64) -> String {
+    serve_http(move |request| (200, chain_response(request, head).dump()))
+}
+
+/// Connect to `url`, reconnecting quickly should the connection be deemed lost
+fn connect(url: &str) -> GethRpc {
+    let mut rpc = GethRpc::new(url).unwrap();
+    rpc.set_reconnect(Reconnect {
+        max_attempts: 2,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+    });
+    rpc
+}
+
+#[test]
+fn single_request() {
+    let mut rpc = GethRpc::new(&chain_at(7)).unwrap();
+
+    assert_eq!(rpc.get_latest_block().unwrap(), 7);
+}
+
+#[test]
+fn rpc_error() {
+    let mut rpc = GethRpc::new(&chain_at(7)).unwrap();
+
+    match rpc.get_code("0xbb", None) {
+        Err(GethRpcError::Rpc { code, .. }) => assert_eq!(code, -32601),
+        other => panic!("expected an RPC error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn batch_request() {
+    let mut rpc = GethRpc::new(&chain_at(7)).unwrap();
+
+    let block = rpc.block_with_receipts(3).unwrap();
+
+    assert_eq!(block.block_num, 3);
+    assert_eq!(block.txns.len() as u64, TXNS_PER_BLOCK);
+    for (idx, txn) in block.txns.iter().enumerate() {
+        let receipt = txn.receipt.as_ref().expect("receipt attached");
+        assert_eq!(receipt.cumulative_gas_used, 21000 * (idx as u64 + 1));
+    }
+}
+
+#[test]
+fn streamed_request() {
+    let mut rpc = GethRpc::new(&chain_at(7)).unwrap();
+
+    let tallies = rpc.struct_log_tallies(3).unwrap();
+
+    assert_eq!(tallies.len() as u64, TXNS_PER_BLOCK);
+    for tally in tallies {
+        assert_eq!(tally.exec.expect("code ran").last_op, "STOP");
+    }
+
+    assert!(rpc.struct_log_tallies(2).unwrap().is_empty());
+}
+
+#[test]
+fn mismatched_id() {
+    let url = serve_http(|request| {
+        let response = object! { "jsonrpc" => "2.0", "id" => 99, "result" => "0x1" };
+        assert_eq!(request["id"], 1);
+        (200, response.dump())
+    });
+    let mut http = HttpTransport::new(&url, Duration::from_secs(5)).unwrap();
+
+    let rpc = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#;
+
+    match http.request(1, rpc, "test") {
+        Err(GethRpcError::Malformed(FrameError::MismatchedId { expected, received })) => {
+            assert_eq!(expected, 1);
+            assert_eq!(received, "99");
+        }
+        other => panic!("expected a mismatched id, got {:?}", other),
+    }
+}
+
+#[test]
+fn slow_response_is_a_timeout() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let url = {
+        let requests = requests.clone();
+        serve_http(move |request| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(1500));
+            (200, chain_response(request, 7).dump())
+        })
+    };
+    let mut rpc = connect(&url);
+    rpc.set_timeout(Duration::from_millis(300));
+
+    match rpc.get_latest_block() {
+        Err(GethRpcError::Timeout { waited }) => assert_eq!(waited, Duration::from_millis(300)),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+
+    // a slow geth isn't a lost connection: nothing was replayed
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn http_status_is_not_retried() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let url = {
+        let requests = requests.clone();
+        serve_http(move |_| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            (503, "{}".to_string())
+        })
+    };
+    let mut rpc = connect(&url);
+
+    match rpc.get_latest_block() {
+        Err(GethRpcError::HttpStatus { status, .. }) => assert_eq!(status, 503),
+        other => panic!("expected an HTTP status, got {:?}", other),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn malformed_request() {
+    let mut http = HttpTransport::new(&chain_at(7), Duration::from_secs(5)).unwrap();
+
+    match http.request(1, r#"{"method":"eth_blockNumber","id":1"#, "test") {
+        Err(GethRpcError::Rpc { code, .. }) => assert_eq!(code, -32700),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
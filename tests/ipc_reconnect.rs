@@ -0,0 +1,179 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Talks JSON-RPC over a Unix socket to a geth stand-in that drops connections or answers
+//! too late
+//!
+
+extern crate evmobserver;
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::chain_response;
+use evmobserver::gethrpc::{GethRpc, GethRpcError, Reconnect};
+use json::JsonValue;
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// What the stand-in does with the request on the `n`th connection (counting from 0)
+#[derive(Clone, Copy)]
+enum Behaviour {
+    Answer,
+    Hangup,
+    /// Send half the answer, then the rest after this long
+    Stall(Duration),
+}
+
+/// The ids of the requests received, by connection
+type Seen = Arc<Mutex<Vec<Vec<JsonValue>>>>;
+
+/// Listen on a fresh socket named for `test`, treating each connection as `behave` says.
+/// Returns the socket path and the requests received.
+fn serve_ipc<F>(test: &str, behave: F) -> (String, Seen)
+where
+    F: Fn(usize) -> Behaviour + Send + 'static,
+{
+    let path: PathBuf =
+        env::temp_dir().join(format!("evmobserver-{}-{}.ipc", test, std::process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let seen = Seen::default();
+
+    {
+        let seen = seen.clone();
+        thread::spawn(move || {
+            for (n, conn) in listener.incoming().enumerate() {
+                seen.lock().unwrap().push(Vec::new());
+                let (seen, behaviour) = (seen.clone(), behave(n));
+                thread::spawn(move || serve(conn.unwrap(), n, behaviour, &seen));
+            }
+        });
+    }
+
+    (path.to_str().unwrap().to_string(), seen)
+}
+
+/// Answer requests on `conn` like geth with the chain head at block 7, or misbehave
+fn serve(mut conn: UnixStream, n: usize, behaviour: Behaviour, seen: &Seen) {
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let size = conn.read(&mut buf).unwrap_or(0);
+        if size == 0 {
+            return;
+        }
+        pending.extend_from_slice(&buf[..size]);
+
+        let request = match json::parse(&String::from_utf8_lossy(&pending)) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        pending.clear();
+        seen.lock().unwrap()[n].push(request["id"].clone());
+
+        let answer = chain_response(&request, 7).dump().into_bytes();
+        match behaviour {
+            Behaviour::Answer => conn.write_all(&answer).unwrap(),
+            Behaviour::Hangup => return,
+            Behaviour::Stall(delay) => {
+                let (first, rest) = answer.split_at(answer.len() / 2);
+                conn.write_all(first).unwrap();
+                thread::sleep(delay);
+                let _ = conn.write_all(rest);
+            }
+        }
+    }
+}
+
+fn connect(path: &str) -> GethRpc {
+    let mut rpc = GethRpc::new(path).unwrap();
+    rpc.set_reconnect(Reconnect {
+        max_attempts: 3,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(40),
+    });
+    rpc
+}
+
+#[test]
+fn dropped_connection_is_replayed() {
+    let (path, seen) = serve_ipc("dropped", |n| {
+        if n < 2 {
+            Behaviour::Hangup
+        } else {
+            Behaviour::Answer
+        }
+    });
+    let mut rpc = connect(&path);
+
+    assert_eq!(rpc.get_latest_block().unwrap(), 7);
+
+    // the same request went out on every connection
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 3);
+    for ids in seen.iter() {
+        assert_eq!(ids, &seen[0]);
+    }
+}
+
+#[test]
+fn gives_up_reconnecting() {
+    let (path, seen) = serve_ipc("gives-up", |_| Behaviour::Hangup);
+    let mut rpc = connect(&path);
+
+    match rpc.get_latest_block() {
+        Err(GethRpcError::Io(_)) => {}
+        other => panic!("expected a lost connection, got {:?}", other),
+    }
+    assert_eq!(seen.lock().unwrap().len(), 4);
+}
+
+#[test]
+fn late_response_is_not_taken_for_the_next() {
+    let (path, seen) = serve_ipc("late", |n| {
+        if n == 0 {
+            Behaviour::Stall(Duration::from_millis(600))
+        } else {
+            Behaviour::Answer
+        }
+    });
+    let mut rpc = connect(&path);
+    rpc.set_timeout(Duration::from_millis(200));
+
+    match rpc.get_latest_block() {
+        Err(GethRpcError::Timeout { .. }) => {}
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+
+    // let the rest of the first answer arrive, on a connection we're no longer reading
+    thread::sleep(Duration::from_millis(600));
+
+    assert_eq!(rpc.get_latest_block().unwrap(), 7);
+    assert_eq!(rpc.get_latest_block().unwrap(), 7);
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[0].len(), 1);
+    assert_eq!(seen[1].len(), 2);
+}
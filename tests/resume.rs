@@ -0,0 +1,203 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Interrupts `evmextract` and resumes it, checking the output is what an uninterrupted run
+//! writes, that files it didn't write are left alone and that a reorg while stopped is noticed
+//!
+
+extern crate evmobserver;
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::{block_hash, chain_response, reorganise, serve_http};
+use evmobserver::checkpoint::Checkpoint;
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+fn out_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("evmextract-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `evmextract` to completion with `args`, writing small files to `dir`
+fn extract(rpc_url: &str, dir: &Path, args: &[&str]) -> ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_evmextract"))
+        .args(["--rpc-url", rpc_url, "--rotate-rows", "3"])
+        .args(args)
+        .arg("--out-dir")
+        .arg(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+}
+
+fn contents(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect()
+}
+
+fn checkpoint(dir: &Path) -> Checkpoint {
+    Checkpoint::load(dir.join(Checkpoint::FILE_NAME)).unwrap()
+}
+
+/// What a run interrupted in the middle of block 12 leaves behind: part of its first row in
+/// the file being written and, had that block started a file, the file
+fn interrupt(dir: &Path) {
+    let checkpoint = checkpoint(dir);
+    assert_eq!(checkpoint.next_block, 12);
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.join(&checkpoint.file_name))
+        .unwrap();
+    file.write_all(b"1526726656,12,0,0xaa,0xbb,1000000000,1,")
+        .unwrap();
+
+    fs::write(dir.join("counts.12.csv"), "ts,block_num\n").unwrap();
+}
+
+#[test]
+fn resumed_output_matches_uninterrupted() {
+    let rpc_url = serve_http(|request| (200, chain_response(request, 30).dump()));
+
+    let whole = out_dir("resume-whole");
+    assert!(extract(
+        &rpc_url,
+        &whole,
+        &["--start-block", "1", "--end-block", "20"]
+    )
+    .success());
+
+    let resumed = out_dir("resume-resumed");
+    assert!(extract(
+        &rpc_url,
+        &resumed,
+        &["--start-block", "1", "--end-block", "11"]
+    )
+    .success());
+    interrupt(&resumed);
+    assert!(extract(&rpc_url, &resumed, &["--resume", "--end-block", "20"]).success());
+
+    let expected = contents(&whole);
+    assert!(expected.len() > 3, "files: {:?}", expected.keys());
+    assert_eq!(contents(&resumed), expected);
+
+    let _ = fs::remove_dir_all(&whole);
+    let _ = fs::remove_dir_all(&resumed);
+}
+
+#[test]
+fn resume_leaves_unknown_files_alone() {
+    let rpc_url = serve_http(|request| (200, chain_response(request, 30).dump()));
+
+    let dir = out_dir("resume-unknown");
+    assert!(extract(&rpc_url, &dir, &["--start-block", "1", "--end-block", "11"]).success());
+    interrupt(&dir);
+    fs::write(dir.join("counts.15.csv"), "not ours\n").unwrap();
+    let before = contents(&dir);
+
+    assert!(!extract(&rpc_url, &dir, &["--resume", "--end-block", "20"]).success());
+
+    assert_eq!(contents(&dir), before);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn resume_notices_reorg_while_stopped() {
+    let dir = out_dir("resume-reorg");
+    let rpc_url = serve_http(|request| (200, chain_response(request, 30).dump()));
+    assert!(extract(&rpc_url, &dir, &["--start-block", "1", "--end-block", "11"]).success());
+    assert_eq!(checkpoint(&dir).last_hash, Some(block_hash(11)));
+    let before = contents(&dir);
+
+    // block 11, the last written, is no longer canonical, and nothing before it is known
+    let rpc_url = serve_http(|request| {
+        let mut response = chain_response(request, 30);
+        reorganise(&mut response, 11);
+        (200, response.dump())
+    });
+    assert!(!extract(&rpc_url, &dir, &["--resume", "--end-block", "20"]).success());
+
+    assert_eq!(contents(&dir), before);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Does `request`, or any request of a batch, ask about `block_num`?
+fn asks_for(request: &JsonValue, block_num: u64) -> bool {
+    if request.is_array() {
+        return request.members().any(|r| asks_for(r, block_num));
+    }
+
+    request["params"][0] == format!("{:#x}", block_num).as_str()
+}
+
+#[test]
+fn resumes_from_checkpoint_before_last_file() {
+    let rpc_url = serve_http(|request| (200, chain_response(request, 30).dump()));
+    let whole = out_dir("resume-interval-whole");
+    assert!(extract(
+        &rpc_url,
+        &whole,
+        &["--start-block", "1", "--end-block", "20"]
+    )
+    .success());
+
+    // geth refusing block 15 ends the run; the last checkpoint was saved as block 13 was
+    // about to start counts.13.csv, and blocks 13 and 14 are written past it
+    let failing_url = serve_http(|request| {
+        if asks_for(request, 15) {
+            (500, "{}".to_string())
+        } else {
+            (200, chain_response(request, 30).dump())
+        }
+    });
+    let resumed = out_dir("resume-interval-resumed");
+    let args = [
+        "--start-block",
+        "1",
+        "--end-block",
+        "20",
+        "--checkpoint-blocks",
+        "1000",
+    ];
+    assert!(!extract(&failing_url, &resumed, &args).success());
+    assert_eq!(checkpoint(&resumed).next_block, 13);
+    assert_eq!(checkpoint(&resumed).file_name, "counts.9.csv");
+
+    assert!(extract(&rpc_url, &resumed, &["--resume", "--end-block", "20"]).success());
+
+    assert_eq!(contents(&resumed), contents(&whole));
+
+    let _ = fs::remove_dir_all(&whole);
+    let _ = fs::remove_dir_all(&resumed);
+}
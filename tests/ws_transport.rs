@@ -0,0 +1,111 @@
+// Copyright 2018 int08h, LLC all rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Talks JSON-RPC over WebSocket to a geth stand-in that pushes `newHeads` notifications
+//!
+
+extern crate evmobserver;
+#[macro_use]
+extern crate json;
+extern crate tungstenite;
+
+use evmobserver::gethrpc::GethRpc;
+use json::JsonValue;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+const SUBSCRIPTION: &str = "0xcd0c3e8af590364c09d0fa6a1210faf5";
+
+fn send(socket: &mut WebSocket<TcpStream>, msg: JsonValue) {
+    socket.write_message(Message::Text(msg.dump())).unwrap();
+}
+
+fn new_head(block_num: u64) -> JsonValue {
+    object! {
+        "jsonrpc" => "2.0",
+        "method" => "eth_subscription",
+        "params" => object! {
+            "subscription" => SUBSCRIPTION,
+            "result" => object! { "number" => format!("0x{:x}", block_num) }
+        }
+    }
+}
+
+/// Accept one WebSocket client and answer it like geth, with the chain head at block 7.
+/// A new head, block 8, is announced right after `eth_subscribe` is answered and block 9
+/// while an `eth_blockNumber` call is outstanding, after a response to some other request.
+fn serve_ws() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let conn = listener.incoming().next().unwrap().unwrap();
+        let mut socket = tungstenite::accept(conn).unwrap();
+
+        loop {
+            let request = match socket.read_message() {
+                Ok(Message::Text(text)) => json::parse(&text).unwrap(),
+                Ok(_) => continue,
+                Err(_) => return,
+            };
+            let id = request["id"].clone();
+
+            match request["method"].as_str().unwrap() {
+                "eth_subscribe" => {
+                    assert_eq!(request["params"][0], "newHeads");
+                    send(
+                        &mut socket,
+                        object! { "jsonrpc" => "2.0", "id" => id, "result" => SUBSCRIPTION },
+                    );
+                    send(&mut socket, new_head(8));
+                }
+                "eth_blockNumber" => {
+                    send(
+                        &mut socket,
+                        object! { "jsonrpc" => "2.0", "id" => 999, "result" => "0x0" },
+                    );
+                    send(&mut socket, new_head(9));
+                    send(
+                        &mut socket,
+                        object! { "jsonrpc" => "2.0", "id" => id, "result" => "0x7" },
+                    );
+                }
+                method => panic!("unexpected {}", method),
+            }
+        }
+    });
+
+    url
+}
+
+#[test]
+fn request_and_new_heads() {
+    let mut rpc = GethRpc::new(&serve_ws()).unwrap();
+    let wait = Duration::from_secs(5);
+
+    assert_eq!(
+        rpc.subscribe_new_heads().unwrap(),
+        Some(SUBSCRIPTION.to_string())
+    );
+    assert_eq!(rpc.next_new_head(wait).unwrap(), Some(8));
+
+    // the stray response is dropped and the notification kept for later
+    assert_eq!(rpc.get_latest_block().unwrap(), 7);
+    assert_eq!(rpc.next_new_head(wait).unwrap(), Some(9));
+
+    assert_eq!(rpc.next_new_head(Duration::from_millis(200)).unwrap(), None);
+}